@@ -2,7 +2,6 @@ use std::{
     cmp::{max, min},
     collections::HashMap,
     net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
-    process,
 };
 use url::form_urlencoded::parse;
 
@@ -19,15 +18,34 @@ use hyper::{
 use regex::Regex;
 
 //use crate::fetch::FetchCommand;
+use crate::dataset::{self, DatasetGraph};
+use crate::entry;
 use crate::fetch;
 use crate::opt::OPT;
+use crate::pagerank::{self, RankError, RankOptions};
+use crate::path::{self, Mode, PathOptions};
+use crate::policy::{self, LinkPolicy};
+use crate::title;
+use crate::wiki::{self, Wiki};
+use crate::worker::TxCommands;
 
 static DEAFULT_API_PORT: u16 = 6457;
 static DEFAULT_MANAGEMENT_PORT: u16 = 6458;
 
+// Pages fetched for one path search. Pages are fetched at the rate limit of the wiki
+static PATH_MAX_FETCHES: usize = 100;
+// Paths given by ?mode=k_shortest without ?k=
+static DEFAULT_K: usize = 3;
+// Most pages given by /pagerank
+static PAGERANK_MAX_TOP: usize = 1000;
+
 lazy_static! {
     static ref DEFAULT_API_SOCKET: SocketAddr =
         std::net::SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, DEAFULT_API_PORT));
+    static ref DEFAULT_MANAGEMENT_SOCKET: SocketAddr = std::net::SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::LOCALHOST,
+        DEFAULT_MANAGEMENT_PORT
+    ));
 }
 
 // static DEFAULT_SOCKET: SocketAddr =
@@ -35,7 +53,7 @@ lazy_static! {
 
 // ***********************************************************************************************
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 enum StartFrom {
     title(String),
     url(String),
@@ -76,38 +94,104 @@ struct Worker {
  * entries for which it received a <Fetch> response. Any responses that still return <Fetch> will be returned as a
  * Fetch warning to the client
  *
- * API has one endpoint
- *    /title: look for a page with the title in wikipedia. Title must be appropriately encoded to avoid white space or
- *            other illegal characters
+ * API has two endpoints
+ *    /connections: look for a page in wikipedia, given by either
+ *        ?title= the title of the page. Title must be appropriately encoded to avoid white space or other illegal
+ *                characters
+ *        ?url=   the URL of the page, on any of the wikis served. A URL on any other host is rejected with 400 Bad
+ *                Request
+ *    on the wiki given by ?wiki=, or the default wiki. An unknown wiki is rejected with 400 Bad Request
+ *
+ *    /path: the cheapest path of links between two pages, as JSON
+ *        ?from=            title of the first page, on the wiki given by ?wiki=, or the default wiki
+ *        ?to=              title of the last page, on the wiki given by ?to_wiki=, or the wiki of the first page
+ *        ?langlinks=true   follow interlanguage links between the wikis served
+ *        ?langlink_weight= cost of an interlanguage link, where a link on the same wiki costs 1. Defaults to 2
+ *        ?max_hops=        longest path, 1-6. Defaults to 6
+ *        ?traverse=        namespaces whose links are followed, such as category,portal. Defaults to the namespaces
+ *                          marked traverse by opt::link_namespace. Namespaces whose links are not held are rejected
+ *        ?include_categories=  categories, separated by |, one of which must hold each page between the first and
+ *                              the last, on the wiki of the first page. The Category: prefix may be left out
+ *        ?exclude_categories=  categories, separated by |, none of which may hold a page between the first and the last
+ *        ?category_depth=      levels of subcategories included in each category tree, 0-6. Defaults to 2
+ *        ?context=true     give each link the sentence around it on the page it is from
+ *        ?as_of=           name of a dataset served, searched in place of the live graph. Pages are not fetched, and
+ *                          ?context= is ignored
+ *        ?mode=            shortest, the cheapest path; all_shortest, every path as cheap as the cheapest, up to 50; or
+ *                          k_shortest, the ?k= cheapest loopless paths. Defaults to shortest. Other modes also give
+ *                          every path found as paths
+ *        ?k=               paths given by k_shortest, 1-50. Defaults to 3
+ *        ?reciprocal=true  follow only links from pages that link back. Links into other namespaces are not followed
+ *        ?avoid=           titles, separated by |, of pages that may not be between the first and the last
+ *        ?avoid_pattern=   regular expression, such as ^List of|^\d{4}$, matched against the title of each page between
+ *                          the first and the last. Pages whose titles are matched are not passed through
+ *        ?via=             titles, separated by |, of pages that the path passes through, in order. Only used with
+ *                          ?mode=shortest
+ *    The titles of ?avoid= and ?via= are on the wiki of the first page
+ *        ?rank=true        score each path found by the strength of its links, and sort the paths by score. Each path
+ *                          and each hop is given its score, with the reciprocity, position, hub penalty and PageRank
+ *                          scored. PageRank is scored once it has been computed by /pagerank
+ *    Each hop gives the edge followed to reach it, and is flagged if it was over an interlanguage link
+ *
+ *    /diff: compare two datasets served, as JSON
+ *        ?from=            name of the earlier dataset
+ *        ?to=              name of the later dataset
+ *        ?pages=           titles, separated by |, of pages whose links gained and lost are listed
+ *        ?pairs=           pairs of titles, separated by |, each as from>to, such as Train>Tram, whose shortest paths
+ *                          are compared
+ *        ?limit=           most pages listed as added, and as removed, 0-10000. Defaults to 100
+ *    Datasets that are not served, or that hold different wikis, are rejected with 400 Bad Request
+ *
+ *    /pagerank: compute the PageRank of the pages held by the workers, as JSON, with the best ranked pages
+ *        ?seed=            title of a page, on the wiki given by ?wiki=, or the default wiki, to which the ranks are
+ *                          personalised. Without a seed, the rank of each page is stored, and scored by ?rank=true
+ *        ?top=             pages given, best ranked first, 1-1000. Defaults to 20
+ *        ?iterations=      most rounds run, 1-200. Defaults to 50
+ *        ?damping=         chance of following a link rather than returning to the seed, or jumping to any page,
+ *                          above 0 and below 1. Defaults to 0.85
+ *    While a computation is running, another is rejected with 503 Service Unavailable
  *
  *******************************************************************************************************************/
 
-// pub async fn new(tx_to_fetch: Sender<FetchCommand>) -> JoinHandle<()> {
-pub async fn new(tx_to_fetch: Sender<fetch::FetchCommand>) {
+pub fn new(
+    tx_to_fetch: Sender<fetch::FetchCommand>,
+    tx_to_workers: TxCommands,
+) -> Result<JoinHandle<()>, hyper::Error> {
     trace!("api::new");
-    //   let api_service = tokio::spawn(async move { start_api_service() }).await;
-    start_api_service();
-    trace!("api: REST server started");
-    // let api_service = tokio::spawn(async move { api_service(tx_to_fetch).await });
-
-    //    api_service
-}
-
-fn start_api_service() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    trace!("api::start_api_service");
     let addr = get_api_address();
-    info!("Addr: {:?}", addr);
-
-    // let service = make_service_fn(|_| async { Ok::<_, hyper::Error>(service_fn(api_service)) });
-    // let server = Server::bind(&addr).serve(service);
-    // info!("Listening on http://{}", addr);
-    // server.await?;
-    // info!("API Shutting down");
+    let service = make_service_fn(move |_| {
+        let tx_to_fetch = tx_to_fetch.clone();
+        let tx_to_workers = tx_to_workers.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                api_service(req, tx_to_fetch.clone(), tx_to_workers.clone())
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(service);
+    info!("API listening on http://{}", addr);
 
-    Ok(())
+    Ok(tokio::spawn(async move {
+        if let Err(err) = server.await {
+            error!("API stopped: {}", err);
+        }
+    }))
 }
 
-pub async fn api_service(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+pub async fn api_service(
+    req: Request<Body>,
+    tx_to_fetch: Sender<fetch::FetchCommand>,
+    tx_to_workers: TxCommands,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.method() == Method::GET && req.uri().path().eq_ignore_ascii_case("/path") {
+        return path_service(req, tx_to_fetch, tx_to_workers).await;
+    }
+    if req.method() == Method::GET && req.uri().path().eq_ignore_ascii_case("/diff") {
+        return diff_service(req).await;
+    }
+    if req.method() == Method::GET && req.uri().path().eq_ignore_ascii_case("/pagerank") {
+        return pagerank_service(req, tx_to_workers).await;
+    }
     if req.method() == &Method::GET {
         println!("method::GET");
         let path = req.uri().path();
@@ -158,6 +242,16 @@ pub async fn api_service(req: Request<Body>) -> Result<Response<Body>, hyper::Er
         } else {
             root = None
         }
+        let wiki = params.get("wiki").map(|wiki| wiki.as_str());
+        let root = match resolve_root(root, wiki, wiki::all()) {
+            Ok(root) => root,
+            Err(err) => {
+                let mut bad_request = Response::default();
+                *bad_request.status_mut() = StatusCode::BAD_REQUEST;
+                *bad_request.body_mut() = Body::from(err.to_string());
+                return Ok(bad_request);
+            }
+        };
 
         let body = format!("Depth: {}\nRoot:   {:?}", depth, &root);
         let body = Body::from(body);
@@ -167,6 +261,374 @@ pub async fn api_service(req: Request<Body>) -> Result<Response<Body>, hyper::Er
     *not_found.status_mut() = StatusCode::NOT_FOUND;
     return Ok(not_found);
 }
+// Resolve the root into (wiki, normalised title). The wiki is named by ?wiki=, or by the host of the URL, or is the
+// default wiki. A URL must be on the wiki named by ?wiki=, if it is given
+fn resolve_root<'a>(
+    root: Option<StartFrom>,
+    wiki: Option<&str>,
+    wikis: &'a [Wiki],
+) -> Result<Option<(&'a Wiki, StartFrom)>, Box<dyn std::error::Error>> {
+    let wiki = match wiki {
+        Some(wiki) => Some(wiki::find(wikis, wiki)?),
+        None => None,
+    };
+    match root {
+        Some(StartFrom::title(page_title)) => {
+            let wiki = wiki.unwrap_or(&wikis[0]);
+            let page_title = title::normalise_for(wiki, &page_title);
+            Ok(Some((wiki, StartFrom::title(page_title))))
+        }
+        Some(StartFrom::url(page_url)) => {
+            let wikis = match wiki {
+                Some(wiki) => std::slice::from_ref(wiki),
+                None => wikis,
+            };
+            let (wiki, page_title) = title::from_url(&page_url, wikis)?;
+            Ok(Some((wiki, StartFrom::title(page_title))))
+        }
+        None => Ok(None),
+    }
+}
+
+async fn path_service(
+    req: Request<Body>,
+    tx_to_fetch: Sender<fetch::FetchCommand>,
+    tx_to_workers: TxCommands,
+) -> Result<Response<Body>, hyper::Error> {
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| parse(v.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let (from, to, mut options) = match read_path_request(&params, wiki::all(), policy::get()) {
+        Ok(request) => request,
+        Err(err) => return Ok(bad_request(err.to_string())),
+    };
+    options.fake_hub = OPT.get_fake_hub();
+
+    if let Some(name) = &options.as_of {
+        let dataset = match dataset::get(name) {
+            Ok(dataset) => dataset,
+            Err(err) => return Ok(bad_request(err.to_string())),
+        };
+        if !dataset.info.wiki.eq_ignore_ascii_case(&from.0.id) {
+            return Ok(bad_request(format!(
+                "Dataset {} holds wiki {}",
+                name, dataset.info.wiki
+            )));
+        }
+        let mut result = path::search(&mut DatasetGraph(dataset), &from.1, &to, &options).await;
+        result.dataset = Some(name.clone());
+        return Ok(json_response(&result));
+    }
+
+    let graph_tx_to_fetch = tx_to_fetch.clone();
+    let mut graph = path::WorkerGraph::new(tx_to_workers, tx_to_fetch, PATH_MAX_FETCHES);
+    graph.add_title(from.0, &from.1.title);
+    let mut result = path::search(&mut graph, &from.1, &to, &options).await;
+    if options.context {
+        for path in result.path.iter_mut().chain(result.paths.iter_mut()) {
+            path::add_context(path, &graph_tx_to_fetch).await;
+        }
+    }
+    Ok(json_response(&result))
+}
+
+async fn diff_service(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| parse(v.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let (from, to, request) = match read_diff_request(&params) {
+        Ok(request) => request,
+        Err(err) => return Ok(bad_request(err.to_string())),
+    };
+    let datasets = dataset::get(&from).and_then(|from| Ok((from, dataset::get(&to)?)));
+    let (from, to) = match datasets {
+        Ok(datasets) => datasets,
+        Err(err) => return Ok(bad_request(err.to_string())),
+    };
+    let wiki = match wiki::find(wiki::all(), &from.info.wiki) {
+        Ok(wiki) => wiki,
+        Err(err) => return Ok(bad_request(err.to_string())),
+    };
+    match dataset::diff(from, to, wiki, &request).await {
+        Ok(diff) => Ok(json_response(&diff)),
+        Err(err) => Ok(bad_request(err.to_string())),
+    }
+}
+
+async fn pagerank_service(
+    req: Request<Body>,
+    tx_to_workers: TxCommands,
+) -> Result<Response<Body>, hyper::Error> {
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| parse(v.as_bytes()).into_owned().collect())
+        .unwrap_or_default();
+    let options = match read_pagerank_request(&params, wiki::all()) {
+        Ok(options) => options,
+        Err(err) => return Ok(bad_request(err.to_string())),
+    };
+    match pagerank::compute(&tx_to_workers, &options).await {
+        Ok(ranking) => Ok(json_response(&ranking)),
+        Err(err @ (RankError::Running | RankError::Stopped)) => {
+            let mut unavailable = Response::default();
+            *unavailable.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            *unavailable.body_mut() = Body::from(err.to_string());
+            Ok(unavailable)
+        }
+        Err(err) => Ok(bad_request(err.to_string())),
+    }
+}
+
+fn json_response<T: serde::Serialize>(result: &T) -> Response<Body> {
+    Response::builder()
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_string(result).unwrap()))
+        .unwrap()
+}
+
+fn bad_request(message: String) -> Response<Body> {
+    let mut bad_request = Response::default();
+    *bad_request.status_mut() = StatusCode::BAD_REQUEST;
+    *bad_request.body_mut() = Body::from(message);
+    bad_request
+}
+
+// The first page with its wiki, the last page, and the options of a path search
+type PathRequest<'a> = ((&'a Wiki, path::Page), path::Page, PathOptions);
+
+// Read the pages and options of a path search
+fn read_path_request<'a>(
+    params: &HashMap<String, String>,
+    wikis: &'a [Wiki],
+    link_policy: &LinkPolicy,
+) -> Result<PathRequest<'a>, Box<dyn std::error::Error>> {
+    let page = |title_param: &str, wiki_param: &str, default: &'a Wiki| {
+        let wiki = match params.get(wiki_param) {
+            Some(wiki) => wiki::find(wikis, wiki)?,
+            None => default,
+        };
+        let page_title = params
+            .get(title_param)
+            .map(|page_title| title::normalise_for(wiki, page_title))
+            .filter(|page_title| !page_title.is_empty())
+            .ok_or_else(|| format!("?{}= is required", title_param))?;
+        Ok::<_, Box<dyn std::error::Error>>((wiki, path::Page::new(wiki, &page_title)))
+    };
+    let (from_wiki, from) = page("from", "wiki", &wikis[0])?;
+    let (_, to) = page("to", "to_wiki", from_wiki)?;
+
+    let mut options = PathOptions {
+        namespaces: link_policy.traversed(),
+        ..Default::default()
+    };
+    if let Some(traverse) = params.get("traverse") {
+        options.namespaces = link_policy.read_traverse(traverse)?;
+    }
+    if let Some(langlinks) = params.get("langlinks") {
+        options.langlinks = langlinks
+            .parse()
+            .map_err(|_| format!("?langlinks= must be true or false, not {}", langlinks))?;
+    }
+    if let Some(weight) = params.get("langlink_weight") {
+        options.langlink_weight = weight
+            .parse::<f64>()
+            .ok()
+            .filter(|weight| weight.is_finite() && *weight >= 0.0)
+            .ok_or_else(|| {
+                format!(
+                    "?langlink_weight= must be a number of 0 or more, not {}",
+                    weight
+                )
+            })?;
+    }
+    if let Some(categories) = params.get("include_categories") {
+        options.include_categories = read_categories(from_wiki, categories);
+    }
+    if let Some(categories) = params.get("exclude_categories") {
+        options.exclude_categories = read_categories(from_wiki, categories);
+    }
+    if let Some(depth) = params.get("category_depth") {
+        options.category_depth = depth
+            .parse::<usize>()
+            .ok()
+            .filter(|depth| *depth <= 6)
+            .ok_or_else(|| format!("?category_depth= must be 0-6, not {}", depth))?;
+    }
+    if let Some(context) = params.get("context") {
+        options.context = context
+            .parse()
+            .map_err(|_| format!("?context= must be true or false, not {}", context))?;
+    }
+    if let Some(as_of) = params.get("as_of") {
+        options.as_of = Some(as_of.trim().to_string()).filter(|as_of| !as_of.is_empty());
+        if options.as_of.is_none() {
+            return Err("?as_of= must name a dataset".into());
+        }
+    }
+    if let Some(mode) = params.get("mode") {
+        options.mode = match mode.trim().to_ascii_lowercase().as_str() {
+            "shortest" => Mode::Shortest,
+            "all_shortest" => Mode::AllShortest,
+            "k_shortest" => Mode::KShortest(DEFAULT_K),
+            _ => {
+                return Err(format!(
+                    "?mode= must be shortest, all_shortest or k_shortest, not {}",
+                    mode
+                )
+                .into())
+            }
+        };
+    }
+    if let Some(k) = params.get("k") {
+        let k = k
+            .parse::<usize>()
+            .ok()
+            .filter(|k| (1..=path::MAX_PATHS).contains(k))
+            .ok_or_else(|| format!("?k= must be 1-{}, not {}", path::MAX_PATHS, k))?;
+        match options.mode {
+            Mode::KShortest(_) => options.mode = Mode::KShortest(k),
+            _ => return Err("?k= is only used with ?mode=k_shortest".into()),
+        }
+    }
+    if let Some(avoid) = params.get("avoid") {
+        options.avoid = read_titles(avoid)
+            .map(|avoid| entry::Entry::get_digest_for(from_wiki, avoid))
+            .collect();
+    }
+    if let Some(pattern) = params.get("avoid_pattern") {
+        let pattern = Regex::new(pattern)
+            .map_err(|err| format!("?avoid_pattern= is not a regular expression: {}", err))?;
+        options.avoid_pattern = Some(path::TitlePattern(pattern));
+    }
+    if let Some(via) = params.get("via") {
+        options.via = read_titles(via)
+            .map(|via| path::Page::new(from_wiki, &title::normalise_for(from_wiki, via)))
+            .collect();
+        if !options.via.is_empty() && options.mode != Mode::Shortest {
+            return Err("?via= is only used with ?mode=shortest".into());
+        }
+    }
+    if let Some(reciprocal) = params.get("reciprocal") {
+        options.reciprocal = reciprocal
+            .parse()
+            .map_err(|_| format!("?reciprocal= must be true or false, not {}", reciprocal))?;
+    }
+    if let Some(rank) = params.get("rank") {
+        options.rank = rank
+            .parse()
+            .map_err(|_| format!("?rank= must be true or false, not {}", rank))?;
+    }
+    if let Some(max_hops) = params.get("max_hops") {
+        options.max_hops = max_hops
+            .parse::<usize>()
+            .ok()
+            .filter(|max_hops| (1..=6).contains(max_hops))
+            .ok_or_else(|| format!("?max_hops= must be 1-6, not {}", max_hops))?;
+    }
+    Ok(((from_wiki, from), to, options))
+}
+
+// Read the seed and options of a PageRank computation
+fn read_pagerank_request(
+    params: &HashMap<String, String>,
+    wikis: &[Wiki],
+) -> Result<RankOptions, Box<dyn std::error::Error>> {
+    let wiki = match params.get("wiki") {
+        Some(wiki) => wiki::find(wikis, wiki)?,
+        None => &wikis[0],
+    };
+    let mut options = RankOptions::default();
+    if let Some(seed) = params.get("seed") {
+        let seed = title::normalise_for(wiki, seed);
+        if seed.is_empty() {
+            return Err("?seed= must be the title of a page".into());
+        }
+        options.seed = Some(entry::Entry::get_digest_for(wiki, &seed));
+    }
+    if let Some(top) = params.get("top") {
+        options.top = top
+            .parse::<usize>()
+            .ok()
+            .filter(|top| (1..=PAGERANK_MAX_TOP).contains(top))
+            .ok_or_else(|| format!("?top= must be 1-{}, not {}", PAGERANK_MAX_TOP, top))?;
+    }
+    if let Some(iterations) = params.get("iterations") {
+        options.iterations = iterations
+            .parse::<usize>()
+            .ok()
+            .filter(|iterations| (1..=200).contains(iterations))
+            .ok_or_else(|| format!("?iterations= must be 1-200, not {}", iterations))?;
+    }
+    if let Some(damping) = params.get("damping") {
+        options.damping = damping
+            .parse::<f64>()
+            .ok()
+            .filter(|damping| *damping > 0.0 && *damping < 1.0)
+            .ok_or_else(|| format!("?damping= must be above 0 and below 1, not {}", damping))?;
+    }
+    Ok(options)
+}
+
+// Read the datasets, pages and pairs of pages of a diff
+fn read_diff_request(
+    params: &HashMap<String, String>,
+) -> Result<(String, String, dataset::DiffRequest), Box<dyn std::error::Error>> {
+    let name = |param: &str| {
+        params
+            .get(param)
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| format!("?{}= is required", param))
+    };
+    let (from, to) = (name("from")?, name("to")?);
+    let list = |param: &str| -> Vec<&str> {
+        params
+            .get(param)
+            .map(|list| {
+                list.split('|')
+                    .filter(|item| !item.trim().is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut request = dataset::DiffRequest {
+        pages: list("pages").iter().map(|page| page.to_string()).collect(),
+        limit: 100,
+        ..Default::default()
+    };
+    for pair in list("pairs") {
+        request.pairs.push(dataset::read_pair(pair).ok_or_else(|| {
+            format!("?pairs= must hold pairs of titles as from>to, not {}", pair)
+        })?);
+    }
+    if let Some(limit) = params.get("limit") {
+        request.limit = limit
+            .parse::<usize>()
+            .ok()
+            .filter(|limit| *limit <= 10000)
+            .ok_or_else(|| format!("?limit= must be 0-10000, not {}", limit))?;
+    }
+    Ok((from, to, request))
+}
+
+// The titles in a list separated by |
+fn read_titles(titles: &str) -> impl Iterator<Item = &str> {
+    titles.split('|').filter(|title| !title.trim().is_empty())
+}
+
+// Digests of the categories in a list separated by |
+fn read_categories(wiki: &Wiki, categories: &str) -> Vec<entry::Digest> {
+    read_titles(categories)
+        .map(|category| entry::Entry::get_digest_for(wiki, &title::category_for(wiki, category)))
+        .collect()
+}
+
 // listen for message on tx_to_api
 // spawn a new task "assembler" to process the request
 //    identify target worker
@@ -190,15 +652,16 @@ fn get_api_address() -> SocketAddr {
     //
     // let address = get_address(&add);
 
-    let socket = match OPT.get_api() {
-        Some(api_target) => get_address(&api_target),
+    match OPT.get_api() {
+        Some(address) => parse_socket_address(address, DEAFULT_API_PORT).unwrap_or_else(|| {
+            warn!(
+                "Unable to parse API address {}. Using {}",
+                address, *DEFAULT_API_SOCKET
+            );
+            *DEFAULT_API_SOCKET
+        }),
         None => *DEFAULT_API_SOCKET,
-    };
-
-    println!("Socket Address: {:?}", socket);
-    std::process::exit(1);
-
-    socket
+    }
 }
 
 fn get_address(addr: &str) -> SocketAddr {
@@ -248,6 +711,99 @@ fn try_v4_address(address_from_command_line: &str) -> Option<SocketAddr> {
     Some(*DEFAULT_API_SOCKET)
 }
 
+/* *****************************************************************************************************************
+ *
+ * Start the management task
+ *
+ * The management API is published on opt::management, and has one endpoint
+ *    /cache: the size, page count, pinned page count and hit ratio of the page cache, as JSON
+ *
+ *******************************************************************************************************************/
+
+pub fn new_management(
+    tx_to_fetch: Sender<fetch::FetchCommand>,
+) -> Result<JoinHandle<()>, hyper::Error> {
+    let addr = get_management_address();
+    let service = make_service_fn(move |_| {
+        let tx_to_fetch = tx_to_fetch.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                management_service(req, tx_to_fetch.clone())
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(service);
+    info!("Management API listening on http://{}", addr);
+
+    Ok(tokio::spawn(async move {
+        if let Err(err) = server.await {
+            error!("Management API stopped: {}", err);
+        }
+    }))
+}
+
+async fn management_service(
+    req: Request<Body>,
+    tx_to_fetch: Sender<fetch::FetchCommand>,
+) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::GET || !req.uri().path().eq_ignore_ascii_case("/cache") {
+        let mut not_found = Response::default();
+        *not_found.status_mut() = StatusCode::NOT_FOUND;
+        *not_found.body_mut() = Body::from(format!("Nothing found at {}", req.uri().path()));
+        return Ok(not_found);
+    }
+
+    let (tx, mut rx) = mpsc::channel(1);
+    let stats = match tx_to_fetch.send(fetch::FetchCommand::Stats { tx }).await {
+        Ok(_) => rx.recv().await,
+        Err(_) => None,
+    };
+    match stats {
+        Some(stats) => Ok(Response::builder()
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_string(&stats).unwrap()))
+            .unwrap()),
+        None => {
+            let mut unavailable = Response::default();
+            *unavailable.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            Ok(unavailable)
+        }
+    }
+}
+
+// Accepts address:port, address, or :port. IPv6 addresses with a port must be surrounded in square brackets
+fn get_management_address() -> SocketAddr {
+    match OPT.get_management() {
+        Some(address) => {
+            parse_socket_address(address, DEFAULT_MANAGEMENT_PORT).unwrap_or_else(|| {
+                warn!(
+                    "Unable to parse management address {}. Using {}",
+                    address, *DEFAULT_MANAGEMENT_SOCKET
+                );
+                *DEFAULT_MANAGEMENT_SOCKET
+            })
+        }
+        None => *DEFAULT_MANAGEMENT_SOCKET,
+    }
+}
+
+fn parse_socket_address(address: &str, default_port: u16) -> Option<SocketAddr> {
+    if let Some(port) = address.strip_prefix(':') {
+        return Some(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port.parse().ok()?,
+        ));
+    }
+    if let Ok(socket) = address.parse::<SocketAddr>() {
+        return Some(socket);
+    }
+    let address = address.trim_start_matches('[').trim_end_matches(']');
+    address
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, default_port))
+}
+
 /* *****************************************************************************************************************
  *
  * Tests
@@ -296,6 +852,421 @@ mod tests {
         assert_eq!(get_address("192.168.1.2:67034"), address);
     }
 
+    #[test]
+    fn test_parse_socket_address() {
+        assert_eq!(
+            parse_socket_address("192.168.1.2:3303", 6458),
+            Some("192.168.1.2:3303".parse().unwrap())
+        );
+        assert_eq!(
+            parse_socket_address("192.168.1.2", 6458),
+            Some("192.168.1.2:6458".parse().unwrap())
+        );
+        assert_eq!(
+            parse_socket_address(":3303", 6458),
+            Some("127.0.0.1:3303".parse().unwrap())
+        );
+        assert_eq!(
+            parse_socket_address("[::1]:3303", 6458),
+            Some("[::1]:3303".parse().unwrap())
+        );
+        assert_eq!(
+            parse_socket_address("[::1]", 6458),
+            Some("[::1]:6458".parse().unwrap())
+        );
+        assert_eq!(parse_socket_address("192.168.1.2:67034", 6458), None);
+        assert_eq!(parse_socket_address("266.168.1.2", 6458), None);
+    }
+
+    #[test]
+    fn test_resolve_root() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("de", "https://de.wikipedia.org/", 10),
+        ];
+        let resolve = |root, wiki| {
+            resolve_root(root, wiki, &wikis)
+                .map(|root| root.map(|(wiki, start)| (wiki.id.clone(), start)))
+                .map_err(|err| err.to_string())
+        };
+
+        assert_eq!(
+            resolve(
+                Some(StartFrom::url(
+                    "https://en.m.wikipedia.org/wiki/Rail_transport#History".to_string()
+                )),
+                None
+            ),
+            Ok(Some((
+                "en".to_string(),
+                StartFrom::title("Rail transport".to_string())
+            )))
+        );
+        assert_eq!(
+            resolve(Some(StartFrom::title("rail_transport".to_string())), None),
+            Ok(Some((
+                "en".to_string(),
+                StartFrom::title("Rail transport".to_string())
+            )))
+        );
+        assert_eq!(
+            resolve(Some(StartFrom::title("eisenbahn".to_string())), Some("de")),
+            Ok(Some((
+                "de".to_string(),
+                StartFrom::title("Eisenbahn".to_string())
+            )))
+        );
+        assert_eq!(
+            resolve(
+                Some(StartFrom::url(
+                    "https://de.wikipedia.org/wiki/Eisenbahn".to_string()
+                )),
+                None
+            ),
+            Ok(Some((
+                "de".to_string(),
+                StartFrom::title("Eisenbahn".to_string())
+            )))
+        );
+        assert_eq!(resolve(None, None), Ok(None));
+    }
+
+    #[test]
+    fn test_read_path_request() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("ja", "https://ja.wikipedia.org/", 10),
+        ];
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+
+        let ((from_wiki, from), to, options) = read_path_request(
+            &params(&[("from", "train"), ("to", "Rail_transport")]),
+            &wikis,
+            &LinkPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(from_wiki.id, "en");
+        assert_eq!(from, path::Page::new(&wikis[0], "Train"));
+        assert_eq!(to, path::Page::new(&wikis[0], "Rail transport"));
+        assert_eq!(options, PathOptions::default());
+
+        let (_, to, options) = read_path_request(
+            &params(&[
+                ("from", "Train"),
+                ("to", "列車"),
+                ("to_wiki", "ja"),
+                ("langlinks", "true"),
+                ("langlink_weight", "0.5"),
+                ("max_hops", "3"),
+                ("context", "true"),
+                ("as_of", "en-2015"),
+                ("rank", "true"),
+                ("reciprocal", "true"),
+            ]),
+            &wikis,
+            &LinkPolicy::default(),
+        )
+        .unwrap();
+        assert_eq!(to, path::Page::new(&wikis[1], "列車"));
+        assert_eq!(
+            options,
+            PathOptions {
+                max_hops: 3,
+                langlinks: true,
+                langlink_weight: 0.5,
+                context: true,
+                as_of: Some("en-2015".to_string()),
+                rank: true,
+                reciprocal: true,
+                ..Default::default()
+            }
+        );
+
+        let link_policy =
+            LinkPolicy::from_options(&["category=traverse".to_string(), "portal".to_string()])
+                .unwrap();
+        let request = params(&[("from", "Train"), ("to", "Tram")]);
+        let (_, _, options) = read_path_request(&request, &wikis, &link_policy).unwrap();
+        assert_eq!(options.namespaces, vec![14]);
+        let request = params(&[("from", "Train"), ("to", "Tram"), ("traverse", "portal")]);
+        let (_, _, options) = read_path_request(&request, &wikis, &link_policy).unwrap();
+        assert_eq!(options.namespaces, vec![100]);
+        let request = params(&[("from", "Train"), ("to", "Tram"), ("traverse", "")]);
+        let (_, _, options) = read_path_request(&request, &wikis, &link_policy).unwrap();
+        assert!(options.namespaces.is_empty());
+        let request = params(&[("from", "Train"), ("to", "Tram"), ("traverse", "template")]);
+        assert!(read_path_request(&request, &wikis, &link_policy).is_err());
+
+        let mode = |pairs: &[(&str, &str)]| {
+            let request = params(&[&[("from", "Train"), ("to", "Tram")], pairs].concat());
+            read_path_request(&request, &wikis, &link_policy)
+                .unwrap()
+                .2
+                .mode
+        };
+        assert_eq!(mode(&[]), Mode::Shortest);
+        assert_eq!(mode(&[("mode", "All_Shortest")]), Mode::AllShortest);
+        assert_eq!(mode(&[("mode", "k_shortest")]), Mode::KShortest(3));
+        assert_eq!(
+            mode(&[("mode", "k_shortest"), ("k", "5")]),
+            Mode::KShortest(5)
+        );
+
+        let request = params(&[
+            ("from", "Train"),
+            ("to", "Tram"),
+            ("avoid", "United States||canada"),
+            ("avoid_pattern", r"^List of|^\d{4}$"),
+            ("via", "light_rail"),
+        ]);
+        let (_, _, options) = read_path_request(&request, &wikis, &link_policy).unwrap();
+        assert_eq!(
+            options.avoid,
+            vec![
+                entry::Entry::get_digest_for(&wikis[0], "United States"),
+                entry::Entry::get_digest_for(&wikis[0], "Canada")
+            ]
+        );
+        let pattern = options.avoid_pattern.unwrap();
+        assert!(pattern.0.is_match("List of tram systems"));
+        assert!(pattern.0.is_match("1825"));
+        assert!(!pattern.0.is_match("Tram"));
+        assert_eq!(options.via, vec![path::Page::new(&wikis[0], "Light rail")]);
+
+        let request = params(&[
+            ("from", "Train"),
+            ("to", "Tram"),
+            ("include_categories", "Rail_transport|Category:Trains"),
+            ("exclude_categories", "category:Animals"),
+            ("category_depth", "0"),
+        ]);
+        let (_, _, options) = read_path_request(&request, &wikis, &link_policy).unwrap();
+        let category = |title| entry::Entry::get_digest_for(&wikis[0], title);
+        assert_eq!(
+            options.include_categories,
+            vec![
+                category("Category:Rail transport"),
+                category("Category:Trains")
+            ]
+        );
+        assert_eq!(
+            options.exclude_categories,
+            vec![category("Category:Animals")]
+        );
+        assert_eq!(options.category_depth, 0);
+
+        // Categories on de are named with the local prefix, Kategorie
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+        assert_eq!(
+            read_categories(&de, "Schienenverkehr|Category:Züge|kategorie:Straßenbahn"),
+            vec![
+                entry::Entry::get_digest_for(&de, "Kategorie:Schienenverkehr"),
+                entry::Entry::get_digest_for(&de, "Kategorie:Züge"),
+                entry::Entry::get_digest_for(&de, "Kategorie:Straßenbahn"),
+            ]
+        );
+        let request = params(&[("from", "Train"), ("to", "Tram"), ("category_depth", "7")]);
+        assert!(read_path_request(&request, &wikis, &link_policy).is_err());
+
+        for bad in [
+            params(&[("from", "Train")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("to_wiki", "fr")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("langlinks", "yes")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("langlink_weight", "-1")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("max_hops", "7")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("context", "1")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("as_of", " ")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("mode", "longest")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("rank", "yes")]),
+            params(&[("from", "Train"), ("to", "Tram"), ("reciprocal", "mutual")]),
+            params(&[
+                ("from", "Train"),
+                ("to", "Tram"),
+                ("avoid_pattern", "(List"),
+            ]),
+            params(&[
+                ("from", "Train"),
+                ("to", "Tram"),
+                ("mode", "all_shortest"),
+                ("via", "Bus"),
+            ]),
+            params(&[
+                ("from", "Train"),
+                ("to", "Tram"),
+                ("mode", "k_shortest"),
+                ("k", "0"),
+            ]),
+            params(&[
+                ("from", "Train"),
+                ("to", "Tram"),
+                ("mode", "k_shortest"),
+                ("k", "51"),
+            ]),
+            params(&[("from", "Train"), ("to", "Tram"), ("k", "2")]),
+        ] {
+            assert!(
+                read_path_request(&bad, &wikis, &LinkPolicy::default()).is_err(),
+                "{:?}",
+                bad
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_diff_request() {
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        let request = params(&[
+            ("from", "en-2010"),
+            ("to", " en-2015 "),
+            ("pages", "Train||Tram"),
+            ("pairs", "Train>Tram|Locomotive > Monorail"),
+            ("limit", "0"),
+        ]);
+        assert_eq!(
+            read_diff_request(&request).unwrap(),
+            (
+                "en-2010".to_string(),
+                "en-2015".to_string(),
+                dataset::DiffRequest {
+                    pages: vec!["Train".to_string(), "Tram".to_string()],
+                    pairs: vec![
+                        ("Train".to_string(), "Tram".to_string()),
+                        ("Locomotive".to_string(), "Monorail".to_string()),
+                    ],
+                    limit: 0,
+                }
+            )
+        );
+        let (_, _, request) =
+            read_diff_request(&params(&[("from", "en-2010"), ("to", "en-2015")])).unwrap();
+        assert_eq!(request.limit, 100);
+
+        for bad in [
+            params(&[("from", "en-2010")]),
+            params(&[("from", "en-2010"), ("to", "en-2015"), ("pairs", "Train")]),
+            params(&[("from", "en-2010"), ("to", "en-2015"), ("limit", "10001")]),
+        ] {
+            assert!(read_diff_request(&bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_read_pagerank_request() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("de", "https://de.wikipedia.org/", 10),
+        ];
+        let params = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        };
+        assert_eq!(
+            read_pagerank_request(&params(&[]), &wikis).unwrap(),
+            RankOptions::default()
+        );
+        let request = params(&[
+            ("seed", "zug"),
+            ("wiki", "de"),
+            ("top", "5"),
+            ("iterations", "10"),
+            ("damping", "0.5"),
+        ]);
+        assert_eq!(
+            read_pagerank_request(&request, &wikis).unwrap(),
+            RankOptions {
+                damping: 0.5,
+                iterations: 10,
+                seed: Some(entry::Entry::get_digest_for(&wikis[1], "Zug")),
+                top: 5,
+            }
+        );
+        for bad in [
+            params(&[("seed", " ")]),
+            params(&[("wiki", "fr")]),
+            params(&[("top", "0")]),
+            params(&[("top", "1001")]),
+            params(&[("iterations", "201")]),
+            params(&[("damping", "1")]),
+            params(&[("damping", "0")]),
+        ] {
+            assert!(read_pagerank_request(&bad, &wikis).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_resolve_root_fail() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("de", "https://de.wikipedia.org/", 10),
+        ];
+        let resolve = |root, wiki| resolve_root(root, wiki, &wikis).unwrap_err().to_string();
+
+        assert_eq!(
+            resolve(
+                Some(StartFrom::url("https://example.com/wiki/Train".to_string())),
+                None
+            ),
+            "example.com is not the host of a wiki served"
+        );
+        assert_eq!(
+            resolve(
+                Some(StartFrom::url(
+                    "https://de.wikipedia.org/wiki/Eisenbahn".to_string()
+                )),
+                Some("en")
+            ),
+            "de.wikipedia.org is not the host of a wiki served"
+        );
+        assert_eq!(
+            resolve(Some(StartFrom::title("Train".to_string())), Some("fr")),
+            "Wiki fr is not served"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_management_cache_stats() {
+        let (tx_to_fetch, mut rx_by_fetch) = mpsc::channel(1);
+        let fetch_service = tokio::spawn(async move {
+            if let Some(fetch::FetchCommand::Stats { tx }) = rx_by_fetch.recv().await {
+                let stats = crate::cache::CacheStats {
+                    pages: 2,
+                    hits: 3,
+                    misses: 1,
+                    hit_ratio: 0.75,
+                    ..Default::default()
+                };
+                tx.send(stats).await.unwrap();
+            }
+        });
+
+        let request = Request::get("/cache").body(Body::empty()).unwrap();
+        let response = management_service(request, tx_to_fetch.clone())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(stats["pages"], 2);
+        assert_eq!(stats["hit_ratio"], 0.75);
+        fetch_service.await.unwrap();
+
+        let request = Request::get("/other").body(Body::empty()).unwrap();
+        let response = management_service(request, tx_to_fetch).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
     /*  Tests
 
     1. valid v4 with port
@@ -0,0 +1,1524 @@
+/* *****************************************************************************************************************
+ *
+ * Cache
+ * -----
+ *
+ * Pages pulled from Wikipedia are held in an append-only pack-file store in the pack directory of the wiki, as
+ * FetchEntries serialized with FetchEntry::to. With opt::cache_raw, the raw API payloads are held in a second store
+ * in the raw directory of the wiki. Each wiki served has a directory in the cache, named with its identifier, e.g.
+ * <cache>/de/pack. Each store is split into SHARD_COUNT shards, selected by the first byte of the
+ * page digest, so no single file grows without bound and compaction only ever rewrites one shard at a time.
+ *
+ * Pack file (pack_XX.pack)
+ *    PACK_MAGIC (8 bytes, includes the format version)
+ *    repeated for each record
+ *       digest of the title (16 bytes)
+ *       length of the title (u16, little-endian)
+ *       length of the payload (u32, little-endian)
+ *       CRC-32 of the title and payload (u32, little-endian)
+ *       the title, as requested from the fetch service
+ *       the payload, deflate compressed
+ *
+ * Records are only ever appended. A page that is cached again is appended as a new record, and the earlier record
+ * becomes dead space. When the dead space in a shard exceeds COMPACT_MIN_BYTES and outweighs the live records, the
+ * shard is compacted: the live records are copied to a temporary file, which is then renamed over the pack file.
+ *
+ * Index file (pack_XX.idx)
+ *    INDEX_MAGIC (8 bytes)
+ *    the index, serialized with bincode: the length of the pack file covered by the index, and the offset, length and
+ *    last use of the latest record for each digest
+ *
+ * The index is written to a temporary file and renamed over the index when the store is flushed or a shard is
+ * compacted. On open, any records appended after the index was written are read from the tail of the pack file, so
+ * an index that was not flushed (e.g. after a crash) costs time, not data. A record truncated by a crash is cut from
+ * the end of the pack file. A record damaged in place fails its CRC when read, and is reported as an error, so the
+ * page is pulled from Wikipedia again. `verify` checks every record, and moves damaged records to the quarantine
+ * directory of the cache.
+ *
+ * Eviction
+ * With opt::cache_max_size, the least recently used pages are evicted once the pack files grow beyond the maximum
+ * size, until they hold EVICT_TO_PERCENT of the maximum. Last use is a counter that advances with each read and
+ * write, and is held in the index, so it survives a restart. Pages that are hubs in the in-memory graph are pinned
+ * by the workers, and are never evicted. Pins are held in memory only. If the pinned pages alone hold more than
+ * EVICT_TO_PERCENT of the maximum, a warning is logged once, and eviction is not tried again until the pack files have
+ * grown by the same margin, 100 - EVICT_TO_PERCENT of the maximum, past the pinned pages. Otherwise every write would
+ * rewrite the shards to evict the few pages written since the last.
+ *
+ * Records are keyed by the digest of the title, and the title is held inside the record, so titles never form part of
+ * a file name. Titles holding '/', "..", trailing dots, or more than 255 bytes are cached like any other.
+ *
+ * Caches written by earlier versions held one JSON file per title, in a three-level directory tree named after the
+ * digest. `migrate` parses those pages into the store. Pack files written before records held a title and CRC
+ * (PACK_MAGIC_V1) are upgraded when they are opened. Stores written before digests held the wiki identifier were held
+ * directly in the cache directory, and are moved into the directory of the default wiki by `move_single_wiki_cache`.
+ * Each page is filed again under its new digest, read from the title held in the record.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression, Crc};
+
+use crate::entry::{Digest, Entry};
+use crate::fetch::{self, FetchEntry};
+use crate::wiki::Wiki;
+
+static PACK_DIRECTORY: &str = "pack";
+static RAW_DIRECTORY: &str = "raw";
+static CONTEXT_DIRECTORY: &str = "context";
+static QUARANTINE_DIRECTORY: &str = "quarantine";
+static PACK_MAGIC: &[u8; 8] = b"6DPACK02";
+static PACK_MAGIC_V1: &[u8; 8] = b"6DPACK01";
+static INDEX_MAGIC: &[u8; 8] = b"6DINDX02";
+static SHARD_COUNT: usize = 16;
+static HEADER_LENGTH: u64 = 16 + 2 + 4 + 4;
+static HEADER_LENGTH_V1: u64 = 16 + 4;
+static COMPACT_MIN_BYTES: u64 = 4 * 1024 * 1024;
+static EVICT_TO_PERCENT: u64 = 90;
+
+// ***********************************************************************************************
+
+// Position of a record in a pack file. The offset is the start of the record header, and the length is the length of
+// the title and payload
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+struct Location {
+    offset: u64,
+    length: u32,
+    // Value of PackStore::clock when the record was last read or written. 0 if not known
+    used: u64,
+}
+
+impl Location {
+    fn record_length(&self) -> u64 {
+        HEADER_LENGTH + self.length as u64
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct Index {
+    pack_length: u64,
+    locations: HashMap<Digest, Location>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Record {
+    digest: Digest,
+    title: String,
+    // Deflate compressed
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Header {
+    digest: Digest,
+    title_length: u16,
+    payload_length: u32,
+    crc: u32,
+}
+
+struct Shard {
+    pack_path: PathBuf,
+    index_path: PathBuf,
+    file: File,
+    index: Index,
+    // Bytes held by records that have been superseded
+    dead: u64,
+    // The index on disk is out of date
+    dirty: bool,
+}
+
+pub struct PackStore {
+    shards: Vec<Shard>,
+    max_bytes: Option<u64>,
+    clock: u64,
+    pinned: HashSet<Digest>,
+    // Size up to which eviction is not tried, when the pinned pages hold more than the eviction target
+    evict_above: Option<u64>,
+    hits: u64,
+    misses: u64,
+}
+
+// Reported by the management API
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub pages: u64,
+    pub bytes: u64,
+    pub max_bytes: Option<u64>,
+    pub pinned: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+}
+
+impl CacheStats {
+    /// Combine the stats of the stores of two wikis
+    pub fn add(self, other: CacheStats) -> CacheStats {
+        let hits = self.hits + other.hits;
+        let misses = self.misses + other.misses;
+        CacheStats {
+            pages: self.pages + other.pages,
+            bytes: self.bytes + other.bytes,
+            max_bytes: match (self.max_bytes, other.max_bytes) {
+                (Some(max_bytes), Some(other_max_bytes)) => Some(max_bytes + other_max_bytes),
+                (max_bytes, None) | (None, max_bytes) => max_bytes,
+            },
+            pinned: self.pinned + other.pinned,
+            hits,
+            misses,
+            hit_ratio: match hits + misses {
+                0 => 0.0,
+                requests => hits as f64 / requests as f64,
+            },
+        }
+    }
+}
+
+// Reported by migrate
+#[derive(Debug, Default, PartialEq)]
+pub struct MigrateSummary {
+    pub migrated: u64,
+    pub skipped: u64,
+}
+
+impl fmt::Display for MigrateSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pages migrated: {}, files skipped: {}",
+            self.migrated, self.skipped
+        )
+    }
+}
+
+// Reported by verify. Titles are those of the quarantined pages that could be read, so the pages can be fetched again
+#[derive(Debug, Default, PartialEq)]
+pub struct VerifySummary {
+    pub checked: u64,
+    pub quarantined: u64,
+    pub titles: Vec<String>,
+}
+
+impl fmt::Display for VerifySummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pages checked: {}, pages quarantined: {}",
+            self.checked, self.quarantined
+        )
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Pack store
+ *
+ *******************************************************************************************************************/
+
+/// Directory of the store holding the FetchEntries of the wiki
+pub fn get_pack_directory(cache: &Path, wiki: &Wiki) -> PathBuf {
+    cache.join(&wiki.id).join(PACK_DIRECTORY)
+}
+
+/// Directory of the store holding the raw API payloads of the wiki
+pub fn get_raw_directory(cache: &Path, wiki: &Wiki) -> PathBuf {
+    cache.join(&wiki.id).join(RAW_DIRECTORY)
+}
+
+/// Directory of the store holding the link contexts of the pages of the wiki
+pub fn get_context_directory(cache: &Path, wiki: &Wiki) -> PathBuf {
+    cache.join(&wiki.id).join(CONTEXT_DIRECTORY)
+}
+
+/// Directory holding the records of the wiki that failed verification
+pub fn get_quarantine_directory(cache: &Path, wiki: &Wiki) -> PathBuf {
+    cache.join(&wiki.id).join(QUARANTINE_DIRECTORY)
+}
+
+impl PackStore {
+    /// Open the store in `directory`, creating it if necessary
+    pub fn open(directory: &Path) -> Result<PackStore, io::Error> {
+        fs::create_dir_all(directory)?;
+        let shards = (0..SHARD_COUNT)
+            .map(|shard| Shard::open(directory, shard))
+            .collect::<Result<Vec<Shard>, io::Error>>()?;
+        let clock = shards
+            .iter()
+            .flat_map(|shard| shard.index.locations.values())
+            .map(|location| location.used)
+            .max()
+            .unwrap_or_default();
+        Ok(PackStore {
+            shards,
+            max_bytes: None,
+            clock,
+            pinned: HashSet::new(),
+            evict_above: None,
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Limit the size of the pack files. None removes the limit
+    pub fn set_max_size(&mut self, max_bytes: Option<u64>) {
+        self.max_bytes = max_bytes;
+        self.evict_above = None;
+    }
+
+    /// Read the payload cached for the digest. A record that fails its CRC is reported as InvalidData
+    pub fn get(&mut self, digest: &Digest) -> Result<Option<Vec<u8>>, io::Error> {
+        let used = self.tick();
+        let payload = self.shard_for(digest).get(digest, used)?;
+        match payload {
+            Some(_) => self.hits += 1,
+            None => self.misses += 1,
+        }
+        Ok(payload)
+    }
+
+    /// Append the payload to the store, replacing any payload already cached for the digest
+    pub fn put(&mut self, digest: &Digest, title: &str, payload: &[u8]) -> Result<(), io::Error> {
+        let used = self.tick();
+        let shard = self.shard_for(digest);
+        shard.put(digest, title, payload, used)?;
+        if shard.needs_compaction() {
+            shard.compact()?;
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if self.size() > self.evict_above.unwrap_or_default().max(max_bytes) {
+                self.evict(max_bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Protect the page from eviction
+    pub fn pin(&mut self, digest: Digest) {
+        self.pinned.insert(digest);
+    }
+
+    /// Bytes held by the pack files, including superseded records not yet compacted
+    pub fn size(&self) -> u64 {
+        self.shards
+            .iter()
+            .map(|shard| shard.index.pack_length)
+            .sum()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let requests = self.hits + self.misses;
+        CacheStats {
+            pages: self.len() as u64,
+            bytes: self.size(),
+            max_bytes: self.max_bytes,
+            pinned: self.pinned.len() as u64,
+            hits: self.hits,
+            misses: self.misses,
+            hit_ratio: match requests {
+                0 => 0.0,
+                _ => self.hits as f64 / requests as f64,
+            },
+        }
+    }
+
+    /// Number of pages held in the store
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.index.locations.len())
+            .sum()
+    }
+
+    /// Compact every shard holding dead records. Returns the number of bytes reclaimed
+    pub fn compact(&mut self) -> Result<u64, io::Error> {
+        let mut reclaimed = 0;
+        for shard in self.shards.iter_mut().filter(|shard| shard.dead > 0) {
+            reclaimed += shard.compact()?;
+        }
+        Ok(reclaimed)
+    }
+
+    /// Write the index of every shard that has changed since it was opened
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        for shard in self.shards.iter_mut().filter(|shard| shard.dirty) {
+            shard.save_index()?;
+        }
+        Ok(())
+    }
+
+    /// Check that every record passes its CRC, decompresses, and holds a FetchEntry. Records that fail are copied to
+    /// `quarantine`, named with the hex digest, and removed from the store
+    pub fn verify(&mut self, quarantine: &Path) -> Result<VerifySummary, io::Error> {
+        let mut summary = VerifySummary::default();
+        for shard in self.shards.iter_mut() {
+            for (digest, location) in shard.locations_in_order() {
+                summary.checked += 1;
+                let bytes = shard.read_bytes(&location)?;
+                let error = match check_record(&digest, &bytes) {
+                    Ok(_) => continue,
+                    Err(error) => error,
+                };
+
+                let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+                warn!("Quarantining cached page {}: {}", name, error);
+                fs::create_dir_all(quarantine)?;
+                fs::write(quarantine.join(name).with_extension("record"), &bytes)?;
+                shard.remove(&digest);
+                summary.quarantined += 1;
+                if let Some(title) = recover_title(&bytes) {
+                    summary.titles.push(title);
+                }
+            }
+        }
+        self.compact()?;
+        self.flush()?;
+        Ok(summary)
+    }
+
+    fn shard_for(&mut self, digest: &Digest) -> &mut Shard {
+        &mut self.shards[digest[0] as usize % SHARD_COUNT]
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    // Remove the least recently used pages that are not pinned, until the pack files hold EVICT_TO_PERCENT of the
+    // maximum size. If the pinned pages alone hold more, eviction waits until the pack files grow by the margin again.
+    // When every page is pinned there is nothing to evict, and the shards are left as they are
+    fn evict(&mut self, max_bytes: u64) -> Result<(), io::Error> {
+        let target = max_bytes / 100 * EVICT_TO_PERCENT;
+        let mut candidates: Vec<(u64, usize, Digest, u64)> = Vec::new();
+        for (shard_id, shard) in self.shards.iter().enumerate() {
+            for (digest, location) in &shard.index.locations {
+                if !self.pinned.contains(digest) {
+                    candidates.push((location.used, shard_id, *digest, location.record_length()));
+                }
+            }
+        }
+
+        if !candidates.is_empty() {
+            self.compact()?;
+            let mut size = self.size();
+            if size <= max_bytes {
+                self.evict_above = None;
+                return Ok(());
+            }
+            candidates.sort_unstable_by_key(|(used, _, _, _)| *used);
+
+            let mut evicted = 0;
+            for (_, shard_id, digest, length) in candidates {
+                if size <= target {
+                    break;
+                }
+                self.shards[shard_id].remove(&digest);
+                size -= length;
+                evicted += 1;
+            }
+            self.compact()?;
+            info!(
+                "Evicted {} pages from the page cache, which now holds {} bytes",
+                evicted,
+                self.size()
+            );
+        }
+
+        let size = self.size();
+        if size <= target {
+            self.evict_above = None;
+            return Ok(());
+        }
+        if self.evict_above.is_none() {
+            warn!(
+                "The pinned pages of the page cache hold {} bytes, more than {}% of its maximum size",
+                size, EVICT_TO_PERCENT
+            );
+        }
+        self.evict_above = Some(size + max_bytes - target);
+        Ok(())
+    }
+}
+
+impl Shard {
+    fn open(directory: &Path, shard: usize) -> Result<Shard, io::Error> {
+        let pack_path = directory.join(format!("pack_{:02x}.pack", shard));
+        let index_path = pack_path.with_extension("idx");
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&pack_path)?;
+
+        let mut file_length = file.metadata()?.len();
+        if file_length == 0 {
+            file.write_all(PACK_MAGIC)?;
+        } else {
+            let mut magic = [0u8; 8];
+            file.read_exact(&mut magic)?;
+            if &magic == PACK_MAGIC_V1 {
+                drop(file);
+                upgrade_v1(&pack_path)?;
+                let _ = fs::remove_file(&index_path);
+                file = OpenOptions::new().read(true).write(true).open(&pack_path)?;
+                file_length = file.metadata()?.len();
+            } else if &magic != PACK_MAGIC {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} is not a six_degrees pack file",
+                        pack_path.to_string_lossy()
+                    ),
+                ));
+            }
+        }
+
+        // An index that claims more of the pack file than exists is stale. Rebuild it from the pack file
+        let index = match load_index(&index_path) {
+            Ok(index) if index.pack_length <= file_length => index,
+            _ => Index {
+                pack_length: PACK_MAGIC.len() as u64,
+                locations: HashMap::new(),
+            },
+        };
+
+        let mut shard = Shard {
+            pack_path,
+            index_path,
+            file,
+            index,
+            dead: 0,
+            dirty: false,
+        };
+        shard.scan_tail()?;
+        let live: u64 = shard
+            .index
+            .locations
+            .values()
+            .map(|location| location.record_length())
+            .sum();
+        shard.dead = shard.index.pack_length - PACK_MAGIC.len() as u64 - live;
+        Ok(shard)
+    }
+
+    // Index the records appended after the index was written, and cut any partial record from the end of the file
+    fn scan_tail(&mut self) -> Result<(), io::Error> {
+        let file_length = self.file.metadata()?.len();
+        let mut offset = self.index.pack_length;
+        let mut reader = BufReader::new(&self.file);
+        reader.seek(SeekFrom::Start(offset))?;
+
+        let mut header = [0u8; HEADER_LENGTH as usize];
+        while offset + HEADER_LENGTH <= file_length {
+            reader.read_exact(&mut header)?;
+            let header = Header::from(&header);
+            let location = Location {
+                offset,
+                length: header.title_length as u32 + header.payload_length,
+                used: 0,
+            };
+            if offset + location.record_length() > file_length {
+                break;
+            }
+            reader.seek_relative(location.length as i64)?;
+            self.index.locations.insert(header.digest, location);
+            offset += location.record_length();
+        }
+
+        if offset < file_length {
+            warn!(
+                "Removing a partial record from the end of {}",
+                self.pack_path.to_string_lossy()
+            );
+            self.file.set_len(offset)?;
+        }
+        if offset != self.index.pack_length {
+            self.index.pack_length = offset;
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn get(&mut self, digest: &Digest, used: u64) -> Result<Option<Vec<u8>>, io::Error> {
+        let location = match self.index.locations.get_mut(digest) {
+            Some(location) => {
+                location.used = used;
+                *location
+            }
+            None => return Ok(None),
+        };
+        self.dirty = true;
+        let record = Record::from(&self.read_bytes(&location)?)?;
+        let mut payload = Vec::new();
+        DeflateDecoder::new(&record.payload[..]).read_to_end(&mut payload)?;
+        Ok(Some(payload))
+    }
+
+    fn put(
+        &mut self,
+        digest: &Digest,
+        title: &str,
+        payload: &[u8],
+        used: u64,
+    ) -> Result<(), io::Error> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload)?;
+        let record = Record {
+            digest: *digest,
+            title: title.to_string(),
+            payload: encoder.finish()?,
+        }
+        .to()?;
+
+        let location = Location {
+            offset: self.index.pack_length,
+            length: (record.len() as u64 - HEADER_LENGTH) as u32,
+            used,
+        };
+        self.file.seek(SeekFrom::Start(location.offset))?;
+        self.file.write_all(&record)?;
+
+        if let Some(previous) = self.index.locations.insert(*digest, location) {
+            self.dead += previous.record_length();
+        }
+        self.index.pack_length += location.record_length();
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Drop the record from the index. The space is reclaimed when the shard is compacted
+    fn remove(&mut self, digest: &Digest) {
+        if let Some(location) = self.index.locations.remove(digest) {
+            self.dead += location.record_length();
+            self.dirty = true;
+        }
+    }
+
+    // Read a record, header included, without checking it
+    fn read_bytes(&mut self, location: &Location) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = vec![0u8; location.record_length() as usize];
+        self.file.seek(SeekFrom::Start(location.offset))?;
+        self.file.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    // The live records, in the order in which they appear in the pack file
+    fn locations_in_order(&self) -> Vec<(Digest, Location)> {
+        let mut locations: Vec<(Digest, Location)> = self
+            .index
+            .locations
+            .iter()
+            .map(|(digest, location)| (*digest, *location))
+            .collect();
+        locations.sort_by_key(|(_, location)| location.offset);
+        locations
+    }
+
+    fn needs_compaction(&self) -> bool {
+        let live = self.index.pack_length - PACK_MAGIC.len() as u64 - self.dead;
+        self.dead > COMPACT_MIN_BYTES && self.dead > live
+    }
+
+    // Copy the live records to a new pack file, and replace the old pack file. Returns the number of bytes reclaimed
+    fn compact(&mut self) -> Result<u64, io::Error> {
+        let temp_path = self.pack_path.with_extension("compact");
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(PACK_MAGIC)?;
+
+        let locations = self.locations_in_order();
+        let mut index = Index {
+            pack_length: PACK_MAGIC.len() as u64,
+            locations: HashMap::with_capacity(locations.len()),
+        };
+        for (digest, location) in locations {
+            writer.write_all(&self.read_bytes(&location)?)?;
+            let compacted = Location {
+                offset: index.pack_length,
+                ..location
+            };
+            index.pack_length += compacted.record_length();
+            index.locations.insert(digest, compacted);
+        }
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+
+        fs::rename(&temp_path, &self.pack_path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.pack_path)?;
+        let reclaimed = self.dead;
+        info!(
+            "Compacted {}, reclaimed {} bytes",
+            self.pack_path.to_string_lossy(),
+            reclaimed
+        );
+        self.index = index;
+        self.dead = 0;
+        self.save_index()?;
+        Ok(reclaimed)
+    }
+
+    fn save_index(&mut self) -> Result<(), io::Error> {
+        self.file.sync_data()?;
+        let bytes = bincode::serialize(&self.index)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let mut contents = Vec::with_capacity(INDEX_MAGIC.len() + bytes.len());
+        contents.extend_from_slice(INDEX_MAGIC);
+        contents.extend_from_slice(&bytes);
+        write_atomic(&self.index_path, &contents)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Header {
+    fn from(bytes: &[u8]) -> Header {
+        let mut digest = [0u8; 16];
+        digest.copy_from_slice(&bytes[..16]);
+        Header {
+            digest,
+            title_length: u16::from_le_bytes([bytes[16], bytes[17]]),
+            payload_length: u32::from_le_bytes([bytes[18], bytes[19], bytes[20], bytes[21]]),
+            crc: u32::from_le_bytes([bytes[22], bytes[23], bytes[24], bytes[25]]),
+        }
+    }
+}
+
+impl Record {
+    // Read a record, checking its length and CRC
+    fn from(bytes: &[u8]) -> Result<Record, io::Error> {
+        if bytes.len() < HEADER_LENGTH as usize {
+            return Err(invalid_data("Record is shorter than its header"));
+        }
+        let header = Header::from(bytes);
+        let body = &bytes[HEADER_LENGTH as usize..];
+        if body.len() != header.title_length as usize + header.payload_length as usize {
+            return Err(invalid_data("Record length does not match its header"));
+        }
+        if crc_of(body) != header.crc {
+            return Err(invalid_data("Record fails its CRC"));
+        }
+        let (title, payload) = body.split_at(header.title_length as usize);
+        Ok(Record {
+            digest: header.digest,
+            title: String::from_utf8(title.to_vec())
+                .map_err(|_| invalid_data("Record title is not UTF-8"))?,
+            payload: payload.to_vec(),
+        })
+    }
+
+    fn to(&self) -> Result<Vec<u8>, io::Error> {
+        let title_length: u16 = self
+            .title
+            .len()
+            .try_into()
+            .map_err(|_| invalid_data("Title is too long to cache"))?;
+        let mut bytes =
+            Vec::with_capacity(HEADER_LENGTH as usize + self.title.len() + self.payload.len());
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&title_length.to_le_bytes());
+        bytes.extend_from_slice(&(self.payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 4]);
+        bytes.extend_from_slice(self.title.as_bytes());
+        bytes.extend_from_slice(&self.payload);
+        let crc = crc_of(&bytes[HEADER_LENGTH as usize..]);
+        bytes[22..26].copy_from_slice(&crc.to_le_bytes());
+        Ok(bytes)
+    }
+}
+
+// A record is good if it passes its CRC, is filed under the right digest, and holds a FetchEntry or the record of a
+// page that was not found
+fn check_record(digest: &Digest, bytes: &[u8]) -> Result<(), String> {
+    let record = Record::from(bytes).map_err(|err| err.to_string())?;
+    if &record.digest != digest {
+        return Err("Record is filed under the wrong digest".to_string());
+    }
+    let mut payload = Vec::new();
+    DeflateDecoder::new(&record.payload[..])
+        .read_to_end(&mut payload)
+        .map_err(|err| err.to_string())?;
+    if fetch::missing_since(&payload).is_some() {
+        return Ok(());
+    }
+    FetchEntry::from(&payload)
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+// The title of a damaged record, if the title can still be read
+fn recover_title(bytes: &[u8]) -> Option<String> {
+    if bytes.len() < HEADER_LENGTH as usize {
+        return None;
+    }
+    let header = Header::from(bytes);
+    let title =
+        bytes.get(HEADER_LENGTH as usize..HEADER_LENGTH as usize + header.title_length as usize)?;
+    String::from_utf8(title.to_vec())
+        .ok()
+        .filter(|title| !title.is_empty())
+}
+
+// Rewrite a pack file from before records held a title and CRC. The title is read from the cached page, and pages
+// that cannot be read are dropped
+fn upgrade_v1(pack_path: &Path) -> Result<(), io::Error> {
+    info!("Upgrading {}", pack_path.to_string_lossy());
+    let mut reader = BufReader::new(File::open(pack_path)?);
+    reader.seek(SeekFrom::Start(PACK_MAGIC_V1.len() as u64))?;
+    let mut contents = PACK_MAGIC.to_vec();
+
+    let mut header = [0u8; HEADER_LENGTH_V1 as usize];
+    while reader.read_exact(&mut header).is_ok() {
+        let mut digest = [0u8; 16];
+        digest.copy_from_slice(&header[..16]);
+        let length = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+        let mut compressed = vec![0u8; length as usize];
+        if reader.read_exact(&mut compressed).is_err() {
+            break;
+        }
+
+        let mut payload = Vec::new();
+        let title = DeflateDecoder::new(&compressed[..])
+            .read_to_end(&mut payload)
+            .ok()
+            .and_then(|_| FetchEntry::from(&payload).ok())
+            .map(|fetch_entry| fetch_entry.title);
+        match title {
+            Some(title) => contents.extend(
+                Record {
+                    digest,
+                    title,
+                    payload: compressed,
+                }
+                .to()?,
+            ),
+            None => warn!("Dropping an unreadable page while upgrading the page cache"),
+        }
+    }
+    write_atomic(pack_path, &contents)
+}
+
+fn load_index(path: &Path) -> Result<Index, io::Error> {
+    let contents = fs::read(path)?;
+    if contents.len() < INDEX_MAGIC.len() || &contents[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is not a six_degrees index", path.to_string_lossy()),
+        ));
+    }
+    bincode::deserialize(&contents[INDEX_MAGIC.len()..])
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// Write to a temporary file, and rename it over the target, so the target is never left half-written
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), io::Error> {
+    let temp_path = path.with_extension("tmp");
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temp_path, path)
+}
+
+fn crc_of(bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new();
+    crc.update(bytes);
+    crc.sum()
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/* *****************************************************************************************************************
+ *
+ * Migrate from the one-file-per-title cache
+ *
+ * The earlier cache held the JSON returned by the parse API in <cache>/XX/XX/XX/<title>.json. The file name is not a
+ * reliable record of the title (titles holding a '.' lost their tail to the extension), so pages are keyed by the
+ * title in the payload. A title holding '/' was written to sub-directories of the XX/XX/XX directory (AC/DC became
+ * AC/DC.json), so each XX/XX/XX directory is searched recursively. Files that cannot be parsed as a page are skipped
+ * and left in place.
+ *
+ *******************************************************************************************************************/
+
+pub fn migrate(
+    legacy: &Path,
+    store: &mut PackStore,
+    remove: bool,
+) -> Result<MigrateSummary, io::Error> {
+    let mut summary = MigrateSummary::default();
+    for level_1 in hex_directories(legacy)? {
+        for level_2 in hex_directories(&level_1)? {
+            for level_3 in hex_directories(&level_2)? {
+                migrate_directory(&level_3, store, remove, &mut summary)?;
+            }
+            if remove {
+                let _ = fs::remove_dir(&level_2);
+            }
+        }
+        if remove {
+            let _ = fs::remove_dir(&level_1);
+        }
+    }
+    store.flush()?;
+    Ok(summary)
+}
+
+// Migrate every page in the directory and its sub-directories. Directories are removed once empty
+fn migrate_directory(
+    directory: &Path,
+    store: &mut PackStore,
+    remove: bool,
+    summary: &mut MigrateSummary,
+) -> Result<(), io::Error> {
+    for file in fs::read_dir(directory)? {
+        let path = file?.path();
+        if path.is_dir() {
+            migrate_directory(&path, store, remove, summary)?;
+        } else if path.is_file() {
+            migrate_page(&path, store, remove, summary)?;
+        }
+    }
+    if remove {
+        let _ = fs::remove_dir(directory);
+    }
+    Ok(())
+}
+
+fn migrate_page(
+    path: &Path,
+    store: &mut PackStore,
+    remove: bool,
+    summary: &mut MigrateSummary,
+) -> Result<(), io::Error> {
+    let contents = fs::read(path)?;
+    match FetchEntry::from(&contents) {
+        Ok(fetch_entry) => {
+            let digest = Entry::get_digest(&fetch_entry.title);
+            store.put(&digest, &fetch_entry.title, &fetch_entry.to())?;
+            summary.migrated += 1;
+            if remove {
+                fs::remove_file(path)?;
+            }
+        }
+        Err(err) => {
+            warn!("Skipping {}: {}", path.to_string_lossy(), err);
+            summary.skipped += 1;
+        }
+    }
+    Ok(())
+}
+
+/* *****************************************************************************************************************
+ *
+ * Move the stores of a single-wiki cache
+ *
+ * Before several wikis could be served, the pack and raw stores were held directly in the cache directory, under
+ * digests of the title alone. The stores are moved into the directory of the wiki, and each page is filed under the
+ * digest of the wiki and title. Records that cannot be read are dropped, and the old store is removed.
+ *
+ *******************************************************************************************************************/
+
+/// Move the stores held directly in the cache directory into the directory of the wiki. Does nothing if there are no
+/// such stores
+pub fn move_single_wiki_cache(cache: &Path, wiki: &Wiki) -> Result<MigrateSummary, io::Error> {
+    let mut summary = MigrateSummary::default();
+    for (legacy, directory) in [
+        (cache.join(PACK_DIRECTORY), get_pack_directory(cache, wiki)),
+        (cache.join(RAW_DIRECTORY), get_raw_directory(cache, wiki)),
+    ] {
+        if !legacy.is_dir() {
+            continue;
+        }
+        info!(
+            "Moving {} to {}",
+            legacy.to_string_lossy(),
+            directory.to_string_lossy()
+        );
+        let mut legacy_store = PackStore::open(&legacy)?;
+        let mut store = PackStore::open(&directory)?;
+        for shard in legacy_store.shards.iter_mut() {
+            for (_, location) in shard.locations_in_order() {
+                let record = shard
+                    .read_bytes(&location)
+                    .and_then(|bytes| Record::from(&bytes));
+                let mut payload = Vec::new();
+                match record.and_then(|record| {
+                    DeflateDecoder::new(&record.payload[..]).read_to_end(&mut payload)?;
+                    Ok(record.title)
+                }) {
+                    Ok(title) => {
+                        store.put(&Entry::get_digest_for(wiki, &title), &title, &payload)?;
+                        summary.migrated += 1;
+                    }
+                    Err(err) => {
+                        warn!("Dropping a cached page that cannot be read: {}", err);
+                        summary.skipped += 1;
+                    }
+                }
+            }
+        }
+        store.flush()?;
+        drop(legacy_store);
+        fs::remove_dir_all(&legacy)?;
+    }
+    Ok(summary)
+}
+
+// Sub-directories named with two hex digits, as created by the earlier cache
+fn hex_directories(directory: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    let mut directories = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let is_hex = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(|name| name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit()))
+            .unwrap_or(false);
+        if is_hex && path.is_dir() {
+            directories.push(path);
+        }
+    }
+    Ok(directories)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wiki;
+
+    #[test]
+    fn test_put_get() {
+        let cache = get_cache_path("put_get");
+        let mut store = PackStore::open(&cache).unwrap();
+        let train = Entry::get_digest("Train");
+        store.put(&train, "Train", b"Train payload").unwrap();
+        store
+            .put(
+                &Entry::get_digest("Rail transport"),
+                "Rail transport",
+                b"Rail payload",
+            )
+            .unwrap();
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&train).unwrap().unwrap(), b"Train payload");
+        assert!(store.get(&Entry::get_digest("Missing")).unwrap().is_none());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let cache = get_cache_path("reopen");
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            store.put(&train, "Train", b"Train payload").unwrap();
+            store.flush().unwrap();
+            // Appended after the index was written
+            store
+                .put(&locomotive, "Locomotive", b"Locomotive payload")
+                .unwrap();
+            store
+                .put(&train, "Train", b"Train payload, updated")
+                .unwrap();
+        }
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(
+            store.get(&train).unwrap().unwrap(),
+            b"Train payload, updated"
+        );
+        assert_eq!(
+            store.get(&locomotive).unwrap().unwrap(),
+            b"Locomotive payload"
+        );
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_partial_record() {
+        let cache = get_cache_path("partial_record");
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            store.put(&train, "Train", b"Train payload").unwrap();
+            store
+                .put(&locomotive, "Locomotive", b"Locomotive payload")
+                .unwrap();
+        }
+        let pack_path = get_pack_path(&cache, &locomotive);
+        let length = fs::metadata(&pack_path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&pack_path)
+            .unwrap()
+            .set_len(length - 3)
+            .unwrap();
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert!(store.get(&locomotive).unwrap().is_none());
+        assert_eq!(store.get(&train).unwrap().unwrap(), b"Train payload");
+        store
+            .put(&locomotive, "Locomotive", b"Locomotive payload")
+            .unwrap();
+        assert_eq!(
+            store.get(&locomotive).unwrap().unwrap(),
+            b"Locomotive payload"
+        );
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_damaged_record() {
+        let cache = get_cache_path("damaged_record");
+        let train = Entry::get_digest("Train");
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            store.put(&train, "Train", b"Train payload").unwrap();
+        }
+        damage_last_byte(&cache, &train);
+
+        let mut store = PackStore::open(&cache).unwrap();
+        let err = store.get(&train).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // Caching the page again replaces the damaged record
+        store.put(&train, "Train", b"Train payload").unwrap();
+        assert_eq!(store.get(&train).unwrap().unwrap(), b"Train payload");
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let cache = get_cache_path("verify");
+        let quarantine = cache.join(QUARANTINE_DIRECTORY);
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        let rail_transport = Entry::get_digest("Rail transport");
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            // Written first, so the damaged byte is in the record for Train
+            store
+                .put(&rail_transport, "Rail transport", b"Not a FetchEntry")
+                .unwrap();
+            store
+                .put(
+                    &Entry::get_digest("Tram"),
+                    "Tram",
+                    &fetch::missing_record(1),
+                )
+                .unwrap();
+            for title in ["Train", "Locomotive"] {
+                let fetch_entry = FetchEntry::from_links(title.to_string(), vec![], None);
+                store
+                    .put(&Entry::get_digest(title), title, &fetch_entry.to())
+                    .unwrap();
+            }
+        }
+        damage_last_byte(&cache, &train);
+
+        let mut store = PackStore::open(&cache).unwrap();
+        let mut summary = store.verify(&quarantine).unwrap();
+        summary.titles.sort();
+        assert_eq!(
+            summary,
+            VerifySummary {
+                checked: 4,
+                quarantined: 2,
+                titles: vec!["Rail transport".to_string(), "Train".to_string()]
+            }
+        );
+        assert_eq!(fs::read_dir(&quarantine).unwrap().count(), 2);
+        assert!(store.get(&train).unwrap().is_none());
+        assert!(store.get(&locomotive).unwrap().is_some());
+        drop(store);
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.verify(&quarantine).unwrap().quarantined, 0);
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_v1() {
+        let cache = get_cache_path("upgrade_v1");
+        let train = Entry::get_digest("Train");
+        let fetch_entry = FetchEntry::from_links("Train".to_string(), vec![], None);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&fetch_entry.to()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut contents = PACK_MAGIC_V1.to_vec();
+        contents.extend_from_slice(&train);
+        contents.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        contents.extend_from_slice(&compressed);
+        // An unreadable page, which is dropped
+        contents.extend_from_slice(&Entry::get_digest("Locomotive"));
+        contents.extend_from_slice(&3u32.to_le_bytes());
+        contents.extend_from_slice(b"bad");
+        fs::create_dir_all(&cache).unwrap();
+        fs::write(get_pack_path(&cache, &train), contents).unwrap();
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.len(), 1);
+        let restored = FetchEntry::from(&store.get(&train).unwrap().unwrap()).unwrap();
+        assert_eq!(restored.title, "Train");
+        assert_eq!(
+            recover_title(&fs::read(get_pack_path(&cache, &train)).unwrap()[8..]),
+            Some("Train".to_string())
+        );
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_compact() {
+        let cache = get_cache_path("compact");
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        let mut store = PackStore::open(&cache).unwrap();
+        for version in 0..10 {
+            store
+                .put(
+                    &train,
+                    "Train",
+                    format!("Train payload {}", version).as_bytes(),
+                )
+                .unwrap();
+        }
+        store
+            .put(&locomotive, "Locomotive", b"Locomotive payload")
+            .unwrap();
+
+        assert!(store.compact().unwrap() > 0);
+        assert_eq!(store.compact().unwrap(), 0);
+        assert_eq!(store.get(&train).unwrap().unwrap(), b"Train payload 9");
+        assert_eq!(
+            store.get(&locomotive).unwrap().unwrap(),
+            b"Locomotive payload"
+        );
+        drop(store);
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&train).unwrap().unwrap(), b"Train payload 9");
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = get_cache_path("evict");
+        let mut store = PackStore::open(&cache).unwrap();
+        let pages: Vec<Digest> = (0..20)
+            .map(|page| Entry::get_digest(&format!("Page {}", page)))
+            .collect();
+        // Random bytes do not compress, so each record is a little over 1000 bytes
+        let mut seed: u32 = 1;
+        let payload: Vec<u8> = (0..1000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 24) as u8
+            })
+            .collect();
+        for digest in &pages[..10] {
+            store.put(digest, "Page", &payload).unwrap();
+        }
+        store.pin(pages[0]);
+        store.get(&pages[1]).unwrap();
+        store.set_max_size(Some(store.size() + 2000));
+
+        for digest in &pages[10..15] {
+            store.put(digest, "Page", &payload).unwrap();
+        }
+        assert!(store.size() <= store.max_bytes.unwrap());
+        // Pinned and recently used pages are kept; the oldest are evicted
+        assert!(store.get(&pages[0]).unwrap().is_some());
+        assert!(store.get(&pages[1]).unwrap().is_some());
+        assert!(store.get(&pages[2]).unwrap().is_none());
+        assert!(store.get(&pages[14]).unwrap().is_some());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_evict_all_pinned() {
+        let cache = get_cache_path("evict_pinned");
+        let mut store = PackStore::open(&cache).unwrap();
+        let train = Entry::get_digest("Train");
+        let tram = Entry::get_digest("Tram");
+        store.pin(train);
+        store.pin(tram);
+        store.put(&train, "Train", b"Train payload").unwrap();
+        store.set_max_size(Some(1));
+
+        // The superseded record is left in place, as there is nothing to evict
+        store
+            .put(&train, "Train", b"Train payload, revised")
+            .unwrap();
+        store.put(&tram, "Tram", b"Tram payload").unwrap();
+        assert!(store.shards.iter().any(|shard| shard.dead > 0));
+        assert_eq!(store.len(), 2);
+        assert!(store.get(&train).unwrap().is_some());
+        assert!(store.get(&tram).unwrap().is_some());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_evict_pinned_over_target() {
+        let cache = get_cache_path("evict_over_target");
+        let mut store = PackStore::open(&cache).unwrap();
+        let mut seed: u32 = 1;
+        let payload: Vec<u8> = (0..1000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 24) as u8
+            })
+            .collect();
+        for page in 0..10 {
+            let digest = Entry::get_digest(&format!("Page {}", page));
+            store.pin(digest);
+            store.put(&digest, "Page", &payload).unwrap();
+        }
+        // The pinned pages hold more than the maximum. The margin is 900 bytes
+        store.set_max_size(Some(9000));
+
+        let train = Entry::get_digest("Train");
+        let tram = Entry::get_digest("Tram");
+        let locomotive = Entry::get_digest("Locomotive");
+        store.put(&train, "Train", b"Train payload").unwrap();
+        assert!(store.get(&train).unwrap().is_none());
+        let pinned = store.size();
+
+        // Pages written within the margin are kept, and the shards are not rewritten
+        store.put(&tram, "Tram", b"Tram payload").unwrap();
+        assert!(store.get(&tram).unwrap().is_some());
+        assert!(store.size() > pinned);
+
+        // Once the pack files grow past the margin, the pages that are not pinned are evicted
+        store.put(&locomotive, "Locomotive", &payload).unwrap();
+        assert!(store.get(&tram).unwrap().is_none());
+        assert!(store.get(&locomotive).unwrap().is_none());
+        assert_eq!(store.size(), pinned);
+        assert_eq!(store.len(), 10);
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let cache = get_cache_path("stats");
+        let mut store = PackStore::open(&cache).unwrap();
+        let train = Entry::get_digest("Train");
+        store.put(&train, "Train", b"Train payload").unwrap();
+        store.get(&train).unwrap();
+        store.get(&train).unwrap();
+        store.get(&Entry::get_digest("Missing")).unwrap();
+        store.pin(train);
+
+        let stats = store.stats();
+        assert_eq!(stats.pages, 1);
+        assert_eq!(stats.bytes, store.size());
+        assert_eq!(stats.pinned, 1);
+        assert_eq!((stats.hits, stats.misses), (2, 1));
+        assert!((stats.hit_ratio - 2.0 / 3.0).abs() < 1e-9);
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_last_use_survives_reopen() {
+        let cache = get_cache_path("last_use");
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            store.put(&train, "Train", b"Train payload").unwrap();
+            store
+                .put(&locomotive, "Locomotive", b"Locomotive payload")
+                .unwrap();
+            store.get(&train).unwrap();
+            store.flush().unwrap();
+        }
+        let store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.clock, 3);
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_awkward_titles() {
+        let cache = get_cache_path("awkward_titles");
+        let titles = vec![
+            "AC/DC".to_string(),
+            "..".to_string(),
+            "../../etc/passwd".to_string(),
+            "Washington, D.C.".to_string(),
+            "C:\\Windows".to_string(),
+            "What?".to_string(),
+            "Llanfair\u{200b}pwllgwyngyll".to_string(),
+            "A".repeat(300),
+            "東京".repeat(50),
+        ];
+        {
+            let mut store = PackStore::open(&cache).unwrap();
+            for title in &titles {
+                let fetch_entry = FetchEntry::from_links(title.clone(), vec![], None);
+                store
+                    .put(&Entry::get_digest(title), title, &fetch_entry.to())
+                    .unwrap();
+            }
+            store.flush().unwrap();
+        }
+
+        let mut store = PackStore::open(&cache).unwrap();
+        assert_eq!(store.len(), titles.len());
+        for title in &titles {
+            let cached = store.get(&Entry::get_digest(title)).unwrap().unwrap();
+            assert_eq!(&FetchEntry::from(&cached).unwrap().title, title);
+        }
+        // Nothing but the pack and index files is written
+        let files: Vec<String> = fs::read_dir(&cache)
+            .unwrap()
+            .map(|file| file.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| !name.starts_with("pack_"))
+            .collect();
+        assert!(files.is_empty(), "Unexpected files {:?}", files);
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_migrate_nested_title() {
+        let cache = get_cache_path("migrate_nested");
+        let digest = Entry::get_digest("AC/DC");
+        let mut directory = cache.clone();
+        for byte in [digest[2], digest[1], digest[0]] {
+            directory.push(format!("{:02x}", byte));
+        }
+        // Earlier versions wrote AC/DC to the sub-directory AC
+        fs::create_dir_all(directory.join("AC")).unwrap();
+        let payload = r#"{"parse": {"title": "AC/DC", "pageid": 48306, "links": []}}"#;
+        fs::write(directory.join("AC").join("DC.json"), payload).unwrap();
+
+        let mut store = PackStore::open(&get_pack_directory(&cache, wiki::default_wiki())).unwrap();
+        let summary = migrate(&cache, &mut store, true).unwrap();
+        assert_eq!(
+            summary,
+            MigrateSummary {
+                migrated: 1,
+                skipped: 0
+            }
+        );
+        let fetch_entry = FetchEntry::from(&store.get(&digest).unwrap().unwrap()).unwrap();
+        assert_eq!(fetch_entry.title, "AC/DC");
+        assert!(!directory.exists());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_migrate() {
+        let cache = get_cache_path("migrate");
+        let digest = Entry::get_digest("St. Louis");
+        let mut directory = cache.clone();
+        for byte in [digest[2], digest[1], digest[0]] {
+            directory.push(format!("{:02x}", byte));
+        }
+        fs::create_dir_all(&directory).unwrap();
+        // Earlier versions lost the tail of any title holding a '.'
+        let payload = r#"{"parse": {"title": "St. Louis", "pageid": 26964, "links": []}}"#;
+        fs::write(directory.join("St.json"), payload).unwrap();
+        fs::write(directory.join("Notes.txt"), "Not a page").unwrap();
+
+        let mut store = PackStore::open(&get_pack_directory(&cache, wiki::default_wiki())).unwrap();
+        let summary = migrate(&cache, &mut store, true).unwrap();
+        assert_eq!(
+            summary,
+            MigrateSummary {
+                migrated: 1,
+                skipped: 1
+            }
+        );
+        let fetch_entry = FetchEntry::from(&store.get(&digest).unwrap().unwrap()).unwrap();
+        assert_eq!(fetch_entry.title, "St. Louis");
+        assert_eq!(fetch_entry.pageid, 26964);
+        assert!(!directory.join("St.json").exists());
+        assert!(directory.join("Notes.txt").exists());
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    #[test]
+    fn test_move_single_wiki_cache() {
+        let cache = get_cache_path("move_single_wiki");
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+        {
+            let mut store = PackStore::open(&cache.join(PACK_DIRECTORY)).unwrap();
+            for title in ["Zug", "Lokomotive"] {
+                store
+                    .put(&md5::compute(title).into(), title, title.as_bytes())
+                    .unwrap();
+            }
+            store.flush().unwrap();
+        }
+
+        let summary = move_single_wiki_cache(&cache, &de).unwrap();
+        assert_eq!(
+            summary,
+            MigrateSummary {
+                migrated: 2,
+                skipped: 0
+            }
+        );
+        assert!(!cache.join(PACK_DIRECTORY).exists());
+        let mut store = PackStore::open(&get_pack_directory(&cache, &de)).unwrap();
+        assert_eq!(
+            store
+                .get(&Entry::get_digest_for(&de, "Zug"))
+                .unwrap()
+                .unwrap(),
+            b"Zug"
+        );
+        assert!(store.get(&md5::compute("Zug").into()).unwrap().is_none());
+
+        // A second call has nothing to move
+        assert_eq!(
+            move_single_wiki_cache(&cache, &de).unwrap(),
+            MigrateSummary::default()
+        );
+        fs::remove_dir_all(&cache).unwrap();
+    }
+
+    // ***********************************************************************************************
+
+    fn get_pack_path(cache: &Path, digest: &Digest) -> PathBuf {
+        cache.join(format!(
+            "pack_{:02x}.pack",
+            digest[0] as usize % SHARD_COUNT
+        ))
+    }
+
+    // Flip the last byte of the pack file that holds the digest
+    fn damage_last_byte(cache: &Path, digest: &Digest) {
+        let pack_path = get_pack_path(cache, digest);
+        let mut bytes = fs::read(&pack_path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xff;
+        fs::write(&pack_path, bytes).unwrap();
+    }
+
+    fn get_cache_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("six_degrees_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+}
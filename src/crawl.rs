@@ -0,0 +1,334 @@
+/* *****************************************************************************************************************
+ *
+ * Crawl
+ * -----
+ *
+ * Pre-warm the page cache by walking outbound links breadth-first from one or more seed titles. Every page is
+ * requested through the fetch service, so pages already in the cache are served locally and pages pulled from
+ * Wikipedia pass through the fetch rate limiter.
+ *
+ * The frontier (pages waiting to be fetched, and pages already visited) is saved to the frontier file every
+ * SAVE_INTERVAL pages, and when the crawl is interrupted with Ctrl-C. If the frontier file exists when a crawl
+ * starts, the crawl resumes from the saved frontier and the seeds are ignored. The frontier file is removed when
+ * the crawl completes. The frontier file is written to a temporary file and renamed over the last, so a crash during a
+ * save leaves the frontier saved before it.
+ *
+ * Pages are crawled on the default wiki.
+ *
+ * Depth follows the same convention as opt::depth: 1 = the seed pages only; 2 = the seed pages, plus all those
+ * directly referenced by those pages, etc.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, fs, io,
+    path::Path,
+};
+
+use tokio::sync::mpsc;
+
+use crate::cache;
+use crate::fetch::{FetchCommand, FetchError, FetchResult};
+use crate::wiki;
+
+static SAVE_INTERVAL: u64 = 50;
+static PROGRESS_INTERVAL: u64 = 10;
+
+// ***********************************************************************************************
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct Frontier {
+    // Title, and the depth at which the title was found
+    pending: VecDeque<(String, u32)>,
+    visited: HashSet<String>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Progress {
+    pub fetched: u64,
+    pub cached: u64,
+    pub errors: u64,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "fetched: {}, cached hits: {}, errors: {}",
+            self.fetched, self.cached, self.errors
+        )
+    }
+}
+
+impl Frontier {
+    fn from_seeds(seeds: &[String]) -> Frontier {
+        let mut frontier = Frontier {
+            pending: VecDeque::with_capacity(seeds.len()),
+            visited: HashSet::with_capacity(seeds.len()),
+        };
+        for seed in seeds {
+            frontier.push(seed.trim().to_string(), 1);
+        }
+        frontier
+    }
+
+    fn push(&mut self, title: String, depth: u32) {
+        if self.visited.insert(title.clone()) {
+            self.pending.push_back((title, depth));
+        }
+    }
+
+    fn load(path: &Path) -> Result<Frontier, io::Error> {
+        let contents = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let contents = serde_json::to_string(self)?;
+        cache::write_atomic(path, contents.as_bytes())
+    }
+}
+
+impl Progress {
+    fn record(&mut self, title: &str, result: &FetchResult) {
+        match result {
+            Ok(fetch_entry) if fetch_entry.from_cache => self.cached += 1,
+            Ok(_) => self.fetched += 1,
+            Err(err) => {
+                warn!(r#"Crawl unable to fetch "{}": {}"#, title, err);
+                self.errors += 1
+            }
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.fetched + self.cached + self.errors
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Run the crawl
+ *
+ *******************************************************************************************************************/
+
+pub async fn crawl(
+    tx_to_fetch: &mpsc::Sender<FetchCommand>,
+    seeds: &[String],
+    depth: u32,
+    frontier_path: &Path,
+) -> Result<Progress, io::Error> {
+    let mut frontier = if frontier_path.exists() {
+        info!(
+            "Resuming crawl from frontier {}",
+            frontier_path.to_string_lossy()
+        );
+        Frontier::load(frontier_path)?
+    } else {
+        Frontier::from_seeds(seeds)
+    };
+
+    let mut progress = Progress::default();
+    let (tx, mut rx) = mpsc::channel(1);
+
+    while let Some((title, title_depth)) = frontier.pending.front().cloned() {
+        let request = FetchCommand::Get {
+            wiki: wiki::default_wiki().id.clone(),
+            title: title.clone(),
+            tx: tx.clone(),
+        };
+        if tx_to_fetch.send(request).await.is_err() {
+            error!("Crawl lost connection to the fetch service");
+            break;
+        }
+
+        let result = tokio::select! {
+            result = rx.recv() => result.unwrap_or(Err(FetchError::Parse(String::from("No response from fetch")))),
+            _ = tokio::signal::ctrl_c() => {
+                info!("Crawl interrupted. {}", progress);
+                // Wait for the page in flight, so the fetch service is not left replying to no one. The page stays
+                // pending, and is served from the cache when the crawl resumes
+                rx.recv().await;
+                frontier.save(frontier_path)?;
+                return Ok(progress);
+            }
+        };
+
+        frontier.pending.pop_front();
+        progress.record(&title, &result);
+        if let Ok(fetch_entry) = result {
+            if title_depth < depth {
+                for outbound in fetch_entry.outbound {
+                    frontier.push(outbound, title_depth + 1);
+                }
+            }
+        }
+
+        if progress.total() % PROGRESS_INTERVAL == 0 {
+            info!(
+                "Crawl progress: {}, pending: {}",
+                progress,
+                frontier.pending.len()
+            );
+        }
+        if progress.total() % SAVE_INTERVAL == 0 {
+            frontier.save(frontier_path)?;
+        }
+    }
+
+    info!("Crawl complete. {}", progress);
+    if frontier_path.exists() {
+        fs::remove_file(frontier_path)?;
+    }
+    Ok(progress)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::FetchEntry;
+    use std::{collections::HashMap, path::PathBuf};
+    use tokio::task::JoinHandle;
+
+    #[tokio::test]
+    async fn test_crawl_depth_1() {
+        let frontier_path = get_frontier_path("depth_1");
+        let (fetch_service, tx_to_fetch) = start_test_fetch_service();
+
+        let seeds = vec!["A".to_string()];
+        let progress = crawl(&tx_to_fetch, &seeds, 1, &frontier_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            progress,
+            Progress {
+                fetched: 1,
+                cached: 0,
+                errors: 0
+            }
+        );
+        assert!(!frontier_path.exists());
+
+        tx_to_fetch.send(FetchCommand::End).await.unwrap();
+        fetch_service.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_crawl_depth_3() {
+        let frontier_path = get_frontier_path("depth_3");
+        let (fetch_service, tx_to_fetch) = start_test_fetch_service();
+
+        // A -> B, C; B -> C, Missing; C -> A, D
+        let seeds = vec!["A".to_string()];
+        let progress = crawl(&tx_to_fetch, &seeds, 3, &frontier_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            progress,
+            Progress {
+                fetched: 3,
+                cached: 1,
+                errors: 1
+            }
+        );
+
+        tx_to_fetch.send(FetchCommand::End).await.unwrap();
+        fetch_service.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_crawl_resume() {
+        let frontier_path = get_frontier_path("resume");
+        let mut frontier = Frontier::from_seeds(&["A".to_string()]);
+        frontier.pending.pop_front();
+        frontier.push("C".to_string(), 2);
+        frontier.save(&frontier_path).unwrap();
+
+        let (fetch_service, tx_to_fetch) = start_test_fetch_service();
+
+        // Seeds are ignored when resuming. C -> A (visited), D
+        let seeds = vec!["B".to_string()];
+        let progress = crawl(&tx_to_fetch, &seeds, 3, &frontier_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            progress,
+            Progress {
+                fetched: 2,
+                cached: 0,
+                errors: 0
+            }
+        );
+        assert!(!frontier_path.exists());
+
+        tx_to_fetch.send(FetchCommand::End).await.unwrap();
+        fetch_service.await.unwrap();
+    }
+
+    #[test]
+    fn test_frontier_ignores_visited() {
+        let mut frontier = Frontier::from_seeds(&["A".to_string(), " A ".to_string()]);
+        frontier.push("B".to_string(), 2);
+        frontier.push("A".to_string(), 2);
+        assert_eq!(frontier.pending.len(), 2);
+    }
+
+    #[test]
+    fn test_frontier_save_replaces() {
+        let frontier_path = get_frontier_path("save");
+        Frontier::from_seeds(&["A".to_string()])
+            .save(&frontier_path)
+            .unwrap();
+        let frontier = Frontier::from_seeds(&["B".to_string(), "C".to_string()]);
+        frontier.save(&frontier_path).unwrap();
+        assert_eq!(Frontier::load(&frontier_path).unwrap(), frontier);
+        assert!(!frontier_path.with_extension("tmp").exists());
+        fs::remove_file(&frontier_path).unwrap();
+    }
+
+    // ***********************************************************************************************
+
+    fn get_frontier_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "six_degrees_crawl_{}_{}.json",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // Mimic the fetch service with a small graph. "B" is served from the cache
+    fn start_test_fetch_service() -> (JoinHandle<()>, mpsc::Sender<FetchCommand>) {
+        let mut graph: HashMap<&str, Vec<&str>> = HashMap::new();
+        graph.insert("A", vec!["B", "C"]);
+        graph.insert("B", vec!["C", "Missing"]);
+        graph.insert("C", vec!["A", "D"]);
+        graph.insert("D", vec![]);
+
+        let (tx_to_fetch, mut rx) = mpsc::channel(8);
+        let fetch_service = tokio::spawn(async move {
+            while let Some(FetchCommand::Get { title, tx, .. }) = rx.recv().await {
+                let result = match graph.get(title.as_str()) {
+                    Some(outbound) => {
+                        let links = outbound.iter().map(|s| (0, s.to_string())).collect();
+                        let mut fetch_entry = FetchEntry::from_links(title.clone(), links, None);
+                        fetch_entry.from_cache = title == "B";
+                        Ok(fetch_entry)
+                    }
+                    None => Err(FetchError::MissingTitle),
+                };
+                tx.send(result).await.unwrap();
+            }
+        });
+        (fetch_service, tx_to_fetch)
+    }
+}
@@ -0,0 +1,607 @@
+/* *****************************************************************************************************************
+ *
+ * Datasets
+ * --------
+ *
+ * A dataset is a named snapshot of a wiki as it was at a point in time, held apart from the live graph, so that a
+ * path search can ask how two pages were connected then. Datasets are built by the dump importers with --dataset, from
+ * a dated dump, or from a history dump with --as_of, which keeps the latest revision of each page made at or before
+ * the timestamp. Each dataset is held in the datasets directory of the cache as
+ *
+ *    <name>.snap    the snapshot
+ *    <name>.json    the dataset info: the wiki, the date of the graph, and the dump it was built from
+ *
+ * The date of a dataset built without --as_of is read from the dump name, such as enwiki-20150101-page.sql.gz, if it
+ * holds one.
+ *
+ * The server loads the datasets named by opt::dataset at startup. Each is held in memory, with outbound links only,
+ * and is searched with ?as_of=<name> in place of the live graph. Pages are not fetched for a dataset.
+ *
+ * Two datasets of the same wiki are compared by diff, from the diff-datasets subcommand or the /diff endpoint, giving
+ *    - the pages added and removed between the two
+ *    - the links gained and lost by each page asked for
+ *    - the change in the length of the shortest path, in links, between each pair of pages asked for
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use regex::Regex;
+
+use crate::entry::{Digest, Entry};
+use crate::fetch;
+use crate::path::{self, Graph, PathOptions};
+use crate::snapshot::SnapshotReader;
+use crate::title;
+use crate::wiki::Wiki;
+
+static DATASETS: OnceLock<HashMap<String, Dataset>> = OnceLock::new();
+
+static DATASETS_DIRECTORY: &str = "datasets";
+
+lazy_static! {
+    static ref NAME: Regex = Regex::new(r"^[A-Za-z0-9_-]{1,64}$").unwrap();
+    static ref DUMP_DATE: Regex = Regex::new(r"-((?:19|20)\d{2})(\d{2})(\d{2})-").unwrap();
+}
+
+// ***********************************************************************************************
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct DatasetInfo {
+    pub name: String,
+    pub wiki: String,
+    // Date of the graph, such as 2015-01-01 or 2015-01-01T00:00:00Z, if known
+    pub as_of: Option<String>,
+    // Name of the dump the dataset was built from
+    pub source: String,
+    pub entries: u64,
+}
+
+pub struct Dataset {
+    pub info: DatasetInfo,
+    entries: HashMap<Digest, Entry>,
+}
+
+#[derive(Debug)]
+pub enum DatasetError {
+    InvalidName(String),
+    NotLoaded(String),
+    IO(String, io::Error),
+    DifferentWikis(String, String),
+}
+
+impl fmt::Display for DatasetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DatasetError::InvalidName(name) => write!(
+                f,
+                "{} is not a dataset name. Use up to 64 letters, digits, - and _",
+                name
+            ),
+            DatasetError::NotLoaded(name) => write!(f, "Dataset {} is not loaded", name),
+            DatasetError::IO(name, err) => write!(f, "Unable to read dataset {}: {}", name, err),
+            DatasetError::DifferentWikis(from, to) => write!(
+                f,
+                "Datasets {} and {} hold different wikis, and cannot be compared",
+                from, to
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatasetError {}
+
+/// Directory holding the datasets
+pub fn get_datasets_directory(cache: &Path) -> PathBuf {
+    cache.join(DATASETS_DIRECTORY)
+}
+
+/// Path of the snapshot of the dataset. The name must be valid
+pub fn get_snapshot_path(directory: &Path, name: &str) -> Result<PathBuf, DatasetError> {
+    check_name(name)?;
+    Ok(directory.join(format!("{}.snap", name)))
+}
+
+fn get_info_path(directory: &Path, name: &str) -> Result<PathBuf, DatasetError> {
+    check_name(name)?;
+    Ok(directory.join(format!("{}.json", name)))
+}
+
+fn check_name(name: &str) -> Result<(), DatasetError> {
+    match NAME.is_match(name) {
+        true => Ok(()),
+        false => Err(DatasetError::InvalidName(name.to_string())),
+    }
+}
+
+/// Seconds since the Unix epoch of an --as_of timestamp, such as 2015-01-01T12:00:00Z. A date alone is the start of
+/// the day
+pub fn read_as_of(as_of: &str) -> Option<u64> {
+    let as_of = as_of.trim();
+    match as_of.contains('T') {
+        true => fetch::read_timestamp(as_of),
+        false => fetch::read_timestamp(&format!("{}T00:00:00Z", as_of)),
+    }
+}
+
+/// The date in the name of a dump, such as 2015-01-01 for enwiki-20150101-pages-articles.xml.bz2
+pub fn dump_date(dump: &Path) -> Option<String> {
+    let name = dump.file_name()?.to_string_lossy();
+    let date = DUMP_DATE.captures(&name)?;
+    Some(format!("{}-{}-{}", &date[1], &date[2], &date[3]))
+}
+
+impl DatasetInfo {
+    /// Save the info alongside the snapshot of the dataset
+    pub fn save(&self, directory: &Path) -> Result<(), DatasetError> {
+        let path = get_info_path(directory, &self.name)?;
+        let json = serde_json::to_string_pretty(self).unwrap();
+        fs::write(path, json).map_err(|err| DatasetError::IO(self.name.clone(), err))
+    }
+
+    pub fn read(directory: &Path, name: &str) -> Result<DatasetInfo, DatasetError> {
+        let path = get_info_path(directory, name)?;
+        let io_error = |err| DatasetError::IO(name.to_string(), err);
+        let json = fs::read_to_string(path).map_err(io_error)?;
+        serde_json::from_str(&json)
+            .map_err(|err| io_error(io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+}
+
+impl fmt::Display for DatasetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (wiki {}, as of {}, {} pages from {})",
+            self.name,
+            self.wiki,
+            self.as_of.as_deref().unwrap_or("an unknown date"),
+            self.entries,
+            self.source
+        )
+    }
+}
+
+impl Dataset {
+    /// Read the dataset from the datasets directory
+    pub fn load(directory: &Path, name: &str) -> Result<Dataset, DatasetError> {
+        let info = DatasetInfo::read(directory, name)?;
+        let io_error = |err| DatasetError::IO(name.to_string(), err);
+        let mut entries = HashMap::new();
+        for entry in SnapshotReader::open(&get_snapshot_path(directory, name)?).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            entries.insert(entry.digest(), entry);
+        }
+        Ok(Dataset { info, entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Pages of a dataset, for path search
+pub struct DatasetGraph<'a>(pub &'a Dataset);
+
+impl Graph for DatasetGraph<'_> {
+    async fn get(&mut self, digest: Digest) -> Option<Entry> {
+        self.0.entries.get(&digest).cloned()
+    }
+
+    fn title(&self, digest: Digest) -> Option<&str> {
+        self.0.entries.get(&digest).map(|entry| entry.get_title())
+    }
+
+    // A dataset holds no back-links, so the page linked to is read in place, rather than copied by get
+    async fn links_back(&mut self, from: Digest, to: Digest) -> bool {
+        self.0
+            .entries
+            .get(&to)
+            .is_some_and(|to| to.get_outbound().contains(&from))
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Diff
+ *
+ *******************************************************************************************************************/
+
+/// The pages and pairs of pages compared by a diff
+#[derive(Debug, Default, PartialEq)]
+pub struct DiffRequest {
+    pub pages: Vec<String>,
+    pub pairs: Vec<(String, String)>,
+    // Most pages listed as added, and as removed
+    pub limit: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DatasetDiff {
+    pub from: String,
+    pub to: String,
+    pub pages_added: usize,
+    pub pages_removed: usize,
+    // Titles of the pages added and removed, in order, up to the limit of the request
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub pages: Vec<PageDiff>,
+    pub pairs: Vec<PairDiff>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PageDiff {
+    pub title: String,
+    // The page is held by each dataset
+    pub in_from: bool,
+    pub in_to: bool,
+    // Titles of the pages linked to in `to` and not in `from`, and in `from` and not in `to`
+    pub links_gained: Vec<String>,
+    pub links_lost: Vec<String>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct PairDiff {
+    pub from: String,
+    pub to: String,
+    // Links in the shortest path in each dataset, if there is a path
+    pub from_length: Option<usize>,
+    pub to_length: Option<usize>,
+    // to_length less from_length, if there is a path in both
+    pub change: Option<i64>,
+}
+
+/// A pair of titles separated by >, such as Train>Tram. > cannot appear in a title
+pub fn read_pair(pair: &str) -> Option<(String, String)> {
+    let (from, to) = pair.split_once('>')?;
+    let (from, to) = (from.trim(), to.trim());
+    match from.is_empty() || to.is_empty() {
+        true => None,
+        false => Some((from.to_string(), to.to_string())),
+    }
+}
+
+/// Compare two datasets of the wiki
+pub async fn diff(
+    from: &Dataset,
+    to: &Dataset,
+    wiki: &Wiki,
+    request: &DiffRequest,
+) -> Result<DatasetDiff, DatasetError> {
+    if !from.info.wiki.eq_ignore_ascii_case(&to.info.wiki) {
+        return Err(DatasetError::DifferentWikis(
+            from.info.name.clone(),
+            to.info.name.clone(),
+        ));
+    }
+    let titles = |digests: &mut dyn Iterator<Item = &Digest>| {
+        let mut titles: Vec<String> = digests.map(|digest| title_of(*digest, from, to)).collect();
+        titles.sort();
+        titles
+    };
+    let mut added = titles(&mut only_in(to, from));
+    let mut removed = titles(&mut only_in(from, to));
+    let (pages_added, pages_removed) = (added.len(), removed.len());
+    added.truncate(request.limit);
+    removed.truncate(request.limit);
+
+    let pages = request
+        .pages
+        .iter()
+        .map(|page_title| {
+            let page_title = title::normalise_for(wiki, page_title);
+            let digest = Entry::get_digest_for(wiki, &page_title);
+            let (from_entry, to_entry) = (from.entries.get(&digest), to.entries.get(&digest));
+            let outbound = |entry: Option<&Entry>| -> HashSet<Digest> {
+                entry
+                    .map(|entry| entry.get_outbound().iter().copied().collect())
+                    .unwrap_or_default()
+            };
+            let (from_links, to_links) = (outbound(from_entry), outbound(to_entry));
+            PageDiff {
+                title: page_title,
+                in_from: from_entry.is_some(),
+                in_to: to_entry.is_some(),
+                links_gained: titles(&mut to_links.difference(&from_links)),
+                links_lost: titles(&mut from_links.difference(&to_links)),
+            }
+        })
+        .collect();
+
+    let mut pairs = Vec::new();
+    for (from_title, to_title) in &request.pairs {
+        let first = path::Page::new(wiki, &title::normalise_for(wiki, from_title));
+        let last = path::Page::new(wiki, &title::normalise_for(wiki, to_title));
+        let from_length = shortest(from, &first, &last).await;
+        let to_length = shortest(to, &first, &last).await;
+        pairs.push(PairDiff {
+            from: first.title,
+            to: last.title,
+            from_length,
+            to_length,
+            change: match (from_length, to_length) {
+                (Some(from_length), Some(to_length)) => Some(to_length as i64 - from_length as i64),
+                _ => None,
+            },
+        });
+    }
+
+    Ok(DatasetDiff {
+        from: from.info.name.clone(),
+        to: to.info.name.clone(),
+        pages_added,
+        pages_removed,
+        added,
+        removed,
+        pages,
+        pairs,
+    })
+}
+
+// Digests of the pages held by one dataset and not the other
+fn only_in<'a>(dataset: &'a Dataset, other: &'a Dataset) -> impl Iterator<Item = &'a Digest> {
+    dataset
+        .entries
+        .keys()
+        .filter(|digest| !other.entries.contains_key(*digest))
+}
+
+// Title of a page held by either dataset, or its digest in hex
+fn title_of(digest: Digest, from: &Dataset, to: &Dataset) -> String {
+    match to
+        .entries
+        .get(&digest)
+        .or_else(|| from.entries.get(&digest))
+    {
+        Some(entry) => entry.get_title().to_string(),
+        None => digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+// Links in the shortest path between the pages, if there is one
+async fn shortest(dataset: &Dataset, first: &path::Page, last: &path::Page) -> Option<usize> {
+    let search = path::search(
+        &mut DatasetGraph(dataset),
+        first,
+        last,
+        &PathOptions::default(),
+    )
+    .await;
+    search.path.map(|path| path.links)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Registry
+ *
+ *******************************************************************************************************************/
+
+/// Set the datasets served. Called once, at startup
+pub fn init(datasets: Vec<Dataset>) {
+    let datasets = datasets
+        .into_iter()
+        .map(|dataset| (dataset.info.name.clone(), dataset))
+        .collect();
+    if DATASETS.set(datasets).is_err() {
+        warn!("The datasets have already been set");
+    }
+}
+
+/// A dataset served. None of the datasets are served if init has not been called
+pub fn get(name: &str) -> Result<&'static Dataset, DatasetError> {
+    DATASETS
+        .get_or_init(HashMap::new)
+        .get(name)
+        .ok_or_else(|| DatasetError::NotLoaded(name.to_string()))
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::SnapshotWriter;
+
+    #[test]
+    fn test_dump_date() {
+        let date = |name: &str| dump_date(Path::new(name));
+        assert_eq!(
+            date("/dumps/enwiki-20150101-pages-articles.xml.bz2"),
+            Some("2015-01-01".to_string())
+        );
+        assert_eq!(
+            date("enwiki-20150101-page.sql.gz"),
+            Some("2015-01-01".to_string())
+        );
+        assert_eq!(date("enwiki-latest-page.sql.gz"), None);
+    }
+
+    #[test]
+    fn test_read_as_of() {
+        assert_eq!(read_as_of("2015-01-01"), Some(1420070400));
+        assert_eq!(read_as_of("2015-01-01T12:00:00Z"), Some(1420113600));
+        assert_eq!(read_as_of("2015"), None);
+    }
+
+    #[test]
+    fn test_names() {
+        let directory = Path::new("datasets");
+        assert_eq!(
+            get_snapshot_path(directory, "enwiki-2015").unwrap(),
+            directory.join("enwiki-2015.snap")
+        );
+        for name in ["", "../live", "en wiki", "2015.01"] {
+            assert!(get_snapshot_path(directory, name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_read_pair() {
+        assert_eq!(
+            read_pair(" Train > Rail transport"),
+            Some(("Train".to_string(), "Rail transport".to_string()))
+        );
+        assert_eq!(read_pair("Train"), None);
+        assert_eq!(read_pair("Train>"), None);
+    }
+
+    // A dataset of pages, each with the titles of the pages it links to
+    fn dataset(name: &str, pages: &[(&str, &[&str])]) -> Dataset {
+        let entries: HashMap<Digest, Entry> = pages
+            .iter()
+            .map(|(page_title, links)| {
+                let outbound = links.iter().map(|link| Entry::get_digest(link)).collect();
+                let entry = Entry::new(page_title.to_string(), outbound);
+                (entry.digest(), entry)
+            })
+            .collect();
+        Dataset {
+            info: DatasetInfo {
+                name: name.to_string(),
+                wiki: "en".to_string(),
+                as_of: None,
+                source: String::new(),
+                entries: entries.len() as u64,
+            },
+            entries,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_diff() {
+        let wiki = Wiki::new("en", "https://en.wikipedia.org/", 10);
+        let from = dataset(
+            "en-2010",
+            &[
+                ("Train", &["Locomotive", "Tram"]),
+                ("Locomotive", &["Tram"]),
+                ("Tram", &[]),
+                ("Horsecar", &["Tram"]),
+            ],
+        );
+        let to = dataset(
+            "en-2015",
+            &[
+                ("Train", &["Tram", "Monorail"]),
+                ("Locomotive", &["Train"]),
+                ("Tram", &["Train"]),
+                ("Monorail", &["Locomotive"]),
+            ],
+        );
+        let request = DiffRequest {
+            pages: vec!["train".to_string(), "Horsecar".to_string()],
+            pairs: vec![
+                ("Locomotive".to_string(), "Tram".to_string()),
+                ("Tram".to_string(), "Monorail".to_string()),
+            ],
+            limit: 10,
+        };
+        let diff = diff(&from, &to, &wiki, &request).await.unwrap();
+        let strings = |titles: &[&str]| titles.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(diff.pages_added, 1);
+        assert_eq!(diff.added, strings(&["Monorail"]));
+        assert_eq!(diff.removed, strings(&["Horsecar"]));
+        assert_eq!(
+            diff.pages,
+            vec![
+                PageDiff {
+                    title: "Train".to_string(),
+                    in_from: true,
+                    in_to: true,
+                    links_gained: strings(&["Monorail"]),
+                    links_lost: strings(&["Locomotive"]),
+                },
+                PageDiff {
+                    title: "Horsecar".to_string(),
+                    in_from: true,
+                    in_to: false,
+                    links_gained: vec![],
+                    links_lost: strings(&["Tram"]),
+                },
+            ]
+        );
+        assert_eq!(
+            diff.pairs,
+            vec![
+                PairDiff {
+                    from: "Locomotive".to_string(),
+                    to: "Tram".to_string(),
+                    from_length: Some(1),
+                    to_length: Some(2),
+                    change: Some(1),
+                },
+                PairDiff {
+                    from: "Tram".to_string(),
+                    to: "Monorail".to_string(),
+                    from_length: None,
+                    to_length: Some(2),
+                    change: None,
+                },
+            ]
+        );
+
+        let limited = DiffRequest::default();
+        let diff = super::diff(&from, &to, &wiki, &limited).await.unwrap();
+        assert_eq!((diff.pages_removed, diff.removed.len()), (1, 0));
+
+        let mut other = dataset("de-2015", &[]);
+        other.info.wiki = "de".to_string();
+        assert!(matches!(
+            super::diff(&from, &other, &wiki, &limited).await,
+            Err(DatasetError::DifferentWikis(..))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_save_load() {
+        let directory =
+            std::env::temp_dir().join(format!("six_degrees_datasets_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let train = Entry::get_digest("Train");
+        let mut writer =
+            SnapshotWriter::create(&get_snapshot_path(&directory, "en-2015").unwrap()).unwrap();
+        writer
+            .write(&Entry::new("Rail transport".to_string(), vec![train]))
+            .unwrap();
+        writer
+            .write(&Entry::new("Train".to_string(), vec![]))
+            .unwrap();
+        let info = DatasetInfo {
+            name: "en-2015".to_string(),
+            wiki: "en".to_string(),
+            as_of: Some("2015-01-01".to_string()),
+            source: "enwiki-20150101-pages-articles.xml.bz2".to_string(),
+            entries: writer.finish().unwrap(),
+        };
+        info.save(&directory).unwrap();
+
+        let dataset = Dataset::load(&directory, "en-2015").unwrap();
+        assert_eq!(dataset.info, info);
+        assert_eq!(dataset.len(), 2);
+        let dataset: &'static Dataset = Box::leak(Box::new(dataset));
+        let mut graph = DatasetGraph(dataset);
+        let entry = graph
+            .get(Entry::get_digest("Rail transport"))
+            .await
+            .unwrap();
+        assert_eq!(entry.get_outbound(), &[train]);
+        assert!(graph.get(Entry::get_digest("Tram")).await.is_none());
+        assert!(!graph.links_back(entry.digest(), train).await);
+        assert!(graph.links_back(train, entry.digest()).await);
+
+        assert!(matches!(
+            Dataset::load(&directory, "en-2016"),
+            Err(DatasetError::IO(..))
+        ));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
+use crate::title;
+use crate::wiki::{self, Wiki};
+
 pub(crate) type Digest = [u8; 16];
 
-#[derive(Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct Entry {
     digest: Digest,
     outbound_count: u32,
@@ -8,18 +11,156 @@ pub struct Entry {
     outbound: Vec<Digest>,
     inbound: Vec<Digest>,
     title: String,
+    // Interlanguage links to the same topic on other wikis served, as (wiki identifier, digest)
+    langlinks: Vec<(String, Digest)>,
+    // Links into namespaces other than articles held by the link policy, as (namespace, digest)
+    namespace_links: Vec<(i32, Digest)>,
+    // Categories that hold the page. For a category page, the parent categories
+    categories: Vec<Digest>,
+    // PageRank relative to the mean, from the last computation by pagerank::compute. Not saved with the entry
+    #[serde(skip)]
+    pagerank: Option<f64>,
 }
 
 impl Entry {
-    pub fn from(source: &[u8]) {}
+    /// Create an entry for a page on the default wiki
+    pub fn new(title: String, outbound: Vec<Digest>) -> Entry {
+        Entry::new_for(wiki::default_wiki(), title, outbound)
+    }
+
+    pub fn new_for(wiki: &Wiki, title: String, outbound: Vec<Digest>) -> Entry {
+        Entry {
+            digest: Entry::get_digest_for(wiki, &title),
+            outbound_count: outbound.len() as u32,
+            inbound_count: 0,
+            outbound,
+            inbound: Vec::new(),
+            title,
+            langlinks: Vec::new(),
+            namespace_links: Vec::new(),
+            categories: Vec::new(),
+            pagerank: None,
+        }
+    }
+
+    /// Create a placeholder for a page that is referenced by another page, but has not yet been loaded
+    pub fn new_stub(digest: Digest) -> Entry {
+        Entry {
+            digest,
+            outbound_count: 0,
+            inbound_count: 0,
+            outbound: Vec::new(),
+            inbound: Vec::new(),
+            title: String::new(),
+            langlinks: Vec::new(),
+            namespace_links: Vec::new(),
+            categories: Vec::new(),
+            pagerank: None,
+        }
+    }
+
+    pub fn from(source: &[u8]) -> Result<Entry, bincode::Error> {
+        bincode::deserialize(source)
+    }
 
     pub fn to(&self) -> Vec<u8> {
-        let to = Vec::new();
-        to
+        bincode::serialize(self).expect("Internal error serializing Entry")
     }
 
+    /// Digest of a title on the default wiki
     pub fn get_digest(title: &str) -> Digest {
-        md5::compute(title).into()
+        Entry::get_digest_for(wiki::default_wiki(), title)
+    }
+
+    /// Digest of the wiki identifier and the normalised title, so every spelling of a title has the same digest, and
+    /// the same title on two wikis has different digests
+    pub fn get_digest_for(wiki: &Wiki, title: &str) -> Digest {
+        md5::compute(format!("{}:{}", wiki.id, title::normalise_for(wiki, title))).into()
+    }
+
+    pub fn digest(&self) -> Digest {
+        self.digest
+    }
+
+    pub fn get_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn get_outbound(&self) -> &[Digest] {
+        &self.outbound
+    }
+
+    pub fn get_inbound(&self) -> &[Digest] {
+        &self.inbound
+    }
+
+    pub fn get_outbound_count(&self) -> u32 {
+        self.outbound_count
+    }
+
+    pub fn get_inbound_count(&self) -> u32 {
+        self.inbound_count
+    }
+
+    pub fn get_langlinks(&self) -> &[(String, Digest)] {
+        &self.langlinks
+    }
+
+    pub fn set_langlinks(&mut self, langlinks: Vec<(String, Digest)>) {
+        self.langlinks = langlinks;
+    }
+
+    pub fn get_namespace_links(&self) -> &[(i32, Digest)] {
+        &self.namespace_links
+    }
+
+    pub fn set_namespace_links(&mut self, namespace_links: Vec<(i32, Digest)>) {
+        self.namespace_links = namespace_links;
+    }
+
+    pub fn get_categories(&self) -> &[Digest] {
+        &self.categories
+    }
+
+    pub fn set_categories(&mut self, categories: Vec<Digest>) {
+        self.categories = categories;
+    }
+
+    pub fn get_pagerank(&self) -> Option<f64> {
+        self.pagerank
+    }
+
+    pub fn set_pagerank(&mut self, pagerank: f64) {
+        self.pagerank = Some(pagerank);
+    }
+
+    /// True if the entry was created from a back-link, and the page itself has not been loaded
+    pub fn is_stub(&self) -> bool {
+        self.title.is_empty()
+    }
+
+    /// Record a link into this page. Back-links are not de-duplicated, so callers send one back-link for each new
+    /// link
+    pub fn add_inbound(&mut self, from: Digest) {
+        self.inbound.push(from);
+        self.inbound_count = self.inbound.len() as u32;
+    }
+
+    /// Remove the links from a page into this page. Returns true if any were held
+    pub fn remove_inbound(&mut self, from: Digest) -> bool {
+        let held = self.inbound.len();
+        self.inbound.retain(|inbound| *inbound != from);
+        self.inbound_count = self.inbound.len() as u32;
+        self.inbound.len() < held
+    }
+
+    /// Replace the page content with a newly loaded version, retaining the back-links already collected, and the
+    /// PageRank until it is next computed
+    pub fn replace_with(&mut self, mut entry: Entry) {
+        entry.inbound.append(&mut self.inbound);
+        entry.inbound_count = entry.inbound.len() as u32;
+        entry.pagerank = self.pagerank;
+        *self = entry;
     }
 }
 
@@ -52,3 +193,73 @@ impl Entry {
  *     do a boolean AND between the worker_id and the (foundation.worker_count - 1)
  *     the resulting value is the index into the Vector of TxCommands to which a request should be sent
  */
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_from() {
+        let mut entry = Entry::new(
+            "Rail transport".to_string(),
+            vec![Entry::get_digest("Train"), Entry::get_digest("Railway")],
+        );
+        entry.add_inbound(Entry::get_digest("Locomotive"));
+        entry.set_langlinks(vec![(
+            "de".to_string(),
+            Entry::get_digest("Schienenverkehr"),
+        )]);
+        entry.set_namespace_links(vec![(14, Entry::get_digest("Category:Rail transport"))]);
+        entry.set_categories(vec![Entry::get_digest("Category:Transport")]);
+
+        let restored = Entry::from(&entry.to()).unwrap();
+        assert_eq!(restored, entry);
+        assert_eq!(restored.outbound_count, 2);
+        assert_eq!(restored.inbound_count, 1);
+    }
+
+    #[test]
+    fn test_digest_normalises_title() {
+        let digest = Entry::get_digest("Rail transport");
+        assert_eq!(Entry::get_digest("rail transport"), digest);
+        assert_eq!(Entry::get_digest("Rail_transport"), digest);
+        assert_eq!(Entry::get_digest("Rail transport "), digest);
+        assert_ne!(Entry::get_digest("Rail transports"), digest);
+
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+        assert_ne!(Entry::get_digest_for(&de, "Rail transport"), digest);
+        assert_eq!(
+            Entry::get_digest_for(&de, "rail_transport"),
+            Entry::get_digest_for(&de, "Rail transport")
+        );
+    }
+
+    #[test]
+    fn test_from_fail() {
+        assert!(Entry::from(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_replace_keeps_inbound() {
+        let digest = Entry::get_digest("Train");
+        let mut entry = Entry::new_stub(digest);
+        entry.add_inbound(Entry::get_digest("Rail transport"));
+        assert!(entry.is_stub());
+
+        entry.replace_with(Entry::new(
+            "Train".to_string(),
+            vec![Entry::get_digest("Locomotive")],
+        ));
+        assert!(!entry.is_stub());
+        assert_eq!(entry.digest(), digest);
+        assert_eq!(entry.get_outbound().len(), 1);
+        assert_eq!(entry.get_inbound(), &[Entry::get_digest("Rail transport")]);
+        assert_eq!(entry.inbound_count, 1);
+    }
+}
@@ -46,6 +46,29 @@
  * Pages that parse successfully: Calculated from page last update time (Min 7 days)
  * Pages that are not found:      7 days
  *
+ * A page that is not found is recorded in the cache, so the title is not requested from the wiki again until the
+ * record is older than NOT_FOUND_AGE. Links to pages that do not exist (red links) are dropped when the page is
+ * parsed, as the API marks the links to pages that exist.
+ *
+ *************************************************************************************************
+ *
+ * Link context
+ * ------------
+ *
+ * The sentence around each outbound link is only needed for the links of a path returned by the API, so it is pulled
+ * on request (FetchCommand::Context), from the wikitext of the page, and saved to a separate store keyed by the digest
+ * of the page. Contexts are not refreshed when the page changes.
+ *
+ *************************************************************************************************
+ *
+ * Refresh
+ * -------
+ *
+ * Each cached page records the revision its links were read from (revid), and, for pages pulled again because they
+ * changed, when the change was made (touched). FetchCommand::RecentChanges reads the changes made to a wiki since a
+ * time from list=recentchanges, oldest first, and FetchCommand::Refresh pulls a page again if the cached copy is older
+ * than a change. Pages that are not cached are not pulled. See refresh.rs
+ *
  *************************************************************************************************/
 
 /*************************************************************************************************
@@ -59,7 +82,9 @@
  *          Lag error loop until request == 5
  *             request == 5 -> return FetchError::Lag(String)
  *    Fetch successful
- *    Save page to cache - Save in folder hierarchy based on 16 LSB: 256 dirs, each holding 256 dirs
+ *    Requests name the wiki, and are sent to the domain name of the wiki through the rate limiter of the wiki
+ *    Save the parsed FetchEntry to cache - Appended to the pack-file store of the wiki, see cache.rs
+ *       With opt::cache_raw, the raw payload is also saved, to a separate store
  *    Parse page
  *       Page not found error - return FetchError::PageNotFound(String)
  *       Parse error -> Return FetchError::Parse(String)
@@ -68,16 +93,24 @@
  *
  *************************************************************************************************/
 
+use crate::cache::{self, CacheStats, PackStore};
 use crate::entry;
 use crate::foundation;
+use crate::policy::LinkPolicy;
+use crate::title;
+use crate::wiki::{self, Wiki};
+use crate::wikitext;
 use reqwest::{blocking, header::HeaderValue, StatusCode, Url};
-use tokio::{sync::mpsc, task::JoinHandle};
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use std::{
-    fmt,
-    fs::{self, create_dir_all},
-    io,
-    path::PathBuf,
+    collections::HashMap,
+    fmt, io,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::opt;
@@ -87,7 +120,7 @@ lazy_static! {
         let mut v = Vec::with_capacity(3);
         v.push(("action", "parse"));
         v.push(("format", "json"));
-        v.push(("prop", "links"));
+        v.push(("prop", "links|langlinks|categories"));
         v.push(("maxlag", MAXLAG));
         v
     };
@@ -100,11 +133,6 @@ lazy_static! {
             .build()
             .expect("Internal error creating fetch::client")
     };
-    static ref URL: String = {
-        let mut url = opt::OPT.get_domain_name().to_string();
-        url.push_str(PATH);
-        url
-    };
     static ref MAXLAG_VALUE: u64 = MAXLAG.parse().unwrap();
 }
 
@@ -112,6 +140,36 @@ static MAXLAG: &'static str = "5";
 static PATH: &'static str = "/w/api.php";
 static PARSE_ERROR: &'static str = "Unknown wikipedia payload";
 
+// Disambiguation and redirect rules. See doc/assumptions.md
+static DISAMBIGUATION: &str = "(disambiguation)";
+static DISAMBIGUATION_PERCENT: usize = 75;
+static DISAMBIGUATION_MIN_LINKS: usize = 4;
+static CATEGORIZING_REDIRECTS: &str = "categorizing redirects";
+
+// First byte of a FetchEntry held in the cache. Entries in each earlier format lack
+//    '{'               the binary form. Caches written before it hold the JSON payload
+//    CACHE_FORMAT_V1   interlanguage links
+//    CACHE_FORMAT_V2   links into other namespaces
+//    CACHE_FORMAT_V3   categories
+//    CACHE_FORMAT_V4   revisions
+static CACHE_FORMAT: u8 = 5;
+static CACHE_FORMAT_V1: u8 = 1;
+static CACHE_FORMAT_V2: u8 = 2;
+static CACHE_FORMAT_V3: u8 = 3;
+static CACHE_FORMAT_V4: u8 = 4;
+// First byte of the record of a page that was not found, followed by when it was looked for (u64, little-endian)
+static CACHE_MISSING: u8 = 0;
+
+// First byte of the link contexts of a page held in the context store, followed by the bincode contexts
+static CONTEXT_FORMAT: u8 = 1;
+
+// Changes read from list=recentchanges in one request, and requests made for one poll
+static RECENT_CHANGES_LIMIT: &str = "500";
+static RECENT_CHANGES_REQUESTS: usize = 10;
+
+// Seconds before a page that was not found is requested again
+static NOT_FOUND_AGE: u64 = 7 * 24 * 60 * 60;
+
 // ***********************************************************************************************
 
 // ***********************************************************************************************
@@ -127,12 +185,36 @@ pub struct Link {
     pub title: String,
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LangLink {
+    pub lang: String,
+    #[serde(rename = "*")]
+    pub title: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Category {
+    // Present, and empty, for hidden maintenance categories
+    pub hidden: Option<String>,
+    // Name of the category, without the namespace
+    #[serde(rename = "*")]
+    pub name: String,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Links {
     pub title: String,
     pub pageid: u32,
+    #[serde(default)]
+    pub revid: u64,
     pub links: Vec<Link>,
+    #[serde(default)]
+    pub langlinks: Vec<LangLink>,
+    #[serde(default)]
+    pub categories: Vec<Category>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -141,6 +223,61 @@ struct Page {
     parse: Links,
 }
 
+/// A change to a page, as listed by list=recentchanges
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RecentChange {
+    // Set for changes read from a file that holds changes to several wikis
+    #[serde(default)]
+    pub wiki: Option<String>,
+    pub title: String,
+    pub revid: u64,
+    // When the change was made, such as 2024-05-01T12:30:00Z
+    pub timestamp: String,
+}
+
+impl RecentChange {
+    /// When the change was made, in seconds since the Unix epoch
+    pub fn changed(&self) -> Option<u64> {
+        read_timestamp(&self.timestamp)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentChangesList {
+    recentchanges: Vec<RecentChange>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentChangesContinue {
+    rccontinue: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentChangesPage {
+    query: RecentChangesList,
+    #[serde(rename = "continue")]
+    more: Option<RecentChangesContinue>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Wikitext {
+    #[serde(rename = "*")]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Source {
+    title: String,
+    wikitext: Wikitext,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SourcePage {
+    parse: Source,
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)]
@@ -188,22 +325,299 @@ struct MissingTitleError {
 pub enum FetchCommand {
     End,
     Get {
+        wiki: String,
         title: String,
         tx: mpsc::Sender<FetchResult>,
     },
+    // Protect pages that are hubs in the in-memory graph from eviction
+    Pin(Vec<entry::Digest>),
+    // Report the size and hit ratio of the page cache
+    Stats {
+        tx: mpsc::Sender<CacheStats>,
+    },
+    // The sentence around the first link to each page from the page, by digest of the linked page
+    Context {
+        wiki: String,
+        title: String,
+        tx: mpsc::Sender<ContextResult>,
+    },
+    // Changes made to the wiki since a time, in seconds since the Unix epoch, oldest first
+    RecentChanges {
+        wiki: String,
+        since: u64,
+        tx: mpsc::Sender<RecentChangesResult>,
+    },
+    // Pull a page again if the cached copy is older than the change. None if the page is not cached, or is current
+    Refresh {
+        wiki: String,
+        change: RecentChange,
+        tx: mpsc::Sender<Option<FetchResult>>,
+    },
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct FetchEntry {
     pub digest: entry::Digest,
     pub title: String,
+    // Wikipedia page id. 0 if the page was not pulled from the API
+    pub pageid: u32,
     pub outbound: Vec<String>,
+    // Target of the redirect if the page is a redirect. The target is the only outbound link
+    pub redirect: Option<String>,
+    // Disambiguation pages hold no outbound links
+    pub disambiguation: bool,
+    // When the links were read, in seconds since the Unix epoch
+    pub fetched: u64,
+    // Interlanguage links, as (language, title)
+    pub langlinks: Vec<(String, String)>,
+    // Links into namespaces other than articles, as (namespace, title). The link policy decides which are held
+    pub namespace_links: Vec<(i32, String)>,
+    // Titles of the categories that hold the page, other than hidden categories. For a category page, these are the
+    // parent categories. Pages parsed from an API payload hold the names of the categories until the wiki is known
+    pub categories: Vec<String>,
+    // Revision the links were read from. 0 if not known
+    pub revid: u64,
+    // When the revision was made, in seconds since the Unix epoch, if the page was pulled again because it changed.
+    // 0 otherwise
+    pub touched: u64,
+    // True if the page was read from the local cache rather than pulled from Wikipedia
+    #[serde(skip)]
+    pub from_cache: bool,
 }
 
 impl FetchEntry {
     fn log(&self, title: &str) {
         info!("Retrieved page {}", title);
     }
+
+    /// Build an entry from the (namespace, title) links on a page, applying the disambiguation and redirect rules
+    /// in doc/assumptions.md. Used for pages pulled from Wikipedia and for pages read from dumps. If `redirect` is
+    /// None, the page is checked for the links that mark a redirect
+    pub fn from_links(
+        title: String,
+        links: Vec<(i32, String)>,
+        redirect: Option<String>,
+    ) -> FetchEntry {
+        let redirect = redirect.or_else(|| find_redirect_in(&links));
+        let (outbound, namespace_links): (Vec<_>, Vec<_>) =
+            links.into_iter().partition(|(ns, _)| *ns == 0);
+        let outbound: Vec<String> = outbound.into_iter().map(|(_, title)| title).collect();
+
+        let disambiguation = is_disambiguation(&title, &outbound);
+        let (outbound, namespace_links) = match (&redirect, disambiguation) {
+            (Some(target), _) => (vec![target.clone()], Vec::new()),
+            (None, true) => (Vec::new(), Vec::new()),
+            (None, false) => (
+                outbound
+                    .into_iter()
+                    .filter(|link| !link.contains(DISAMBIGUATION))
+                    .collect(),
+                namespace_links,
+            ),
+        };
+
+        FetchEntry {
+            digest: entry::Entry::get_digest(&title),
+            title,
+            pageid: 0,
+            outbound,
+            redirect,
+            disambiguation,
+            fetched: now(),
+            langlinks: Vec::new(),
+            namespace_links,
+            categories: Vec::new(),
+            revid: 0,
+            touched: 0,
+            from_cache: false,
+        }
+    }
+
+    /// Build the Entry for the page on the wiki. Interlanguage links are kept if the target wiki is served, and links
+    /// into other namespaces if the policy holds them. Categories are always kept
+    pub fn to_entry(&self, wiki: &Wiki, wikis: &[Wiki], policy: &LinkPolicy) -> entry::Entry {
+        let outbound = self
+            .outbound
+            .iter()
+            .map(|title| entry::Entry::get_digest_for(wiki, title))
+            .collect();
+        let mut entry = entry::Entry::new_for(wiki, self.title.clone(), outbound);
+        let langlinks = self
+            .langlinks
+            .iter()
+            .filter_map(|(lang, title)| {
+                let target = wiki::language_edition(wikis, wiki, lang)?;
+                Some((
+                    target.id.clone(),
+                    entry::Entry::get_digest_for(target, title),
+                ))
+            })
+            .collect();
+        entry.set_langlinks(langlinks);
+        let namespace_links = self
+            .namespace_links
+            .iter()
+            .filter(|(ns, _)| policy.holds(*ns))
+            .map(|(ns, title)| (*ns, entry::Entry::get_digest_for(wiki, title)))
+            .collect();
+        entry.set_namespace_links(namespace_links);
+        let categories = self
+            .categories
+            .iter()
+            .map(|name| entry::Entry::get_digest_for(wiki, &title::category_for(wiki, name)))
+            .collect();
+        entry.set_categories(categories);
+        entry
+    }
+
+    /// Restore an entry from the cache. Entries cached by earlier versions are held as the API payload, and are parsed
+    pub fn from(source: &[u8]) -> FetchResult {
+        match source.split_first() {
+            Some((format, entry)) if (CACHE_FORMAT_V1..=CACHE_FORMAT).contains(format) => {
+                read_cached(*format, entry).map_err(|err| {
+                    FetchError::Parse(format!("Unable to read cached entry: {}", err))
+                })
+            }
+            Some((b'{', _)) => parse(&String::from_utf8_lossy(source)),
+            _ => Err(FetchError::Parse(String::from(
+                "Unknown cached entry format",
+            ))),
+        }
+    }
+
+    /// Serialize the entry for the cache
+    pub fn to(&self) -> Vec<u8> {
+        let mut bytes = vec![CACHE_FORMAT];
+        bytes.extend(bincode::serialize(self).unwrap());
+        bytes
+    }
+}
+
+// Read a FetchEntry cached in any binary format. Each format appended fields to the format before it, so the fields
+// are read in order, and the fields appended since the entry was cached take their defaults
+fn read_cached(format: u8, mut bytes: &[u8]) -> Result<FetchEntry, bincode::Error> {
+    fn next<T: serde::de::DeserializeOwned>(bytes: &mut &[u8]) -> Result<T, bincode::Error> {
+        bincode::deserialize_from(bytes)
+    }
+    let mut entry = FetchEntry {
+        digest: next(&mut bytes)?,
+        title: next(&mut bytes)?,
+        pageid: next(&mut bytes)?,
+        outbound: next(&mut bytes)?,
+        redirect: next(&mut bytes)?,
+        disambiguation: next(&mut bytes)?,
+        fetched: next(&mut bytes)?,
+        langlinks: Vec::new(),
+        namespace_links: Vec::new(),
+        categories: Vec::new(),
+        revid: 0,
+        touched: 0,
+        from_cache: false,
+    };
+    if format >= CACHE_FORMAT_V2 {
+        entry.langlinks = next(&mut bytes)?;
+    }
+    if format >= CACHE_FORMAT_V3 {
+        entry.namespace_links = next(&mut bytes)?;
+    }
+    if format >= CACHE_FORMAT_V4 {
+        entry.categories = next(&mut bytes)?;
+    }
+    if format >= CACHE_FORMAT {
+        entry.revid = next(&mut bytes)?;
+        entry.touched = next(&mut bytes)?;
+    }
+    Ok(entry)
+}
+
+/// The cache record of a page that was not found, at `checked` seconds since the Unix epoch
+pub fn missing_record(checked: u64) -> Vec<u8> {
+    let mut bytes = vec![CACHE_MISSING];
+    bytes.extend_from_slice(&checked.to_le_bytes());
+    bytes
+}
+
+/// When the page was looked for, if the cache record is of a page that was not found
+pub fn missing_since(source: &[u8]) -> Option<u64> {
+    match source.split_first() {
+        Some((format, checked)) if *format == CACHE_MISSING => {
+            Some(u64::from_le_bytes(checked.try_into().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Seconds since the Unix epoch of a wiki timestamp, such as 2024-05-01T12:30:00Z
+pub fn read_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim().trim_end_matches('Z').split_once('T')?;
+    let fields = |text: &str, separator| -> Option<Vec<i64>> {
+        let fields: Vec<i64> = text
+            .split(separator)
+            .map(|field| field.parse().ok())
+            .collect::<Option<_>>()?;
+        (fields.len() == 3).then_some(fields)
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let (year, month, day) = (date[0], date[1], date[2]);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || time[0] > 23 || time[1] > 59 {
+        return None;
+    }
+
+    // Days from 1970-01-01, counting years from March so that the leap day falls at the end of the year
+    let (year, month) = match month {
+        1 | 2 => (year - 1, month + 9),
+        _ => (year, month - 3),
+    };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400 + time[0] * 3_600 + time[1] * 60 + time[2]).ok()
+}
+
+// A page is a disambiguation page if the title says so, or if most of the links on the page contain the title
+fn is_disambiguation(title: &str, outbound: &[String]) -> bool {
+    if title.contains(DISAMBIGUATION) {
+        return true;
+    }
+    if outbound.len() < DISAMBIGUATION_MIN_LINKS {
+        return false;
+    }
+    let title = title.to_lowercase();
+    let matches = outbound
+        .iter()
+        .filter(|link| link.to_lowercase().contains(&title))
+        .count();
+    matches * 100 >= outbound.len() * DISAMBIGUATION_PERCENT
+}
+
+// A redirect page links to Wikipedia:Categorizing redirects (namespace 4). The target is the only link in
+// namespace 0
+fn find_redirect_in(links: &[(i32, String)]) -> Option<String> {
+    let is_redirect = links.iter().any(|(ns, title)| {
+        *ns == 4
+            && title
+                .replace('_', " ")
+                .to_lowercase()
+                .contains(CATEGORIZING_REDIRECTS)
+    });
+    if !is_redirect {
+        return None;
+    }
+    let mut targets = links.iter().filter(|(ns, _)| *ns == 0);
+    match (targets.next(), targets.next()) {
+        (Some((_, target)), None) => Some(target.clone()),
+        _ => None,
+    }
 }
 
 #[derive(Debug)]
@@ -214,6 +628,7 @@ pub enum FetchError {
     Lag(f32),
     MissingTitle,
     Parse(String),
+    UnknownWiki(String),
 }
 
 impl FetchError {
@@ -231,6 +646,12 @@ impl FetchError {
                 "Unable to parse response from page title {} secs fetching page: {}",
                 title, parse_err
             ),
+            FetchError::UnknownWiki(wiki) => {
+                error!(
+                    r#"Requested title "{}" on wiki {}, which is not served"#,
+                    title, wiki
+                )
+            }
         }
     }
 }
@@ -244,6 +665,7 @@ impl fmt::Display for FetchError {
             FetchError::Lag(message) => message.to_string(),
             FetchError::MissingTitle => "Missing title".to_string(),
             FetchError::Parse(parse_error_) => parse_error_.to_string(),
+            FetchError::UnknownWiki(wiki) => format!("Wiki {} is not served", wiki),
         };
         write!(f, "{}", err_msg)
     }
@@ -261,7 +683,48 @@ impl From<reqwest::Error> for FetchError {
     }
 }
 
-type FetchResult = Result<FetchEntry, FetchError>;
+pub type FetchResult = Result<FetchEntry, FetchError>;
+
+pub type ContextResult = Result<Vec<(entry::Digest, String)>, FetchError>;
+
+pub type RecentChangesResult = Result<Vec<RecentChange>, FetchError>;
+
+/* *****************************************************************************************************************
+ *
+ * Rate limiter
+ *
+ * Spaces requests to Wikipedia so that no more than opt::rate_limit requests are sent each second. Pages served from
+ * the local cache do not pass through the limiter. A limit of 0 disables the limiter.
+ *
+ *******************************************************************************************************************/
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> RateLimiter {
+        let interval = match requests_per_second {
+            0 => Duration::ZERO,
+            rate => Duration::from_secs(1) / rate,
+        };
+        RateLimiter {
+            interval,
+            next: Instant::now(),
+        }
+    }
+
+    /// Wait until the next request may be sent to Wikipedia
+    pub async fn wait(&mut self) {
+        let now = Instant::now();
+        if self.next > now {
+            tokio::time::sleep_until(self.next).await;
+        }
+        self.next = std::cmp::max(now, self.next) + self.interval;
+    }
+}
 
 /* *****************************************************************************************************************
  *
@@ -269,21 +732,62 @@ type FetchResult = Result<FetchEntry, FetchError>;
  *
  *******************************************************************************************************************/
 
+// The page cache and rate limiter of one wiki
+pub struct WikiFetch {
+    wiki: &'static Wiki,
+    store: PackStore,
+    raw_store: Option<PackStore>,
+    context_store: PackStore,
+    rate_limiter: RateLimiter,
+}
+
 pub async fn new(
     foundation: &foundation::Foundation,
-) -> (JoinHandle<()>, mpsc::Sender<FetchCommand>) {
+) -> Result<(JoinHandle<()>, mpsc::Sender<FetchCommand>), io::Error> {
     trace!("main::init_fetch");
 
+    let cache_directory = opt::OPT.get_cache();
+    let summary = cache::move_single_wiki_cache(&cache_directory, wiki::default_wiki())?;
+    if summary.migrated > 0 {
+        info!("Moved the page cache into the default wiki. {}", summary);
+    }
+
+    let mut wikis = HashMap::new();
+    for wiki in wiki::all() {
+        let mut store = PackStore::open(&cache::get_pack_directory(&cache_directory, wiki))?;
+        store.set_max_size(opt::OPT.get_cache_max_size());
+        info!("Page cache of wiki {} holds {} pages", wiki.id, store.len());
+        let raw_store = match opt::OPT.get_cache_raw() {
+            true => Some(PackStore::open(&cache::get_raw_directory(
+                &cache_directory,
+                wiki,
+            ))?),
+            false => None,
+        };
+        let context_store = PackStore::open(&cache::get_context_directory(&cache_directory, wiki))?;
+        let wiki_fetch = WikiFetch {
+            wiki,
+            store,
+            raw_store,
+            context_store,
+            rate_limiter: RateLimiter::new(wiki.rate_limit),
+        };
+        wikis.insert(wiki.id.clone(), wiki_fetch);
+    }
+
     let worker_count = foundation.get_worker_count().try_into().unwrap();
     let (tx_to_fetch, rx_by_fetch): (mpsc::Sender<FetchCommand>, mpsc::Receiver<FetchCommand>) =
         mpsc::channel(worker_count);
 
-    let fetch_service = tokio::spawn(async move { fetch_service(rx_by_fetch).await });
+    let fetch_service = tokio::spawn(async move { fetch_service(rx_by_fetch, wikis).await });
 
-    (fetch_service, tx_to_fetch)
+    Ok((fetch_service, tx_to_fetch))
 }
 
-pub async fn fetch_service(mut rx: mpsc::Receiver<FetchCommand>) {
+pub async fn fetch_service(
+    mut rx: mpsc::Receiver<FetchCommand>,
+    mut wikis: HashMap<String, WikiFetch>,
+) {
     //pub async fn new() {
     trace!("fetch::new: Spawned fetch");
     loop {
@@ -292,10 +796,64 @@ pub async fn fetch_service(mut rx: mpsc::Receiver<FetchCommand>) {
         let fetch_command = rx.recv().await.unwrap();
         trace!("fetch:: Got command");
         match fetch_command {
-            Get { title, tx } => tx.send(get_links_from_title(title).await).await.unwrap(),
+            Get { wiki, title, tx } => {
+                let result = match wikis.get_mut(&wiki) {
+                    Some(wiki_fetch) => get_links_from_title(title, wiki_fetch).await,
+                    None => Err(FetchError::UnknownWiki(wiki)),
+                };
+                // The requester may have gone, such as a client that dropped a path search
+                let _ = tx.send(result).await;
+            }
+            // Digests hold the wiki, so a digest can only match a page in the store of its own wiki
+            Pin(digests) => {
+                for wiki_fetch in wikis.values_mut() {
+                    for digest in &digests {
+                        wiki_fetch.store.pin(*digest);
+                    }
+                }
+            }
+            Stats { tx } => {
+                let stats = wikis
+                    .values()
+                    .map(|wiki_fetch| wiki_fetch.store.stats())
+                    .fold(CacheStats::default(), CacheStats::add);
+                let _ = tx.send(stats).await;
+            }
+            Context { wiki, title, tx } => {
+                let result = match wikis.get_mut(&wiki) {
+                    Some(wiki_fetch) => get_link_contexts(&title, wiki_fetch).await,
+                    None => Err(FetchError::UnknownWiki(wiki)),
+                };
+                let _ = tx.send(result).await;
+            }
+            RecentChanges { wiki, since, tx } => {
+                let result = match wikis.get_mut(&wiki) {
+                    Some(wiki_fetch) => get_recent_changes(since, wiki_fetch).await,
+                    None => Err(FetchError::UnknownWiki(wiki)),
+                };
+                let _ = tx.send(result).await;
+            }
+            Refresh { wiki, change, tx } => {
+                let result = match wikis.get_mut(&wiki) {
+                    Some(wiki_fetch) => refresh_page(&change, wiki_fetch).await,
+                    None => Some(Err(FetchError::UnknownWiki(wiki))),
+                };
+                let _ = tx.send(result).await;
+            }
             End => break,
         }
     }
+    for wiki_fetch in wikis.values_mut() {
+        let stores = [&mut wiki_fetch.store, &mut wiki_fetch.context_store];
+        for store in stores.into_iter().chain(wiki_fetch.raw_store.as_mut()) {
+            if let Err(err) = store.flush() {
+                error!(
+                    "Unable to write the page cache index of wiki {}: {}",
+                    wiki_fetch.wiki.id, err
+                );
+            }
+        }
+    }
     trace!("Ending...");
 }
 
@@ -306,29 +864,221 @@ pub async fn fetch_service(mut rx: mpsc::Receiver<FetchCommand>) {
  *******************************************************************************************************************/
 
 // UNTESTED
-pub async fn get_links_from_title(title: String) -> FetchResult {
+pub async fn get_links_from_title(title: String, wiki_fetch: &mut WikiFetch) -> FetchResult {
+    let wiki = wiki_fetch.wiki;
     let title = title.trim();
-    let fetched_page = get_page_from(title).await?;
+    match get_cached_entry(wiki, title, &mut wiki_fetch.store) {
+        Some(Ok(mut fetch_entry)) => {
+            info!(
+                r#"Found page "{}" on wiki {} in local cache"#,
+                title, wiki.id
+            );
+            fetch_entry.from_cache = true;
+            return Ok(fetch_entry);
+        }
+        Some(Err(err)) => {
+            info!(
+                r#"Page "{}" was not found on wiki {} when last requested"#,
+                title, wiki.id
+            );
+            return Err(err);
+        }
+        None => {}
+    }
+    pull_page(title, 0, wiki_fetch).await
+}
+
+// Pull the page from the wiki, and save it to the cache. `touched` is when the page last changed, if known
+async fn pull_page(title: &str, touched: u64, wiki_fetch: &mut WikiFetch) -> FetchResult {
+    let wiki = wiki_fetch.wiki;
+    info!(r#"Pulling page "{}" from wiki {}"#, title, wiki.id);
+    let url = get_api_url(wiki);
+    let rate_limiter = &mut wiki_fetch.rate_limiter;
+    rate_limiter.wait().await;
+    let mut fetched_page = fetch_page(&url, title).await?;
     let response = parse(&fetched_page);
-    check_maxlag(&URL, response, fetched_page, title).await
+    let mut result = check_maxlag(&url, response, &mut fetched_page, title, rate_limiter).await;
+    if let Ok(fetch_entry) = &mut result {
+        fetch_entry.digest = entry::Entry::get_digest_for(wiki, &fetch_entry.title);
+        fetch_entry.touched = touched;
+        for category in &mut fetch_entry.categories {
+            *category = title::category_for(wiki, category);
+        }
+        cache_page(wiki, &mut wiki_fetch.store, title, &fetch_entry.to());
+        if let Some(raw_store) = &mut wiki_fetch.raw_store {
+            cache_page(wiki, raw_store, title, fetched_page.as_bytes());
+        }
+    }
+    if let Err(FetchError::MissingTitle) = &result {
+        cache_page(wiki, &mut wiki_fetch.store, title, &missing_record(now()));
+    }
+    result
 }
 
-// UNTESTED
-async fn get_page_from(title: &str) -> Result<String, FetchError> {
-    let path_to_page = get_cache_directory_from(&title);
+/// Pull the page again if it is cached, and the cached copy is older than the change. Pages cached before revisions
+/// were recorded are always pulled. A page cached as not found is pulled, as the change may have created it
+pub async fn refresh_page(
+    change: &RecentChange,
+    wiki_fetch: &mut WikiFetch,
+) -> Option<FetchResult> {
+    let wiki = wiki_fetch.wiki;
+    let title = change.title.trim();
+    let cached = match wiki_fetch
+        .store
+        .get(&entry::Entry::get_digest_for(wiki, title))
+    {
+        Ok(cached) => cached?,
+        Err(err) => {
+            warn!(
+                r#"Unable to read page "{}" from local cache: {}"#,
+                title, err
+            );
+            return None;
+        }
+    };
+    if missing_since(&cached).is_none() {
+        if let Ok(fetch_entry) = FetchEntry::from(&cached) {
+            if fetch_entry.revid >= change.revid {
+                return None;
+            }
+        }
+    }
+    info!(
+        r#"Page "{}" on wiki {} changed in revision {}"#,
+        title, wiki.id, change.revid
+    );
+    Some(pull_page(title, change.changed().unwrap_or_default(), wiki_fetch).await)
+}
+
+/// Changes made to the wiki since a time, in seconds since the Unix epoch, oldest first. Up to
+/// RECENT_CHANGES_REQUESTS requests are made. Later changes are left for the next poll
+pub async fn get_recent_changes(since: u64, wiki_fetch: &mut WikiFetch) -> RecentChangesResult {
+    let url = get_api_url(wiki_fetch.wiki);
+    let mut changes = Vec::new();
+    let mut more = None;
+    for _ in 0..RECENT_CHANGES_REQUESTS {
+        wiki_fetch.rate_limiter.wait().await;
+        let payload = fetch_url(build_recent_changes_url(&url, since, more.as_deref())).await?;
+        let page = parse_recent_changes(&payload)?;
+        changes.extend(page.query.recentchanges);
+        match page.more {
+            Some(next) => more = Some(next.rccontinue),
+            None => return Ok(changes),
+        }
+    }
+    warn!(
+        "More than {} recent changes on wiki {}. Later changes are read at the next poll",
+        changes.len(),
+        wiki_fetch.wiki.id
+    );
+    Ok(changes)
+}
 
-    let mut exists = false;
-    if let Ok(path) = &path_to_page {
-        exists = path.exists();
+fn parse_recent_changes(payload: &str) -> Result<RecentChangesPage, FetchError> {
+    if let Ok(page) = serde_json::from_str::<RecentChangesPage>(payload) {
+        return Ok(page);
     }
+    if let Ok(lag) = serde_json::from_str::<MaxLagError>(payload) {
+        return Err(FetchError::Lag(lag.error.lag));
+    }
+    error!(
+        "fetch::parse_recent_changes: Unknown wikipedia payload: {}",
+        payload
+    );
+    Err(FetchError::Parse(String::from(PARSE_ERROR)))
+}
 
-    if exists {
-        info!(r#"Found page "{}" in local cache"#, title);
-        Ok(fs::read_to_string(path_to_page.as_ref().unwrap())?)
-    } else {
-        info!(r#"Pulling page "{}" from Wikipedia"#, title);
-        let fetch = fetch_page(&URL, title).await?;
-        Ok(fetch)
+/// The sentence around the first link to each page from the page, from the context store or the wikitext of the page
+pub async fn get_link_contexts(title: &str, wiki_fetch: &mut WikiFetch) -> ContextResult {
+    let wiki = wiki_fetch.wiki;
+    let title = title.trim();
+    let digest = entry::Entry::get_digest_for(wiki, title);
+    match wiki_fetch.context_store.get(&digest) {
+        Ok(Some(cached)) => match read_contexts(&cached) {
+            Some(contexts) => return Ok(digest_contexts(wiki, contexts)),
+            None => warn!(r#"Unable to read the link contexts of "{}""#, title),
+        },
+        Ok(None) => {}
+        Err(err) => warn!(
+            r#"Unable to read the link contexts of "{}": {}"#,
+            title, err
+        ),
+    }
+
+    info!(
+        r#"Pulling the wikitext of page "{}" from wiki {}"#,
+        title, wiki.id
+    );
+    wiki_fetch.rate_limiter.wait().await;
+    let payload = fetch_url(build_source_url(&get_api_url(wiki), title)).await?;
+    let source: SourcePage = match serde_json::from_str(&payload) {
+        Ok(source) => source,
+        Err(_) => return parse(&payload).map(|_| Vec::new()),
+    };
+    let contexts = wikitext::link_contexts(&source.parse.wikitext.text);
+    trace!(
+        "fetch::get_link_contexts: {} link contexts in {}",
+        contexts.len(),
+        source.parse.title
+    );
+    let mut record = vec![CONTEXT_FORMAT];
+    record.extend(bincode::serialize(&contexts).unwrap());
+    cache_page(wiki, &mut wiki_fetch.context_store, title, &record);
+    Ok(digest_contexts(wiki, contexts))
+}
+
+fn read_contexts(record: &[u8]) -> Option<Vec<(String, String)>> {
+    match record.split_first() {
+        Some((format, contexts)) if *format == CONTEXT_FORMAT => {
+            bincode::deserialize(contexts).ok()
+        }
+        _ => None,
+    }
+}
+
+fn digest_contexts(wiki: &Wiki, contexts: Vec<(String, String)>) -> Vec<(entry::Digest, String)> {
+    contexts
+        .into_iter()
+        .map(|(title, context)| (entry::Entry::get_digest_for(wiki, &title), context))
+        .collect()
+}
+
+fn get_api_url(wiki: &Wiki) -> String {
+    let mut url = wiki.domain_name.clone();
+    url.push_str(PATH);
+    url
+}
+
+// Returns None if the page is not in the local cache, the cached page cannot be read, or the page was not found
+// longer ago than NOT_FOUND_AGE. Returns MissingTitle if the page was not found more recently. Entries cached before
+// digests held the wiki hold the digest of the title alone, so the digest is set from the title
+fn get_cached_entry(wiki: &Wiki, title: &str, store: &mut PackStore) -> Option<FetchResult> {
+    let cached = match store.get(&entry::Entry::get_digest_for(wiki, title)) {
+        Ok(cached) => cached?,
+        Err(err) => {
+            warn!(
+                r#"Unable to read page "{}" from local cache: {}"#,
+                title, err
+            );
+            return None;
+        }
+    };
+    if let Some(checked) = missing_since(&cached) {
+        return (now().saturating_sub(checked) < NOT_FOUND_AGE)
+            .then_some(Err(FetchError::MissingTitle));
+    }
+    match FetchEntry::from(&cached) {
+        Ok(mut fetch_entry) => {
+            fetch_entry.digest = entry::Entry::get_digest_for(wiki, &fetch_entry.title);
+            Some(Ok(fetch_entry))
+        }
+        Err(err) => {
+            warn!(
+                r#"Unable to read page "{}" from local cache: {}"#,
+                title, err
+            );
+            None
+        }
     }
 }
 
@@ -360,16 +1110,14 @@ fn parse(payload: &str) -> FetchResult {
 async fn check_maxlag(
     url: &str,
     mut response: FetchResult,
-    mut page: String,
+    page: &mut String,
     title: &str,
+    rate_limiter: &mut RateLimiter,
 ) -> FetchResult {
     let mut tries = 4;
     loop {
         match &response {
-            Ok(_) => {
-                cache_page(&page, get_cache_directory_from(&title));
-                break response;
-            }
+            Ok(_) => break response,
             Err(lag_error) if matches!(lag_error, FetchError::Lag(_)) => {
                 if tries <= 0 {
                     break response;
@@ -377,8 +1125,9 @@ async fn check_maxlag(
                 tries -= 1;
                 let duration = tokio::time::Duration::new(*MAXLAG_VALUE, 0);
                 tokio::time::sleep(duration).await;
-                page = fetch_page(url, title).await?;
-                response = parse(&page);
+                rate_limiter.wait().await;
+                *page = fetch_page(url, title).await?;
+                response = parse(page);
             }
             Err(_) => break response,
         }
@@ -388,7 +1137,10 @@ async fn check_maxlag(
 // ***********************************************************************************************
 
 async fn fetch_page(root_url: &str, title: &str) -> Result<String, FetchError> {
-    let url = build_url(root_url, title);
+    fetch_url(build_url(root_url, title)).await
+}
+
+async fn fetch_url(url: Url) -> Result<String, FetchError> {
     let response = reqwest::get(url.as_str()).await?;
     let status = response.status();
     let links = match status {
@@ -405,43 +1157,40 @@ async fn fetch_page(root_url: &str, title: &str) -> Result<String, FetchError> {
 }
 
 fn extract_links_from(parsed: Page) -> FetchResult {
-    let outbound: Vec<String> = parsed
+    let links = parsed
         .parse
         .links
         .into_iter()
-        .filter(|link| link.ns == 0)
-        .map(|link| link.title)
+        .filter(|link| link.exists.is_some())
+        .map(|link| (link.ns, link.title))
         .collect();
 
-    let digest = entry::Entry::get_digest(&parsed.parse.title);
-    Ok(FetchEntry {
-        digest,
-        title: parsed.parse.title,
-        outbound,
-    })
+    let mut fetch_entry = FetchEntry::from_links(parsed.parse.title, links, None);
+    fetch_entry.pageid = parsed.parse.pageid;
+    fetch_entry.revid = parsed.parse.revid;
+    fetch_entry.langlinks = parsed
+        .parse
+        .langlinks
+        .into_iter()
+        .map(|langlink| (langlink.lang, langlink.title))
+        .collect();
+    fetch_entry.categories = parsed
+        .parse
+        .categories
+        .into_iter()
+        .filter(|category| category.hidden.is_none())
+        .map(|category| category.name.replace('_', " "))
+        .collect();
+    Ok(fetch_entry)
 }
 
-fn cache_page(contents: &str, path_to_page: Result<PathBuf, io::Error>) {
-    if let Ok(path) = &path_to_page {
-        match fs::write(path, &contents) {
-            Ok(_) => info!("Saved {:?} to cache", path.as_os_str()),
-            Err(_) => info!("Failed to save {:?} to cache", path.as_os_str()),
-        }
+fn cache_page(wiki: &Wiki, store: &mut PackStore, title: &str, contents: &[u8]) {
+    match store.put(&entry::Entry::get_digest_for(wiki, title), title, contents) {
+        Ok(_) => info!(r#"Saved "{}" to cache"#, title),
+        Err(err) => info!(r#"Failed to save "{}" to cache: {}"#, title, err),
     }
 }
 
-fn get_cache_directory_from(title: &str) -> Result<PathBuf, io::Error> {
-    let title_digest = entry::Entry::get_digest(title);
-    let mut path_to_page = opt::OPT.get_cache();
-    path_to_page.push(format!("{:02x?}", title_digest[2]));
-    path_to_page.push(format!("{:02x?}", title_digest[1]));
-    path_to_page.push(format!("{:02x?}", title_digest[0]));
-    create_dir_all(&path_to_page)?;
-    path_to_page.push(title);
-    path_to_page.set_extension("json");
-    Ok(path_to_page)
-}
-
 fn build_url(root_url: &str, title: &str) -> Url {
     let api = Url::parse_with_params(
         root_url,
@@ -449,7 +1198,7 @@ fn build_url(root_url: &str, title: &str) -> Url {
             ("action", "parse"),
             ("format", "json"),
             ("page", title),
-            ("prop", "links"),
+            ("prop", "links|langlinks|categories"),
         ],
     )
     .unwrap();
@@ -457,6 +1206,38 @@ fn build_url(root_url: &str, title: &str) -> Url {
     api
 }
 
+fn build_recent_changes_url(root_url: &str, since: u64, more: Option<&str>) -> Url {
+    let since = since.to_string();
+    let mut params = vec![
+        ("action", "query"),
+        ("format", "json"),
+        ("list", "recentchanges"),
+        ("rcprop", "title|ids|timestamp"),
+        ("rctype", "edit|new"),
+        ("rcdir", "newer"),
+        ("rcstart", since.as_str()),
+        ("rclimit", RECENT_CHANGES_LIMIT),
+        ("maxlag", MAXLAG),
+    ];
+    if let Some(more) = more {
+        params.push(("rccontinue", more));
+    }
+    Url::parse_with_params(root_url, &params).unwrap()
+}
+
+fn build_source_url(root_url: &str, title: &str) -> Url {
+    Url::parse_with_params(
+        root_url,
+        &[
+            ("action", "parse"),
+            ("format", "json"),
+            ("page", title),
+            ("prop", "wikitext"),
+        ],
+    )
+    .unwrap()
+}
+
 /* *****************************************************************************************************************
  *
  * Tests
@@ -500,15 +1281,302 @@ mod tests {
     fn test_parse_success() {
         let entry = parse(SUCCESS_PAGE).unwrap();
         assert_eq!(entry.title, "Value network");
+        // md5 of "en:Value network"
         assert_eq!(
             entry.digest,
-            [165, 46, 141, 56, 102, 47, 14, 148, 186, 90, 70, 92, 181, 12, 96, 46]
+            <[u8; 16]>::from(md5::compute("en:Value network"))
         );
         assert_eq!(entry.outbound.len(), 2);
         assert_eq!(entry.outbound[0], "Adolescent cliques");
         assert_eq!(entry.outbound[1], "Assortative mixing");
     }
 
+    #[test]
+    fn test_from_links_disambiguation() {
+        let links = vec![
+            (0, "Mercury (planet)".to_string()),
+            (0, "Mercury (element)".to_string()),
+            (0, "Mercury (mythology)".to_string()),
+            (0, "Freddie Mercury".to_string()),
+            (0, "Planet".to_string()),
+        ];
+        let entry = FetchEntry::from_links("Mercury".to_string(), links, None);
+        assert!(entry.disambiguation);
+        assert!(entry.outbound.is_empty());
+
+        let entry = FetchEntry::from_links("Mercury (disambiguation)".to_string(), vec![], None);
+        assert!(entry.disambiguation);
+    }
+
+    #[test]
+    fn test_from_links_drops_disambiguation_links() {
+        let links = vec![
+            (0, "Planet".to_string()),
+            (0, "Mercury (disambiguation)".to_string()),
+            (0, "Venus".to_string()),
+        ];
+        let entry = FetchEntry::from_links("Solar System".to_string(), links, None);
+        assert!(!entry.disambiguation);
+        assert_eq!(entry.outbound, vec!["Planet", "Venus"]);
+    }
+
+    #[test]
+    fn test_parse_drops_red_links() {
+        let entry = parse(RED_LINKS_PAGE).unwrap();
+        assert_eq!(entry.outbound, vec!["Locomotive"]);
+        assert_eq!(
+            entry.namespace_links,
+            vec![(14, "Category:Rail transport".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_missing_record() {
+        assert_eq!(missing_since(&missing_record(1234)), Some(1234));
+        assert_eq!(missing_since(&[CACHE_MISSING, 1, 2]), None);
+        let fetch_entry = FetchEntry::from_links("Train".to_string(), vec![], None);
+        assert_eq!(missing_since(&fetch_entry.to()), None);
+        assert!(FetchEntry::from(&missing_record(1234)).is_err());
+    }
+
+    #[test]
+    fn test_from_links_namespaces() {
+        let links = vec![
+            (0, "Locomotive".to_string()),
+            (14, "Category:Rail transport".to_string()),
+            (100, "Portal:Trains".to_string()),
+            (10, "Template:Rail transport".to_string()),
+        ];
+        let fetch_entry = FetchEntry::from_links("Train".to_string(), links, None);
+        assert_eq!(fetch_entry.outbound, vec!["Locomotive"]);
+        assert_eq!(fetch_entry.namespace_links.len(), 3);
+
+        let wikis = [Wiki::new("en", "https://en.wikipedia.org/", 10)];
+        let entry = fetch_entry.to_entry(&wikis[0], &wikis, &LinkPolicy::default());
+        assert!(entry.get_namespace_links().is_empty());
+
+        let policy =
+            LinkPolicy::from_options(&["category=traverse".to_string(), "portal".to_string()])
+                .unwrap();
+        let entry = fetch_entry.to_entry(&wikis[0], &wikis, &policy);
+        assert_eq!(
+            entry.get_namespace_links(),
+            &[
+                (14, entry::Entry::get_digest("Category:Rail transport")),
+                (100, entry::Entry::get_digest("Portal:Trains")),
+            ]
+        );
+        assert_eq!(
+            entry.get_outbound(),
+            &[entry::Entry::get_digest("Locomotive")]
+        );
+    }
+
+    #[test]
+    fn test_from_links_redirect() {
+        let links = vec![
+            (0, "Rail transport".to_string()),
+            (4, "Wikipedia:Categorizing redirects".to_string()),
+            (10, "Template:R from plural".to_string()),
+        ];
+        let entry = FetchEntry::from_links("Railways".to_string(), links, None);
+        assert_eq!(entry.redirect, Some("Rail transport".to_string()));
+        assert_eq!(entry.outbound, vec!["Rail transport"]);
+        assert!(entry.namespace_links.is_empty());
+
+        let entry = FetchEntry::from_links("Trains".to_string(), vec![], Some("Train".to_string()));
+        assert_eq!(entry.redirect, Some("Train".to_string()));
+        assert_eq!(entry.outbound, vec!["Train"]);
+    }
+
+    #[test]
+    fn test_cached_entry() {
+        let mut entry = parse(SUCCESS_PAGE).unwrap();
+        entry.from_cache = true;
+        let restored = FetchEntry::from(&entry.to()).unwrap();
+        assert_eq!(restored.title, "Value network");
+        assert_eq!(restored.pageid, 1614337);
+        assert_eq!(restored.outbound, entry.outbound);
+        assert_eq!(restored.fetched, entry.fetched);
+        assert!(!restored.from_cache);
+    }
+
+    #[test]
+    fn test_parse_langlinks() {
+        let entry = parse(LANGLINKS_PAGE).unwrap();
+        assert_eq!(
+            entry.langlinks,
+            vec![
+                ("de".to_string(), "Zug (Eisenbahn)".to_string()),
+                ("ja".to_string(), "列車".to_string()),
+            ]
+        );
+        assert!(parse(SUCCESS_PAGE).unwrap().langlinks.is_empty());
+
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("ja", "https://ja.wikipedia.org/", 10),
+        ];
+        let train = entry.to_entry(&wikis[0], &wikis, &LinkPolicy::default());
+        assert_eq!(
+            train.digest(),
+            entry::Entry::get_digest_for(&wikis[0], "Train")
+        );
+        assert_eq!(
+            train.get_outbound(),
+            &[entry::Entry::get_digest_for(&wikis[0], "Locomotive")]
+        );
+        assert_eq!(
+            train.get_langlinks(),
+            &[(
+                "ja".to_string(),
+                entry::Entry::get_digest_for(&wikis[1], "列車")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_categories() {
+        let fetch_entry = parse(LANGLINKS_PAGE).unwrap();
+        assert_eq!(fetch_entry.categories, vec!["Rail transport", "Trains"]);
+        assert!(parse(SUCCESS_PAGE).unwrap().categories.is_empty());
+
+        let wikis = [Wiki::new("en", "https://en.wikipedia.org/", 10)];
+        let entry = fetch_entry.to_entry(&wikis[0], &wikis, &LinkPolicy::default());
+        assert_eq!(
+            entry.get_categories(),
+            &[
+                entry::Entry::get_digest("Category:Rail transport"),
+                entry::Entry::get_digest("Category:Trains"),
+            ]
+        );
+
+        let restored = FetchEntry::from(&fetch_entry.to()).unwrap();
+        assert_eq!(restored.categories, fetch_entry.categories);
+
+        // The categories of a page on de are the category pages under the local prefix, Kategorie
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+        let entry = fetch_entry.to_entry(&de, &wikis, &LinkPolicy::default());
+        assert_eq!(
+            entry.get_categories(),
+            &[
+                entry::Entry::get_digest_for(&de, "Kategorie:Rail transport"),
+                entry::Entry::get_digest_for(&de, "Kategorie:Trains"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pull_page_local_categories() {
+        let server = MockServer::start();
+        server.mock(|when, then| {
+            when.path(PATH).query_param("page", "Value network");
+            then.status(200).body(LANGLINKS_PAGE);
+        });
+
+        let (mut wiki_fetch, cache_directory) = test_wiki_fetch("categories", "de", &server);
+        let mut de = wiki_fetch.wiki.clone();
+        de.namespaces = title::local_namespaces("de");
+        wiki_fetch.wiki = Box::leak(Box::new(de));
+        let fetch_entry = get_links_from_title("Value network".to_string(), &mut wiki_fetch)
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch_entry.categories,
+            vec!["Kategorie:Rail transport", "Kategorie:Trains"]
+        );
+
+        drop(wiki_fetch);
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
+    #[test]
+    fn test_cached_entry_v1() {
+        #[derive(Serialize)]
+        struct V1<'a> {
+            digest: entry::Digest,
+            title: &'a str,
+            pageid: u32,
+            outbound: Vec<&'a str>,
+            redirect: Option<String>,
+            disambiguation: bool,
+            fetched: u64,
+        }
+        let v1 = V1 {
+            digest: entry::Entry::get_digest("Train"),
+            title: "Train",
+            pageid: 1,
+            outbound: vec!["Locomotive"],
+            redirect: None,
+            disambiguation: false,
+            fetched: 2,
+        };
+        let mut bytes = vec![CACHE_FORMAT_V1];
+        bytes.extend(bincode::serialize(&v1).unwrap());
+        let restored = FetchEntry::from(&bytes).unwrap();
+        assert_eq!(restored.title, "Train");
+        assert_eq!(restored.outbound, vec!["Locomotive"]);
+        assert_eq!(restored.fetched, 2);
+        assert!(restored.langlinks.is_empty());
+    }
+
+    #[test]
+    fn test_cached_entry_v4() {
+        // A V4 entry is a V1 entry followed by the interlanguage links, namespace links and categories
+        let mut bytes = vec![CACHE_FORMAT_V4];
+        bytes.extend(bincode::serialize(&entry::Entry::get_digest("Train")).unwrap());
+        bytes.extend(bincode::serialize(&("Train", 1u32, vec!["Locomotive"])).unwrap());
+        bytes.extend(bincode::serialize(&(None::<String>, false, 2u64)).unwrap());
+        bytes.extend(bincode::serialize(&vec![("de", "Zug")]).unwrap());
+        bytes.extend(bincode::serialize(&vec![(14, "Category:Trains")]).unwrap());
+        bytes.extend(bincode::serialize(&vec!["Trains"]).unwrap());
+        let restored = FetchEntry::from(&bytes).unwrap();
+        assert_eq!(restored.title, "Train");
+        assert_eq!(
+            restored.langlinks,
+            vec![("de".to_string(), "Zug".to_string())]
+        );
+        assert_eq!(
+            restored.namespace_links,
+            vec![(14, "Category:Trains".to_string())]
+        );
+        assert_eq!(restored.categories, vec!["Trains"]);
+        assert_eq!((restored.revid, restored.touched), (0, 0));
+
+        // Truncated before the categories
+        bytes.truncate(bytes.len() - 4);
+        assert!(FetchEntry::from(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_cached_payload() {
+        let entry = FetchEntry::from(SUCCESS_PAGE.as_bytes()).unwrap();
+        assert_eq!(entry.title, "Value network");
+        assert_eq!(entry.outbound.len(), 2);
+        assert!(FetchEntry::from(b"").is_err());
+        assert!(FetchEntry::from(&[CACHE_FORMAT, 1, 2]).is_err());
+    }
+
+    // The page cache and rate limiter of a wiki served by the mock server, with the cache in a directory of its own
+    fn test_wiki_fetch(
+        name: &str,
+        id: &str,
+        server: &MockServer,
+    ) -> (WikiFetch, std::path::PathBuf) {
+        let cache_directory =
+            std::env::temp_dir().join(format!("six_degrees_fetch_{}_{}", name, std::process::id()));
+        let wiki: &'static Wiki = Box::leak(Box::new(Wiki::new(id, &server.url(""), 0)));
+        let wiki_fetch = WikiFetch {
+            wiki,
+            store: PackStore::open(&cache::get_pack_directory(&cache_directory, wiki)).unwrap(),
+            raw_store: None,
+            context_store: PackStore::open(&cache::get_context_directory(&cache_directory, wiki))
+                .unwrap(),
+            rate_limiter: RateLimiter::new(wiki.rate_limit),
+        };
+        (wiki_fetch, cache_directory)
+    }
+
     #[tokio::test]
     async fn test_fetch_success() {
         // External url "https://en.wikipedia.org/w/api.php?action=parse&format=json&page=Value+network&prop=links"
@@ -520,7 +1588,7 @@ mod tests {
                 .query_param("action", "parse")
                 .query_param("format", "json")
                 .query_param("page", "Value network")
-                .query_param("prop", "links");
+                .query_param("prop", "links|langlinks|categories");
             then.status(200).body(SUCCESS_PAGE);
         });
 
@@ -531,6 +1599,145 @@ mod tests {
         assert_eq!(links.unwrap(), SUCCESS_PAGE);
     }
 
+    #[tokio::test]
+    async fn test_missing_title_cached() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path(PATH).query_param("page", "Red link");
+            then.status(200).body(MISSING_TITLE_PAGE);
+        });
+
+        let (mut wiki_fetch, cache_directory) = test_wiki_fetch("missing", "en", &server);
+        let wiki = wiki_fetch.wiki;
+        for _ in 0..2 {
+            let result = get_links_from_title("Red link".to_string(), &mut wiki_fetch).await;
+            assert!(matches!(result, Err(FetchError::MissingTitle)));
+        }
+        mock.assert_hits(1);
+
+        // Requested again once the record is older than NOT_FOUND_AGE
+        let digest = entry::Entry::get_digest_for(wiki, "Red link");
+        let checked = missing_since(&wiki_fetch.store.get(&digest).unwrap().unwrap()).unwrap();
+        assert!(checked > 0);
+        let expired = missing_record(checked - NOT_FOUND_AGE);
+        wiki_fetch.store.put(&digest, "Red link", &expired).unwrap();
+        let result = get_links_from_title("Red link".to_string(), &mut wiki_fetch).await;
+        assert!(matches!(result, Err(FetchError::MissingTitle)));
+        mock.assert_hits(2);
+
+        drop(wiki_fetch);
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_link_contexts() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path(PATH)
+                .query_param("page", "Train")
+                .query_param("prop", "wikitext");
+            then.status(200).body(WIKITEXT_PAGE);
+        });
+
+        let (mut wiki_fetch, cache_directory) = test_wiki_fetch("context", "en", &server);
+        let wiki = wiki_fetch.wiki;
+        let expected = vec![
+            (
+                entry::Entry::get_digest_for(wiki, "Locomotive"),
+                "Trains are hauled by a locomotive.".to_string(),
+            ),
+            (
+                entry::Entry::get_digest_for(wiki, "Tram"),
+                "See also trams.".to_string(),
+            ),
+        ];
+        for _ in 0..2 {
+            let contexts = get_link_contexts("Train", &mut wiki_fetch).await.unwrap();
+            assert_eq!(contexts, expected);
+        }
+        mock.assert_hits(1);
+
+        drop(wiki_fetch);
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
+    #[test]
+    fn test_read_timestamp() {
+        assert_eq!(read_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(read_timestamp("2024-05-01T12:30:00Z"), Some(1714566600));
+        assert_eq!(read_timestamp("2000-02-29T23:59:59Z"), Some(951868799));
+        assert_eq!(read_timestamp("2024-13-01T12:30:00Z"), None);
+        assert_eq!(read_timestamp("2024-05-01 12:30:00"), None);
+        assert_eq!(read_timestamp("1969-12-31T23:59:59Z"), None);
+    }
+
+    #[test]
+    fn test_parse_recent_changes() {
+        let page = parse_recent_changes(RECENT_CHANGES_PAGE).unwrap();
+        assert_eq!(page.more.unwrap().rccontinue, "20240501123000|1001");
+        let changes = page.query.recentchanges;
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].title, "Train");
+        assert_eq!(changes[0].revid, 1187402592);
+        assert_eq!(changes[0].wiki, None);
+        assert_eq!(changes[1].changed(), Some(1714566660));
+
+        assert!(matches!(
+            parse_recent_changes(MAXLAG_PAGE),
+            Err(FetchError::Lag(_))
+        ));
+        assert!(matches!(
+            parse_recent_changes(FAIL_PAGE),
+            Err(FetchError::Parse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_page() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path(PATH).query_param("page", "Train");
+            then.status(200).body(LANGLINKS_PAGE);
+        });
+
+        let (mut wiki_fetch, cache_directory) = test_wiki_fetch("refresh", "en", &server);
+        let wiki = wiki_fetch.wiki;
+        let change = |revid| RecentChange {
+            wiki: None,
+            title: "Train".to_string(),
+            revid,
+            timestamp: "2024-05-01T12:30:00Z".to_string(),
+        };
+
+        // Pages that are not cached are not pulled
+        assert!(refresh_page(&change(6), &mut wiki_fetch).await.is_none());
+
+        let mut cached = FetchEntry::from_links("Train".to_string(), Vec::new(), None);
+        cached.revid = 5;
+        cache_page(wiki, &mut wiki_fetch.store, "Train", &cached.to());
+        assert!(refresh_page(&change(5), &mut wiki_fetch).await.is_none());
+        mock.assert_hits(0);
+
+        let refreshed = refresh_page(&change(6), &mut wiki_fetch)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(refreshed.revid, 1187402591);
+        assert_eq!(refreshed.touched, 1714566600);
+        mock.assert_hits(1);
+        let cached = get_cached_entry(wiki, "Train", &mut wiki_fetch.store)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.revid, 1187402591);
+        assert_eq!(cached.touched, 1714566600);
+        assert!(refresh_page(&change(1187402591), &mut wiki_fetch)
+            .await
+            .is_none());
+
+        drop(wiki_fetch);
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
     #[tokio::test]
     async fn test_maxlag() {
         let server = MockServer::start();
@@ -539,27 +1746,144 @@ mod tests {
                 .query_param("action", "parse")
                 .query_param("format", "json")
                 .query_param("page", "Maxlag Value")
-                .query_param("prop", "links");
+                .query_param("prop", "links|langlinks|categories");
             then.status(200).body(MAXLAG_PAGE);
         });
 
         let url = server.url(PATH).to_string();
         //  let links = fetch_page(&url, "Maxlag Value").await;
         let response = parse(&MAXLAG_PAGE);
-        let fetch_result =
-            check_maxlag(&url, response, String::from(MAXLAG_PAGE), "Maxlag Value").await;
+        let fetch_result = check_maxlag(
+            &url,
+            response,
+            &mut String::from(MAXLAG_PAGE),
+            "Maxlag Value",
+            &mut RateLimiter::new(0),
+        )
+        .await;
         ms.assert_hits(4);
         assert!(fetch_result.is_err());
         assert!(matches!(fetch_result.unwrap_err(), FetchError::Lag(_)));
     }
 
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        let mut rate_limiter = RateLimiter::new(20);
+        let start = Instant::now();
+        for _ in 0..5 {
+            rate_limiter.wait().await;
+        }
+        // The first request is sent immediately, the remaining four are spaced 50ms apart
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_disabled() {
+        let mut rate_limiter = RateLimiter::new(0);
+        let start = Instant::now();
+        for _ in 0..100 {
+            rate_limiter.wait().await;
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_service_wikis() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path(PATH).query_param("page", "Value network");
+            then.status(200).body(SUCCESS_PAGE);
+        });
+
+        let (wiki_fetch, cache_directory) = test_wiki_fetch("wikis", "de", &server);
+        let de = wiki_fetch.wiki;
+        let mut wikis = HashMap::new();
+        wikis.insert(de.id.clone(), wiki_fetch);
+        let (tx_to_fetch, rx_by_fetch) = mpsc::channel(1);
+        let fetch_service = tokio::spawn(async move { fetch_service(rx_by_fetch, wikis).await });
+
+        let (tx, mut rx) = mpsc::channel(1);
+        for wiki in ["de", "de", "fr"] {
+            tx_to_fetch
+                .send(FetchCommand::Get {
+                    wiki: wiki.to_string(),
+                    title: "Value network".to_string(),
+                    tx: tx.clone(),
+                })
+                .await
+                .unwrap();
+        }
+        let pulled = rx.recv().await.unwrap().unwrap();
+        assert!(!pulled.from_cache);
+        assert_eq!(
+            pulled.digest,
+            entry::Entry::get_digest_for(de, "Value network")
+        );
+        let cached = rx.recv().await.unwrap().unwrap();
+        assert!(cached.from_cache);
+        assert!(matches!(
+            rx.recv().await.unwrap(),
+            Err(FetchError::UnknownWiki(_))
+        ));
+        mock.assert_hits(1);
+
+        tx_to_fetch.send(FetchCommand::End).await.unwrap();
+        fetch_service.await.unwrap();
+        assert!(cache_directory.join("de").join("pack").is_dir());
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fetch_service_requester_gone() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.path(PATH).query_param("page", "Value network");
+            then.status(200).body(SUCCESS_PAGE);
+        });
+
+        let (wiki_fetch, cache_directory) = test_wiki_fetch("gone", "en", &server);
+        let mut wikis = HashMap::new();
+        wikis.insert(wiki_fetch.wiki.id.clone(), wiki_fetch);
+        let (tx_to_fetch, rx_by_fetch) = mpsc::channel(1);
+        let fetch_service = tokio::spawn(async move { fetch_service(rx_by_fetch, wikis).await });
+
+        // The receiver is dropped before the reply is sent
+        let (tx, rx) = mpsc::channel(1);
+        drop(rx);
+        tx_to_fetch
+            .send(FetchCommand::Get {
+                wiki: "en".to_string(),
+                title: "Value network".to_string(),
+                tx,
+            })
+            .await
+            .unwrap();
+
+        // The service still answers later requests, and ends cleanly
+        let (tx, mut rx) = mpsc::channel(1);
+        tx_to_fetch
+            .send(FetchCommand::Get {
+                wiki: "en".to_string(),
+                title: "Value network".to_string(),
+                tx,
+            })
+            .await
+            .unwrap();
+        assert!(rx.recv().await.unwrap().unwrap().from_cache);
+        mock.assert_hits(1);
+
+        tx_to_fetch.send(FetchCommand::End).await.unwrap();
+        fetch_service.await.unwrap();
+        std::fs::remove_dir_all(&cache_directory).unwrap();
+    }
+
     #[test]
     fn test_build_url() {
         let root_url = "https://en.wikipedia.org/";
         let url = build_url(root_url, "Value network");
         assert_eq!(
             url.as_str(),
-            "https://en.wikipedia.org/?action=parse&format=json&page=Value+network&prop=links"
+            "https://en.wikipedia.org/?action=parse&format=json&page=Value+network&prop=links%7Clanglinks%7Ccategories"
         );
     }
 
@@ -598,6 +1922,125 @@ mod tests {
 		]
 	}
 }
+"###;
+
+    const LANGLINKS_PAGE: &str = r###"{
+	"parse": {
+		"title": "Train",
+		"pageid": 29863,
+		"revid": 1187402591,
+		"links": [
+			{
+				"ns": 0,
+				"exists": "",
+				"*": "Locomotive"
+			}
+		],
+		"langlinks": [
+			{
+				"lang": "de",
+				"url": "https://de.wikipedia.org/wiki/Zug_(Eisenbahn)",
+				"langname": "German",
+				"autonym": "Deutsch",
+				"*": "Zug (Eisenbahn)"
+			},
+			{
+				"lang": "ja",
+				"url": "https://ja.wikipedia.org/wiki/%E5%88%97%E8%BB%8A",
+				"langname": "Japanese",
+				"autonym": "日本語",
+				"*": "列車"
+			}
+		],
+		"categories": [
+			{
+				"sortkey": "",
+				"*": "Rail_transport"
+			},
+			{
+				"sortkey": "",
+				"hidden": "",
+				"*": "Articles_with_short_description"
+			},
+			{
+				"sortkey": "",
+				"*": "Trains"
+			}
+		]
+	}
+}
+"###;
+
+    const RED_LINKS_PAGE: &str = r###"{
+	"parse": {
+		"title": "Train",
+		"pageid": 29863,
+		"links": [
+			{
+				"ns": 0,
+				"exists": "",
+				"*": "Locomotive"
+			},
+			{
+				"ns": 0,
+				"*": "Trainset of the future"
+			},
+			{
+				"ns": 14,
+				"exists": "",
+				"*": "Category:Rail transport"
+			},
+			{
+				"ns": 14,
+				"*": "Category:Trains of the future"
+			}
+		]
+	}
+}
+"###;
+
+    const WIKITEXT_PAGE: &str = r###"{
+    "parse": {
+        "title": "Train",
+        "pageid": 29886,
+        "wikitext": {
+            "*": "A '''train''' is a vehicle.<ref>{{cite book|title=Trains}}</ref> Trains are hauled by a [[locomotive]]. See also [[Tram|trams]].\n[[Category:Trains]]"
+        }
+    }
+}
+"###;
+
+    const RECENT_CHANGES_PAGE: &str = r###"{
+    "batchcomplete": "",
+    "continue": {
+        "rccontinue": "20240501123000|1001",
+        "continue": "-||"
+    },
+    "query": {
+        "recentchanges": [
+            {
+                "type": "edit",
+                "ns": 0,
+                "title": "Train",
+                "pageid": 29863,
+                "revid": 1187402592,
+                "old_revid": 1187402591,
+                "rcid": 1000,
+                "timestamp": "2024-05-01T12:30:00Z"
+            },
+            {
+                "type": "new",
+                "ns": 0,
+                "title": "Tram stop",
+                "pageid": 29900,
+                "revid": 1187402600,
+                "old_revid": 0,
+                "rcid": 1001,
+                "timestamp": "2024-05-01T12:31:00Z"
+            }
+        ]
+    }
+}
 "###;
 
     const FAIL_PAGE: &str = r###"{
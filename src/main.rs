@@ -7,13 +7,31 @@ extern crate serde_derive;
 extern crate tokio;
 
 mod api;
+mod cache;
+mod crawl;
+mod dataset;
 mod entry;
 mod fetch;
 mod foundation;
 mod opt;
+mod pagerank;
+mod path;
+mod policy;
+mod refresh;
+mod score;
+mod slabs;
+mod snapshot;
+mod sql_dump;
+mod title;
+mod wiki;
+mod wikitext;
 mod worker;
+mod xml_dump;
 
-use std::env;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 use tokio::sync::mpsc;
 
 #[tokio::main]
@@ -37,17 +55,69 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     info!("Getting {} pages deep", opt::OPT.get_depth());
     info!("Caching to {}", opt::OPT.get_cache().to_string_lossy());
+    wiki::init(opt::OPT.get_wikis()?);
+    for wiki in wiki::all() {
+        info!("Serving wiki {} from {}", wiki.id, wiki.domain_name);
+    }
+    policy::init(opt::OPT.get_link_policy()?);
+    for namespace in policy::get().namespaces() {
+        info!(
+            "Holding links into {}{}",
+            policy::namespace_name(namespace.ns),
+            if namespace.traverse {
+                ", traversed"
+            } else {
+                ""
+            }
+        );
+    }
 
     let foundation = foundation::Foundation::new();
     info!("Foundation: {:?}", foundation);
 
-    let (workers, tx_to_workers) = worker::new(&foundation).await;
-    let (fetch_service, tx_to_fetch) = fetch::new(&foundation).await;
+    if let Some(command) = opt::OPT.get_command() {
+        return run_command(command, &foundation).await;
+    }
+
+    let datasets_directory = dataset::get_datasets_directory(&opt::OPT.get_cache());
+    let datasets = opt::OPT
+        .get_datasets()
+        .iter()
+        .map(|name| dataset::Dataset::load(&datasets_directory, name))
+        .collect::<Result<Vec<_>, _>>()?;
+    for dataset in &datasets {
+        info!(
+            "Serving dataset {}, {} pages loaded",
+            dataset.info,
+            dataset.len()
+        );
+    }
+    dataset::init(datasets);
+
+    let (fetch_service, tx_to_fetch) = fetch::new(&foundation).await?;
+    let (workers, tx_to_workers) = worker::new(&foundation, tx_to_fetch.clone()).await;
+    if let Some(snapshot) = opt::OPT.get_snapshot() {
+        snapshot::load(snapshot, &tx_to_workers).await?;
+    }
 
     trace!("Starting API");
-    let api_service = api::new(tx_to_fetch.clone()).await;
+    let api_service = api::new(tx_to_fetch.clone(), tx_to_workers.clone())?;
+    let management_service = api::new_management(tx_to_fetch.clone())?;
     trace!("Started API");
 
+    let refresh_service = opt::OPT.get_refresh_interval().map(|interval| {
+        info!(
+            "Refreshing changed pages every {} seconds",
+            interval.as_secs()
+        );
+        refresh::new(
+            tx_to_fetch.clone(),
+            tx_to_workers.clone(),
+            interval,
+            opt::OPT.get_refresh_source(),
+        )
+    });
+
     // *******
     // Temporary test code starts here
 
@@ -56,16 +126,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         mpsc::Receiver<worker::WorkerResponse>,
     ) = mpsc::channel(1024);
     let request = worker::WorkerCommand::Request {
+        wiki: wiki::default_wiki().id.clone(),
         title: "Railways".to_string(),
         tx_resp: response_tx.clone(),
     };
     let _ = tx_to_workers[0].send(request).await;
 
-    // During testing, let things stabilize for 5 seconds
-    let duration = tokio::time::Duration::new(5, 0);
-    tokio::time::sleep(duration).await;
+    info!("Running. Press Ctrl-C to stop");
+    tokio::signal::ctrl_c().await?;
 
     // Stop long-running tasks
+    api_service.abort();
+    management_service.abort();
+    if let Some(refresh_service) = refresh_service {
+        refresh_service.abort();
+    }
     tx_to_fetch.send(fetch::FetchCommand::End).await.unwrap();
     for tx in tx_to_workers {
         tx.send(worker::WorkerCommand::End).await.unwrap();
@@ -78,6 +153,209 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/* *****************************************************************************************************************
+ *
+ * Run a subcommand in place of the server
+ *
+ *******************************************************************************************************************/
+
+async fn run_command(
+    command: &opt::Command,
+    foundation: &foundation::Foundation,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        opt::Command::Crawl {
+            seed,
+            depth,
+            frontier,
+        } => {
+            let frontier = match frontier {
+                Some(frontier) => frontier.clone(),
+                None => opt::OPT.get_cache().join("crawl_frontier.json"),
+            };
+            if let Some(parent) = frontier.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let clamped = (*depth).clamp(1, 6);
+            if clamped != *depth {
+                warn!("Crawl depth must be 1-6, not {}. Using {}", depth, clamped);
+            }
+            let depth = clamped;
+            info!("Crawling {} pages deep from {:?}", depth, seed);
+
+            let (fetch_service, tx_to_fetch) = fetch::new(foundation).await?;
+            let progress = crawl::crawl(&tx_to_fetch, seed, depth, &frontier).await?;
+            println!("Crawl finished. {}", progress);
+
+            tx_to_fetch.send(fetch::FetchCommand::End).await?;
+            tokio::try_join!(fetch_service)?;
+        }
+        opt::Command::ImportSql {
+            page,
+            pagelinks,
+            redirect,
+            linktarget,
+            output,
+            dataset,
+        } => {
+            let dumps = sql_dump::SqlDumps {
+                page: page.clone(),
+                pagelinks: pagelinks.clone(),
+                redirect: redirect.clone(),
+                linktarget: linktarget.clone(),
+            };
+            let summary = sql_dump::import(&dumps, &import_path(output, dataset)?)?;
+            println!("Import finished. {}", summary);
+            if let Some(name) = dataset {
+                save_dataset(name, page, None, summary.entries)?;
+            }
+        }
+        opt::Command::ImportXml {
+            dump,
+            output,
+            dataset,
+            as_of,
+        } => {
+            let as_of_time = match as_of {
+                Some(as_of) => Some(
+                    dataset::read_as_of(as_of)
+                        .ok_or_else(|| format!("Unable to read --as_of {}", as_of))?,
+                ),
+                None => None,
+            };
+            let summary = xml_dump::import(dump, &import_path(output, dataset)?, as_of_time)?;
+            println!("Import finished. {}", summary);
+            if let Some(name) = dataset {
+                save_dataset(name, dump, as_of.clone(), summary.entries)?;
+            }
+        }
+        opt::Command::DiffDatasets {
+            from,
+            to,
+            page,
+            pair,
+            limit,
+        } => {
+            let request = dataset::DiffRequest {
+                pages: page.clone(),
+                pairs: pair
+                    .iter()
+                    .map(|pair| {
+                        dataset::read_pair(pair).ok_or_else(|| {
+                            format!("--pair must be two titles separated by >, not {}", pair)
+                        })
+                    })
+                    .collect::<Result<_, _>>()?,
+                limit: *limit,
+            };
+            let directory = dataset::get_datasets_directory(&opt::OPT.get_cache());
+            let from = dataset::Dataset::load(&directory, from)?;
+            let to = dataset::Dataset::load(&directory, to)?;
+            let wiki = wiki::find(wiki::all(), &from.info.wiki)?;
+            let diff = dataset::diff(&from, &to, wiki, &request).await?;
+            println!("{}", serde_json::to_string_pretty(&diff)?);
+        }
+        opt::Command::Cache { action } => {
+            let wiki = wiki::default_wiki();
+            cache::move_single_wiki_cache(&opt::OPT.get_cache(), wiki)?;
+            let directory = cache::get_pack_directory(&opt::OPT.get_cache(), wiki);
+            let mut store = cache::PackStore::open(&directory)?;
+            match action {
+                opt::CacheCommand::Migrate { from, remove } => {
+                    let from = from.clone().unwrap_or_else(|| opt::OPT.get_cache());
+                    let summary = cache::migrate(&from, &mut store, *remove)?;
+                    println!("Migration finished. {}", summary);
+                }
+                opt::CacheCommand::Verify { refetch } => {
+                    let quarantine = cache::get_quarantine_directory(&opt::OPT.get_cache(), wiki);
+                    let summary = store.verify(&quarantine)?;
+                    println!("Verification finished. {}", summary);
+
+                    // The fetch service opens the store itself
+                    drop(store);
+                    if *refetch && !summary.titles.is_empty() {
+                        let refetched = refetch_pages(&summary.titles, foundation).await?;
+                        println!(
+                            "Pulled {} of {} quarantined pages from Wikipedia",
+                            refetched,
+                            summary.titles.len()
+                        );
+                    }
+                }
+                opt::CacheCommand::Compact => {
+                    let reclaimed = store.compact()?;
+                    store.flush()?;
+                    println!("Compaction finished. {} bytes reclaimed", reclaimed);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// The snapshot file written by an import: the output, or the snapshot of the dataset
+fn import_path(
+    output: &Option<PathBuf>,
+    dataset: &Option<String>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    match (output, dataset) {
+        (_, Some(name)) => {
+            let directory = dataset::get_datasets_directory(&opt::OPT.get_cache());
+            std::fs::create_dir_all(&directory)?;
+            Ok(dataset::get_snapshot_path(&directory, name)?)
+        }
+        (Some(output), None) => Ok(output.clone()),
+        (None, None) => Err("--output or --dataset is required".into()),
+    }
+}
+
+// Record the dataset written by an import. Without as_of, the date of the dataset is read from the name of the dump
+fn save_dataset(
+    name: &str,
+    dump: &Path,
+    as_of: Option<String>,
+    entries: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let info = dataset::DatasetInfo {
+        name: name.to_string(),
+        wiki: wiki::default_wiki().id.clone(),
+        as_of: as_of.or_else(|| dataset::dump_date(dump)),
+        source: dump
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        entries,
+    };
+    info.save(&dataset::get_datasets_directory(&opt::OPT.get_cache()))?;
+    println!("Saved dataset {}", info);
+    Ok(())
+}
+
+// Fetch each page again, returning the number of pages fetched
+async fn refetch_pages(
+    titles: &[String],
+    foundation: &foundation::Foundation,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let (fetch_service, tx_to_fetch) = fetch::new(foundation).await?;
+    let (tx, mut rx) = mpsc::channel(1);
+    let mut refetched = 0;
+    for title in titles {
+        tx_to_fetch
+            .send(fetch::FetchCommand::Get {
+                wiki: wiki::default_wiki().id.clone(),
+                title: title.clone(),
+                tx: tx.clone(),
+            })
+            .await?;
+        if let Some(Ok(_)) = rx.recv().await {
+            refetched += 1;
+        }
+    }
+    tx_to_fetch.send(fetch::FetchCommand::End).await?;
+    tokio::try_join!(fetch_service)?;
+    Ok(refetched)
+}
+
 /* *****************************************************************************************************************
  *
  * Tests
@@ -4,12 +4,17 @@
  *
  *************************************************************************************************/
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::{
     cmp::{max, min},
     path::PathBuf,
+    time::Duration,
 };
 
+use crate::policy::{LinkPolicy, PolicyError};
+use crate::refresh;
+use crate::wiki::{self, Wiki, WikiError};
+
 #[derive(Parser, Debug)]
 #[structopt(name = "six_degrees")]
 pub struct Opt {
@@ -56,12 +61,36 @@ pub struct Opt {
     )]
     domain_name: String,
 
+    // Further wikis to serve alongside the wiki named by domain_name. See wiki.rs
+    #[structopt(
+        long,
+        help = "Serve a further wiki, as ID or ID=DOMAIN_NAME",
+        long_help = "Serve a further wiki alongside the wiki named by domain_name. Give a language code, such as de, to serve that edition of Wikipedia, or ID=DOMAIN_NAME for any other wiki, such as enwikt=https://en.wiktionary.org/. May be repeated. API requests choose the wiki with ?wiki=ID"
+    )]
+    wiki: Vec<String>,
+
+    // Per-wiki rate limit for requests
+    #[structopt(
+        long = "wiki_rate_limit",
+        help = "Rate limit for one wiki, as ID=REQUESTS_PER_SECOND",
+        long_help = "Maximum number of requests per second sent to one wiki, as ID=REQUESTS_PER_SECOND. Wikis without a limit of their own use rate_limit. May be repeated"
+    )]
+    wiki_rate_limit: Vec<String>,
+
+    // Links held beyond articles
+    #[structopt(
+        long = "link_namespace",
+        help = "Hold links into this namespace, as NAMESPACE or NAMESPACE=traverse",
+        long_help = "Hold links into this namespace, named as Category or 14, as well as links into articles. Add =traverse to let path search follow the links. May be repeated"
+    )]
+    link_namespace: Vec<String>,
+
     // Management address:port
     #[structopt(
         short,
         long,
         help = "Manage the server on on this address:port.",
-        long_help = "Manage the server on this address:port.  Address will default to localhost. Port will default to 6457. The colon is a required attribute to specify the port. IPv6 addresses must be surrounded in square brackets following the recommendations in RFC2732"
+        long_help = "Manage the server on this address:port.  Address will default to localhost. Port will default to 6458. The colon is a required attribute to specify the port. IPv6 addresses must be surrounded in square brackets following the recommendations in RFC2732"
     )]
     management: Option<String>,
 
@@ -83,16 +112,262 @@ pub struct Opt {
     )]
     workers: Option<u32>,
 
-    // Fake-hub identifier
-    // TODO: Implement the code to detect a fake-hub
+    // Fake-hub identifier, used to rank paths
     #[structopt(
         short,
         long,
         default_value = "200",
         help = "Threshold of inbound to outbound link ratio to detect fake-hub pages",
-        long_help = "The percentage of inbound to outbound links that is used to determine whether a page should be considered a fake-hub"
+        long_help = "The percentage of inbound to outbound links that is used to determine whether a page should be considered a fake-hub. Paths ranked with ?rank=true are penalised for each fake-hub they pass through. 0 disables the penalty"
+    )]
+    fake_hub: u32,
+
+    // Rate limit for requests to wikipedia
+    // See https://www.mediawiki.org/wiki/API:Etiquette
+    #[structopt(
+        long = "rate_limit",
+        default_value = "10",
+        help = "Maximum number of requests per second sent to Wikipedia",
+        long_help = "Maximum number of requests per second sent to Wikipedia. Pages found in the local cache do not count towards the limit. 0 disables the limit"
+    )]
+    rate_limit: u32,
+
+    // Keep the raw API payloads in the cache, alongside the parsed links
+    #[structopt(
+        long = "cache_raw",
+        help = "Keep the raw Wikipedia payloads in the cache",
+        long_help = "Keep the raw Wikipedia payloads in the cache, alongside the parsed links. The payloads are not read by six_degrees, but are useful for debugging"
+    )]
+    cache_raw: bool,
+
+    // Limit the size of the cache
+    #[structopt(
+        long = "cache_max_size",
+        default_value = "0",
+        help = "Maximum size of the page cache of each wiki in MB",
+        long_help = "Maximum size of the page cache of each wiki in MB. Once the cache grows beyond this size, the least recently used pages are evicted. Pages that are hubs in the in-memory graph are never evicted. 0 removes the limit"
+    )]
+    cache_max_size: u64,
+
+    // Poll the wikis for changed pages
+    #[structopt(
+        long = "refresh_interval",
+        default_value = "0",
+        help = "Seconds between polls for pages changed on the wikis",
+        long_help = "Seconds between polls for pages changed on the wikis. Changed pages that are in the page cache are pulled again, and applied to the workers if they hold them. 0 disables the refresh"
+    )]
+    refresh_interval: u64,
+
+    // Read changed pages from a file rather than the wiki API
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Read changed pages from a file of recent changes",
+        long_help = "Read changed pages from a file of recent changes, in place of list=recentchanges. The file holds one JSON change per line, with the title, revid and timestamp of the change, and the wiki if it is not the default wiki"
+    )]
+    recentchanges: Option<PathBuf>,
+
+    // Datasets served alongside the live graph
+    #[structopt(
+        long,
+        help = "Serve a dataset, by name. May be repeated",
+        long_help = "Serve a dataset, by name, for path searches with ?as_of=<name>. Datasets are built from dated dumps with the --dataset option of the import-sql and import-xml subcommands, and are held in the datasets directory of the cache. May be repeated"
+    )]
+    dataset: Vec<String>,
+
+    // Snapshot to load into the workers at startup
+    #[structopt(
+        short,
+        long,
+        parse(from_os_str),
+        help = "Load a snapshot into the workers at startup",
+        long_help = "Load a snapshot into the workers at startup. Snapshots are created from Wikipedia dumps with the import-sql and import-xml subcommands"
+    )]
+    snapshot: Option<PathBuf>,
+
+    // Optional subcommand. If no subcommand is provided, six_degrees runs as a server
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    // Pre-warm the cache
+    #[clap(
+        about = "Pre-warm the cache by walking outbound links breadth-first from one or more seed titles"
+    )]
+    Crawl {
+        #[clap(
+            long,
+            required = true,
+            help = "Title of a page from which the crawl starts. May be repeated"
+        )]
+        seed: Vec<String>,
+
+        #[clap(
+            short,
+            long,
+            default_value = "2",
+            help = "Depth of the crawl",
+            long_help = "The depth of the crawl below each seed page. 1 = the seed pages only; 2 = the seed pages, plus all those directly referenced by those pages, etc. The maximum depth is 6"
+        )]
+        depth: u32,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "File used to persist the crawl frontier",
+            long_help = "File used to persist the crawl frontier. If the file exists when the crawl starts, the crawl resumes from the saved frontier and the seeds are ignored. Defaults to crawl_frontier.json in the cache directory"
+        )]
+        frontier: Option<PathBuf>,
+    },
+
+    // Build a snapshot from the Wikipedia SQL dumps
+    #[clap(
+        about = "Build a snapshot from the Wikipedia page, pagelinks, redirect and linktarget SQL dumps",
+        long_about = "Build a snapshot from the Wikipedia page, pagelinks, redirect and linktarget SQL dumps. Dumps are available from https://dumps.wikimedia.org/, and may be gzip compressed"
     )]
-    fake_hub: i32,
+    ImportSql {
+        #[clap(long, parse(from_os_str), help = "The page table dump")]
+        page: PathBuf,
+
+        #[clap(long, parse(from_os_str), help = "The pagelinks table dump")]
+        pagelinks: PathBuf,
+
+        #[clap(long, parse(from_os_str), help = "The redirect table dump")]
+        redirect: PathBuf,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "The linktarget table dump. Required for dumps from MediaWiki 1.41 onwards"
+        )]
+        linktarget: Option<PathBuf>,
+
+        #[clap(
+            short,
+            long,
+            parse(from_os_str),
+            required_unless_present = "dataset",
+            help = "The snapshot file to create"
+        )]
+        output: Option<PathBuf>,
+
+        #[clap(
+            long,
+            conflicts_with = "output",
+            help = "Save the snapshot as a named dataset, in place of --output",
+            long_help = "Save the snapshot as a named dataset in the datasets directory of the cache, in place of --output. The date of the dataset is read from the name of the page dump, such as enwiki-20150101-page.sql.gz"
+        )]
+        dataset: Option<String>,
+    },
+
+    // Build a snapshot from a MediaWiki XML page dump
+    #[clap(
+        about = "Build a snapshot from a MediaWiki XML page dump",
+        long_about = "Build a snapshot from a MediaWiki XML page dump, such as pages-articles.xml.bz2. Dumps are available from https://dumps.wikimedia.org/, and may be bzip2 or gzip compressed"
+    )]
+    ImportXml {
+        #[clap(long, parse(from_os_str), help = "The XML page dump")]
+        dump: PathBuf,
+
+        #[clap(
+            short,
+            long,
+            parse(from_os_str),
+            required_unless_present = "dataset",
+            help = "The snapshot file to create"
+        )]
+        output: Option<PathBuf>,
+
+        #[clap(
+            long,
+            conflicts_with = "output",
+            help = "Save the snapshot as a named dataset, in place of --output",
+            long_help = "Save the snapshot as a named dataset in the datasets directory of the cache, in place of --output. The date of the dataset is --as_of, or is read from the name of the dump, such as enwiki-20150101-pages-articles.xml.bz2"
+        )]
+        dataset: Option<String>,
+
+        #[clap(
+            long,
+            help = "Read each page as it was at this time, such as 2015-01-01 or 2015-01-01T12:00:00Z",
+            long_help = "Read each page as it was at this time, such as 2015-01-01 or 2015-01-01T12:00:00Z, from the last revision made at or before it. Use with a history dump (pages-meta-history), which holds every revision of each page. Pages with no revision by then are left out"
+        )]
+        as_of: Option<String>,
+    },
+
+    // Compare two datasets
+    #[clap(
+        about = "Compare two datasets, giving the pages added and removed, the links gained and lost by pages, and the change in the length of the shortest path between pairs of pages",
+        long_about = "Compare two datasets of the same wiki, built with the --dataset option of import-sql or import-xml, giving the pages added and removed, the links gained and lost by pages, and the change in the length of the shortest path between pairs of pages. The diff is written to standard output as JSON"
+    )]
+    DiffDatasets {
+        #[clap(long, help = "The earlier dataset")]
+        from: String,
+
+        #[clap(long, help = "The later dataset")]
+        to: String,
+
+        #[clap(
+            long,
+            help = "Title of a page whose links are compared. May be repeated"
+        )]
+        page: Vec<String>,
+
+        #[clap(
+            long,
+            help = "Two titles separated by >, such as Train>Tram, whose shortest paths are compared. May be repeated"
+        )]
+        pair: Vec<String>,
+
+        #[clap(
+            long,
+            default_value = "100",
+            help = "Most pages listed as added, and as removed"
+        )]
+        limit: usize,
+    },
+
+    // Maintain the page cache
+    #[clap(about = "Maintain the page cache")]
+    Cache {
+        #[clap(subcommand)]
+        action: CacheCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    // Copy pages from the one-file-per-title cache into the pack files
+    #[clap(
+        about = "Copy pages cached by earlier versions of six_degrees into the pack files",
+        long_about = "Copy pages cached by earlier versions of six_degrees, held as one JSON file per title in a three-level directory tree, into the pack files"
+    )]
+    Migrate {
+        #[clap(
+            long,
+            parse(from_os_str),
+            help = "Directory holding the earlier cache. Defaults to the cache directory"
+        )]
+        from: Option<PathBuf>,
+
+        #[clap(long, help = "Remove each file once it has been migrated")]
+        remove: bool,
+    },
+
+    // Check every cached page
+    #[clap(
+        about = "Check every page in the cache, and quarantine pages that cannot be read",
+        long_about = "Check every page in the cache, and move pages that fail their checksum or cannot be read to the quarantine directory of the cache"
+    )]
+    Verify {
+        #[clap(long, help = "Pull each quarantined page from Wikipedia again")]
+        refetch: bool,
+    },
+
+    // Reclaim the space held by superseded records
+    #[clap(about = "Reclaim the space held by superseded pages in the pack files")]
+    Compact,
 }
 
 lazy_static! {
@@ -110,14 +385,31 @@ impl Opt {
             self.cache.clone()
         }
     }
+    pub fn get_cache_raw(&self) -> bool {
+        self.cache_raw
+    }
+    pub fn get_cache_max_size(&self) -> Option<u64> {
+        match self.cache_max_size {
+            0 => None,
+            megabytes => Some(megabytes * 1024 * 1024),
+        }
+    }
     pub fn get_depth(&self) -> u32 {
         max(1, min(self.depth, 6))
     }
     pub fn get_api(&self) -> &Option<String> {
         &self.api
     }
-    pub fn get_domain_name(&self) -> &str {
-        &self.domain_name
+    pub fn get_wikis(&self) -> Result<Vec<Wiki>, WikiError> {
+        wiki::from_options(
+            &self.domain_name,
+            &self.wiki,
+            &self.wiki_rate_limit,
+            self.rate_limit,
+        )
+    }
+    pub fn get_link_policy(&self) -> Result<LinkPolicy, PolicyError> {
+        LinkPolicy::from_options(&self.link_namespace)
     }
     pub fn get_management(&self) -> &Option<String> {
         &self.management
@@ -128,6 +420,30 @@ impl Opt {
     pub fn get_cores(&self) -> &Option<u64> {
         &self.cores
     }
+    pub fn get_fake_hub(&self) -> u32 {
+        self.fake_hub
+    }
+    pub fn get_refresh_interval(&self) -> Option<Duration> {
+        match self.refresh_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+    pub fn get_refresh_source(&self) -> refresh::Source {
+        match &self.recentchanges {
+            Some(path) => refresh::Source::File(path.clone()),
+            None => refresh::Source::Api,
+        }
+    }
+    pub fn get_datasets(&self) -> &[String] {
+        &self.dataset
+    }
+    pub fn get_snapshot(&self) -> &Option<PathBuf> {
+        &self.snapshot
+    }
+    pub fn get_command(&self) -> &Option<Command> {
+        &self.command
+    }
     pub fn get_worker_count(&self) -> Option<u32> {
         match self.workers {
             Some(workers) => Some(min(workers, (u16::MAX as u32) + 1)),
@@ -0,0 +1,553 @@
+/* *****************************************************************************************************************
+ *
+ * PageRank
+ * --------
+ *
+ * Compute the PageRank of the pages held by the workers, as rounds of messages between the workers. Each worker ranks
+ * the pages it holds, and the coordinator, compute, drives the rounds and sums what the workers report. Stubs, pages
+ * that are only known from back-links, are not ranked.
+ *
+ *    Start      each worker resets its state, and reports the pages it holds, and whether it holds the seed
+ *    Init       each page is given its starting rank: 1/N, or with a seed, 1 for the seed and 0 for every other page
+ *    then, in each round
+ *    Scatter    each page shares its rank equally between the pages it links to. The shares are sent as Contribute
+ *               to the workers that hold the pages linked to, and each worker reports the rank of its pages with no
+ *               links, which is not passed on
+ *    Gather     each worker reports the shares sent to pages it does not hold, which are also not passed on
+ *    Apply      each page takes its new rank
+ *                  rank = (1 - damping) * p + damping * (shares + lost * p)
+ *               where lost is the rank not passed on in the round, and p is 1/N, or with a seed, 1 for the seed and
+ *               0 for every other page. Each worker reports how far the ranks of its pages moved
+ *    until the ranks move by less than TOLERANCE in a round, or the most rounds have been run
+ *    Finish     each worker reports its best ranked pages, and without a seed, stores the rank of each page in its
+ *               Entry, relative to the mean, so 1 is an average page
+ *
+ * A worker sends its shares from a task of its own, and reports to the coordinator once they are all sent, so a worker
+ * never waits on a full channel to another worker that is itself waiting to send. Each phase is sent to the workers
+ * once every worker has reported on the phase before, and channels deliver in order, so every Contribute of a round
+ * is handled before the Gather.
+ *
+ * With a seed, the ranks are personalised: the rank lost in each round returns to the seed, so the ranks measure how
+ * close each page is to the seed. Personalised ranks are reported, but not stored. One computation runs at a time.
+ *
+ *******************************************************************************************************************/
+
+use std::{collections::HashMap, fmt};
+
+use tokio::sync::{mpsc, Mutex};
+
+use crate::entry::{Digest, Entry};
+use crate::worker::{self, TxCommands, WorkerCommand};
+
+pub static DEFAULT_DAMPING: f64 = 0.85;
+pub static DEFAULT_ITERATIONS: usize = 50;
+pub static DEFAULT_TOP: usize = 20;
+// Sum of the moves of the ranks of every page in a round, below which the ranks have converged
+static TOLERANCE: f64 = 1e-6;
+
+static RUNNING: Mutex<()> = Mutex::const_new(());
+
+// ***********************************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RankOptions {
+    pub damping: f64,
+    // Most rounds run
+    pub iterations: usize,
+    // Personalise the ranks to this page
+    pub seed: Option<Digest>,
+    // Pages reported, best ranked first
+    pub top: usize,
+}
+
+impl Default for RankOptions {
+    fn default() -> Self {
+        RankOptions {
+            damping: DEFAULT_DAMPING,
+            iterations: DEFAULT_ITERATIONS,
+            seed: None,
+            top: DEFAULT_TOP,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct RankedPage {
+    pub title: String,
+    pub rank: f64,
+    // Rank relative to the mean, so 1 is an average page
+    pub relative: f64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Ranking {
+    // Pages ranked
+    pub pages: usize,
+    pub iterations: usize,
+    pub converged: bool,
+    // Sum of the moves of the ranks in the last round
+    pub delta: f64,
+    // The ranks were stored in each Entry
+    pub stored: bool,
+    pub top: Vec<RankedPage>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RankError {
+    Running,
+    Stopped,
+    NoPages,
+    SeedNotHeld,
+}
+
+impl fmt::Display for RankError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RankError::Running => write!(f, "PageRank is already being computed"),
+            RankError::Stopped => write!(f, "The workers have stopped"),
+            RankError::NoPages => write!(f, "The workers hold no pages"),
+            RankError::SeedNotHeld => write!(f, "The seed page is not held by the workers"),
+        }
+    }
+}
+
+impl std::error::Error for RankError {}
+
+/// The phases of a computation, sent to each worker as WorkerCommand::Rank
+#[derive(Debug)]
+pub enum RankCommand {
+    Start {
+        seed: Option<Digest>,
+        damping: f64,
+        // Pages held, and whether the seed is one of them
+        tx: mpsc::Sender<(usize, bool)>,
+    },
+    Init {
+        pages: usize,
+        tx: mpsc::Sender<()>,
+    },
+    Scatter {
+        // Rank of the pages with no links
+        tx: mpsc::Sender<f64>,
+    },
+    Contribute(Vec<(Digest, f64)>),
+    Gather {
+        // Shares sent to pages not held
+        tx: mpsc::Sender<f64>,
+    },
+    Apply {
+        lost: f64,
+        // Sum of the moves of the ranks
+        tx: mpsc::Sender<f64>,
+    },
+    Finish {
+        store: bool,
+        top: usize,
+        tx: mpsc::Sender<Vec<RankedPage>>,
+    },
+}
+
+impl fmt::Display for RankCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RankCommand::Start { .. } => write!(f, "Start"),
+            RankCommand::Init { pages, .. } => write!(f, "Init:: {} pages", pages),
+            RankCommand::Scatter { .. } => write!(f, "Scatter"),
+            RankCommand::Contribute(shares) => write!(f, "Contribute:: {} shares", shares.len()),
+            RankCommand::Gather { .. } => write!(f, "Gather"),
+            RankCommand::Apply { lost, .. } => write!(f, "Apply:: lost {}", lost),
+            RankCommand::Finish { .. } => write!(f, "Finish"),
+        }
+    }
+}
+
+/// The state of a computation held by one worker
+#[derive(Debug, Default)]
+pub struct RankState {
+    seed: Option<Digest>,
+    damping: f64,
+    pages: usize,
+    ranks: HashMap<Digest, f64>,
+    shares: HashMap<Digest, f64>,
+}
+
+impl RankState {
+    pub fn new(seed: Option<Digest>, damping: f64) -> RankState {
+        RankState {
+            seed,
+            damping,
+            ..Default::default()
+        }
+    }
+
+    // Share of the rank lost in a round that goes to the page
+    fn personal(&self, digest: Digest) -> f64 {
+        match self.seed {
+            Some(seed) if seed == digest => 1.0,
+            Some(_) => 0.0,
+            None => 1.0 / self.pages as f64,
+        }
+    }
+
+    /// Give each page its starting rank. `pages` is the number held by every worker
+    pub fn init<'a>(&mut self, held: impl Iterator<Item = &'a Entry>, pages: usize) {
+        self.pages = pages;
+        self.ranks = held
+            .map(|entry| (entry.digest(), self.personal(entry.digest())))
+            .collect();
+    }
+
+    /// The shares of the rank of each page, grouped by the worker that holds the pages linked to, and the rank of
+    /// the pages with no links
+    pub fn scatter<'a>(
+        &self,
+        held: impl Iterator<Item = &'a Entry>,
+        worker_count: usize,
+    ) -> (HashMap<usize, Vec<(Digest, f64)>>, f64) {
+        let mut by_worker: HashMap<usize, Vec<(Digest, f64)>> = HashMap::new();
+        let mut dangling = 0.0;
+        for entry in held {
+            let rank = self.ranks.get(&entry.digest()).copied().unwrap_or_default();
+            let links = entry.get_outbound();
+            if links.is_empty() {
+                dangling += rank;
+                continue;
+            }
+            let share = rank / links.len() as f64;
+            for link in links {
+                by_worker
+                    .entry(worker::get_worker_id_for(*link, worker_count))
+                    .or_default()
+                    .push((*link, share));
+            }
+        }
+        (by_worker, dangling)
+    }
+
+    pub fn contribute(&mut self, shares: Vec<(Digest, f64)>) {
+        for (digest, share) in shares {
+            *self.shares.entry(digest).or_default() += share;
+        }
+    }
+
+    /// The shares sent to pages that are not ranked
+    pub fn gather(&self) -> f64 {
+        self.shares
+            .iter()
+            .filter(|(digest, _)| !self.ranks.contains_key(*digest))
+            .map(|(_, share)| share)
+            .sum()
+    }
+
+    /// Take the new rank of each page, returning the sum of the moves of the ranks
+    pub fn apply(&mut self, lost: f64) -> f64 {
+        let mut delta = 0.0;
+        let shares = std::mem::take(&mut self.shares);
+        let digests: Vec<Digest> = self.ranks.keys().copied().collect();
+        for digest in digests {
+            let personal = self.personal(digest);
+            let share = shares.get(&digest).copied().unwrap_or_default();
+            let rank = (1.0 - self.damping) * personal + self.damping * (share + lost * personal);
+            let old = self.ranks.insert(digest, rank).unwrap_or_default();
+            delta += (rank - old).abs();
+        }
+        delta
+    }
+
+    pub fn get(&self, digest: Digest) -> Option<f64> {
+        self.ranks.get(&digest).copied()
+    }
+
+    /// Rank relative to the mean, so 1 is an average page
+    pub fn relative(&self, digest: Digest) -> Option<f64> {
+        self.get(digest).map(|rank| rank * self.pages as f64)
+    }
+
+    /// The best ranked pages
+    pub fn top<'a>(&self, held: impl Iterator<Item = &'a Entry>, top: usize) -> Vec<RankedPage> {
+        let mut pages: Vec<RankedPage> = held
+            .filter_map(|entry| {
+                Some(RankedPage {
+                    title: entry.get_title().to_string(),
+                    rank: self.get(entry.digest())?,
+                    relative: self.relative(entry.digest())?,
+                })
+            })
+            .collect();
+        sort(&mut pages);
+        pages.truncate(top);
+        pages
+    }
+}
+
+// Best ranked first, then by title
+fn sort(pages: &mut [RankedPage]) {
+    pages.sort_by(|a, b| {
+        b.rank
+            .total_cmp(&a.rank)
+            .then_with(|| a.title.cmp(&b.title))
+    });
+}
+
+/* *****************************************************************************************************************
+ *
+ * Coordinator
+ *
+ *******************************************************************************************************************/
+
+/// Compute the PageRank of the pages held by the workers. Without a seed, the ranks are stored in each Entry
+pub async fn compute(
+    tx_commands: &TxCommands,
+    options: &RankOptions,
+) -> Result<Ranking, RankError> {
+    let _running = RUNNING.try_lock().map_err(|_| RankError::Running)?;
+    let (seed, damping) = (options.seed, options.damping);
+
+    let held = broadcast(tx_commands, |tx| RankCommand::Start { seed, damping, tx }).await?;
+    let pages: usize = held.iter().map(|(pages, _)| pages).sum();
+    if pages == 0 {
+        return Err(RankError::NoPages);
+    }
+    if seed.is_some() && !held.iter().any(|(_, seed)| *seed) {
+        return Err(RankError::SeedNotHeld);
+    }
+    broadcast(tx_commands, |tx| RankCommand::Init { pages, tx }).await?;
+
+    let (mut iterations, mut delta) = (0, f64::MAX);
+    while iterations < options.iterations && delta >= TOLERANCE {
+        let dangling: f64 = broadcast(tx_commands, |tx| RankCommand::Scatter { tx })
+            .await?
+            .iter()
+            .sum();
+        let leaked: f64 = broadcast(tx_commands, |tx| RankCommand::Gather { tx })
+            .await?
+            .iter()
+            .sum();
+        let lost = dangling + leaked;
+        delta = broadcast(tx_commands, |tx| RankCommand::Apply { lost, tx })
+            .await?
+            .iter()
+            .sum();
+        iterations += 1;
+        trace!("PageRank round {}: delta {}", iterations, delta);
+    }
+
+    let (store, top) = (seed.is_none(), options.top);
+    let mut pages_top: Vec<RankedPage> =
+        broadcast(tx_commands, |tx| RankCommand::Finish { store, top, tx })
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+    sort(&mut pages_top);
+    pages_top.truncate(top);
+    Ok(Ranking {
+        pages,
+        iterations,
+        converged: delta < TOLERANCE,
+        delta,
+        stored: store,
+        top: pages_top,
+    })
+}
+
+// Send a phase to every worker, and wait for every worker to report on it
+async fn broadcast<T>(
+    tx_commands: &TxCommands,
+    command: impl Fn(mpsc::Sender<T>) -> RankCommand,
+) -> Result<Vec<T>, RankError> {
+    let (tx, mut rx) = mpsc::channel(tx_commands.len());
+    for tx_command in tx_commands {
+        tx_command
+            .send(WorkerCommand::Rank(command(tx.clone())))
+            .await
+            .map_err(|_| RankError::Stopped)?;
+    }
+    drop(tx);
+    let mut reports = Vec::with_capacity(tx_commands.len());
+    while let Some(report) = rx.recv().await {
+        reports.push(report);
+    }
+    match reports.len() == tx_commands.len() {
+        true => Ok(reports),
+        false => Err(RankError::Stopped),
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::foundation;
+
+    // Pages as (title, links)
+    fn entries(pages: &[(&str, &[&str])]) -> Vec<Entry> {
+        pages
+            .iter()
+            .map(|(title, links)| {
+                let outbound = links.iter().map(|link| Entry::get_digest(link)).collect();
+                Entry::new(title.to_string(), outbound)
+            })
+            .collect()
+    }
+
+    // PageRank by power iteration over the whole graph, to check the rounds between workers against
+    fn expected(
+        entries: &[Entry],
+        seed: Option<Digest>,
+        iterations: usize,
+    ) -> HashMap<Digest, f64> {
+        let pages = entries.len() as f64;
+        let personal = |digest: Digest| match seed {
+            Some(seed) if seed == digest => 1.0,
+            Some(_) => 0.0,
+            None => 1.0 / pages,
+        };
+        let mut ranks: HashMap<Digest, f64> = entries
+            .iter()
+            .map(|entry| (entry.digest(), personal(entry.digest())))
+            .collect();
+        for _ in 0..iterations {
+            let mut shares: HashMap<Digest, f64> = HashMap::new();
+            let mut lost = 0.0;
+            for entry in entries {
+                let rank = ranks[&entry.digest()];
+                if entry.get_outbound().is_empty() {
+                    lost += rank;
+                }
+                for link in entry.get_outbound() {
+                    let share = rank / entry.get_outbound().len() as f64;
+                    match ranks.contains_key(link) {
+                        true => *shares.entry(*link).or_default() += share,
+                        false => lost += share,
+                    }
+                }
+            }
+            for (digest, rank) in ranks.iter_mut() {
+                let share = shares.get(digest).copied().unwrap_or_default();
+                *rank = (1.0 - DEFAULT_DAMPING) * personal(*digest)
+                    + DEFAULT_DAMPING * (share + lost * personal(*digest));
+            }
+        }
+        ranks
+    }
+
+    fn graph() -> Vec<Entry> {
+        // Steam engine has no links, and Horse is linked to but not held
+        entries(&[
+            ("Train", &["Locomotive", "Tram", "Horse"]),
+            ("Locomotive", &["Steam engine", "Train"]),
+            ("Tram", &["Train"]),
+            ("Light rail", &["Tram", "Train"]),
+            ("Steam engine", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_rank_state() {
+        let entries = graph();
+        let mut state = RankState::new(None, DEFAULT_DAMPING);
+        state.init(entries.iter(), entries.len());
+        assert_eq!(state.get(Entry::get_digest("Train")), Some(0.2));
+        assert_eq!(state.relative(Entry::get_digest("Train")), Some(1.0));
+
+        // A single worker holds every page
+        for _ in 0..30 {
+            let (mut by_worker, dangling) = state.scatter(entries.iter(), 1);
+            assert_eq!(by_worker.len(), 1);
+            state.contribute(by_worker.remove(&0).unwrap());
+            let leaked = state.gather();
+            state.apply(dangling + leaked);
+        }
+        let ranks = expected(&entries, None, 30);
+        for entry in &entries {
+            let rank = state.get(entry.digest()).unwrap();
+            assert!(
+                (rank - ranks[&entry.digest()]).abs() < 1e-12,
+                "{}",
+                entry.get_title()
+            );
+        }
+        let total: f64 = entries
+            .iter()
+            .map(|entry| state.get(entry.digest()).unwrap())
+            .sum();
+        assert!((total - 1.0).abs() < 1e-9);
+
+        let top = state.top(entries.iter(), 2);
+        assert_eq!(top[0].title, "Train");
+        assert_eq!(top.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compute() {
+        let (tx_to_fetch, _rx_by_fetch) = mpsc::channel(1024);
+        let (join_handles, tx_commands) =
+            worker::new(&foundation::tests::get_test_foundation(), tx_to_fetch).await;
+        let entries = graph();
+        for entry in &entries {
+            worker::update(entry.clone(), &tx_commands).await.unwrap();
+        }
+
+        let ranking = compute(&tx_commands, &RankOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(ranking.pages, 5);
+        assert!(ranking.converged);
+        assert!(ranking.stored);
+        let ranks = expected(&entries, None, ranking.iterations);
+        let train = Entry::get_digest("Train");
+        assert_eq!(ranking.top[0].title, "Train");
+        assert!((ranking.top[0].rank - ranks[&train]).abs() < 1e-9);
+        assert!((ranking.top[0].relative - ranks[&train] * 5.0).abs() < 1e-9);
+
+        // The relative rank is stored in the entry
+        let (tx, mut rx) = mpsc::channel(1);
+        tx_commands[worker::get_worker_id_for(train, tx_commands.len())]
+            .send(WorkerCommand::Get { digest: train, tx })
+            .await
+            .unwrap();
+        let entry = rx.recv().await.unwrap().unwrap();
+        assert!((entry.get_pagerank().unwrap() - ranks[&train] * 5.0).abs() < 1e-9);
+
+        // Personalised to Light rail, which nothing links to
+        let light_rail = Entry::get_digest("Light rail");
+        let options = RankOptions {
+            seed: Some(light_rail),
+            iterations: 20,
+            top: 10,
+            ..Default::default()
+        };
+        let ranking = compute(&tx_commands, &options).await.unwrap();
+        assert!(!ranking.stored);
+        let ranks = expected(&entries, Some(light_rail), ranking.iterations);
+        for page in &ranking.top {
+            let rank = ranks[&Entry::get_digest(&page.title)];
+            assert!((page.rank - rank).abs() < 1e-9, "{}", page.title);
+        }
+        assert_eq!(ranking.top.len(), 5);
+
+        let options = RankOptions {
+            seed: Some(Entry::get_digest("Horse")),
+            ..Default::default()
+        };
+        assert_eq!(
+            compute(&tx_commands, &options).await,
+            Err(RankError::SeedNotHeld)
+        );
+
+        for tx_command in &tx_commands {
+            tx_command.send(WorkerCommand::End).await.unwrap();
+        }
+        worker::shut_down(join_handles).await.unwrap();
+        assert_eq!(
+            compute(&tx_commands, &RankOptions::default()).await,
+            Err(RankError::Stopped)
+        );
+    }
+}
@@ -0,0 +1,1976 @@
+/* *****************************************************************************************************************
+ *
+ * Path
+ * ----
+ *
+ * Find the cheapest path of links from one page to another. Each link into a page on the same wiki costs 1. Pages
+ * on other wikis are reached through interlanguage links, which are followed only when asked for, and cost
+ * langlink_weight, so that a path through another language edition is only preferred when it is shorter by more than
+ * the cost of the hops between editions. Each hop in the result is flagged if it was over an interlanguage link.
+ *
+ * Links into namespaces other than articles, such as categories, are followed if they are named in namespaces, and
+ * cost 1. Each hop in the result gives the edge followed: a link, a link into a namespace, or an interlanguage link.
+ *
+ * A search can be kept to a topic with category trees. Each page between the first and the last must belong to one of
+ * include_categories, if any are given, and must not belong to any of exclude_categories. A page belongs to a
+ * category if the category holds it, or holds it through subcategories up to category_depth levels deep. Trees are
+ * expanded upwards, from the categories of the page through the parent categories of each category page, so only the
+ * category pages above the pages reached are read.
+ *
+ * Pages are read from the workers. A page that is not held by a worker, or is only held as a stub, is fetched from the
+ * cache or the wiki and added to the workers, up to max_fetches pages for each search. The title of a page must be
+ * known to fetch it, so pages are only fetched if they were named by a page fetched earlier in the search.
+ *
+ * With context, each link of the path found is given the sentence around the link on the page it is from. Contexts
+ * are fetched after the search, for the pages of the path alone.
+ *
+ * A search gives the cheapest path, or alternatives to it, by mode
+ *    Shortest      the cheapest path
+ *    AllShortest   every path as cheap as the cheapest, up to MAX_PATHS. Each page records every page from which it is
+ *                  reached at its cost, and the paths are traced back from the last page through them
+ *    KShortest(k)  up to k loopless paths, cheapest first, by Yen's algorithm. Each path after the first is found by a
+ *                  further search from a spur page of the path before, with the pages before the spur and the links
+ *                  already followed from it left out
+ * Every path is held to max_hops, and the pages expanded by all the searches for one request to MAX_EXPANDED.
+ *
+ * With reciprocal, only mutual links are followed: a link from one page to another is followed only if the other page
+ * links back. Whether a page links back is read from the back-links held by the page. The back-links only hold the
+ * links from pages already loaded, so failing that, the link is queued unconfirmed, and read by Graph::links_back from
+ * the links of the other page once it is the cheapest page left, and only if the link would still be the cheapest way
+ * to it. The other page is fetched then if it is not held, and counts against the pages fetched for the search. Links
+ * into other namespaces are not followed, as the pages they lead to, such as categories, do not link back.
+ * Interlanguage links, which join the editions of one topic, are followed as usual.
+ *
+ * A search can also be kept away from pages, or made to pass through them. Pages between the first and the last may
+ * not be one of avoid, and may not have a title matched by avoid_pattern, such as ^List of or ^\d{4}$ for years. The
+ * constraints are checked as the search runs: a page to avoid is never queued, and neither is a page whose title is
+ * matched, if the title is known from Graph::title, so the page is not fetched only to be left out. A page whose title
+ * is only known once it has been read is matched before it is expanded. Either way, the search goes around them.
+ *
+ * With via, the path passes through each page of via in turn. Each leg, from the first page to the first of via, and
+ * so on to the last page, is the cheapest that does not go back through the pages of the legs before it, and the whole
+ * path is held to max_hops. Via is only used with Shortest.
+ *
+ * With rank, each link of the paths found is scored by score::score_link, and the paths are sorted by score, best
+ * first. Paths with equal scores stay in order of cost.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    cmp::Ordering,
+    collections::{hash_map, BinaryHeap, HashMap, HashSet},
+};
+
+use regex::Regex;
+use tokio::sync::mpsc;
+
+use crate::entry::{Digest, Entry};
+use crate::fetch::FetchCommand;
+use crate::policy::{self, Edge};
+use crate::score::{self, LinkScore, PathScore};
+use crate::wiki::{self, Wiki};
+use crate::worker::{self, TxCommands, WorkerCommand};
+
+static DEFAULT_MAX_HOPS: usize = 6;
+static DEFAULT_LANGLINK_WEIGHT: f64 = 2.0;
+static DEFAULT_CATEGORY_DEPTH: usize = 2;
+static DEFAULT_FAKE_HUB: u32 = 200;
+// Bound the work done for one search
+static MAX_EXPANDED: usize = 100_000;
+/// Most paths given by one search
+pub static MAX_PATHS: usize = 50;
+// Paths whose costs differ by no more than this are equally cheap
+static COST_TOLERANCE: f64 = 1e-9;
+
+// ***********************************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathOptions {
+    pub max_hops: usize,
+    // Follow interlanguage links
+    pub langlinks: bool,
+    // Cost of an interlanguage link. A link on the same wiki costs 1
+    pub langlink_weight: f64,
+    // Namespaces, other than articles, whose links are followed
+    pub namespaces: Vec<i32>,
+    // Digests of categories. Pages between the first and the last must belong to one of these, if any are given
+    pub include_categories: Vec<Digest>,
+    // Digests of categories. Pages between the first and the last must not belong to any of these
+    pub exclude_categories: Vec<Digest>,
+    // Levels of subcategories below each category that also hold pages in the tree
+    pub category_depth: usize,
+    // Give each link of the path the sentence around it
+    pub context: bool,
+    // Name of the dataset searched in place of the live graph
+    pub as_of: Option<String>,
+    pub mode: Mode,
+    // Score the paths found, and sort them by score
+    pub rank: bool,
+    // Percentage of inbound to outbound links above which a page is penalised as a hub when ranking
+    pub fake_hub: u32,
+    // Follow only links from pages that are linked back to
+    pub reciprocal: bool,
+    // Digests of pages that may not be between the first and the last
+    pub avoid: Vec<Digest>,
+    // Titles of pages that may not be between the first and the last
+    pub avoid_pattern: Option<TitlePattern>,
+    // Pages that the path passes through, in order
+    pub via: Vec<Page>,
+}
+
+/// A regular expression matched against titles
+#[derive(Debug, Clone)]
+pub struct TitlePattern(pub Regex);
+
+impl PartialEq for TitlePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// The paths given by a search
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    // The cheapest path
+    Shortest,
+    // Every path as cheap as the cheapest, up to MAX_PATHS
+    AllShortest,
+    // Up to k loopless paths, cheapest first
+    KShortest(usize),
+}
+
+impl Default for PathOptions {
+    fn default() -> Self {
+        PathOptions {
+            max_hops: DEFAULT_MAX_HOPS,
+            langlinks: false,
+            langlink_weight: DEFAULT_LANGLINK_WEIGHT,
+            namespaces: Vec::new(),
+            include_categories: Vec::new(),
+            exclude_categories: Vec::new(),
+            category_depth: DEFAULT_CATEGORY_DEPTH,
+            context: false,
+            as_of: None,
+            mode: Mode::Shortest,
+            rank: false,
+            fake_hub: DEFAULT_FAKE_HUB,
+            reciprocal: false,
+            avoid: Vec::new(),
+            avoid_pattern: None,
+            via: Vec::new(),
+        }
+    }
+}
+
+/// A page at one end of a search
+#[derive(Debug, Clone, PartialEq)]
+pub struct Page {
+    pub wiki: String,
+    pub title: String,
+    pub digest: Digest,
+}
+
+impl Page {
+    pub fn new(wiki: &Wiki, title: &str) -> Page {
+        Page {
+            wiki: wiki.id.clone(),
+            title: title.to_string(),
+            digest: Entry::get_digest_for(wiki, title),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Hop {
+    #[serde(skip)]
+    pub digest: Digest,
+    pub wiki: String,
+    pub title: String,
+    // The edge followed to reach the page. The first page has no edge
+    pub edge: Option<Edge>,
+    // The page was reached over an interlanguage link
+    pub interlanguage: bool,
+    // The sentence around the link to the page on the page before, if asked for and found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    // The score of the link to the page, if ranked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<LinkScore>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Path {
+    pub hops: Vec<Hop>,
+    pub cost: f64,
+    pub links: usize,
+    pub interlanguage_links: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<PathScore>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Search {
+    // The cheapest path, or if ranked, the path with the best score
+    pub path: Option<Path>,
+    // Every path found, cheapest first or by score, unless the mode is Shortest
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<Path>,
+    // There were more paths as cheap as the cheapest than MAX_PATHS
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    // Pages whose links were read
+    pub expanded: usize,
+    // Pages fetched from the cache or the wiki
+    pub fetched: usize,
+    // Pages not expanded because of their categories
+    pub filtered: usize,
+    // The dataset searched, if not the live graph
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dataset: Option<String>,
+}
+
+/// Source of pages for a search
+pub trait Graph {
+    async fn get(&mut self, digest: Digest) -> Option<Entry>;
+
+    /// Pages fetched so far
+    fn fetched(&self) -> usize {
+        0
+    }
+
+    /// Title of a page, if it is known without reading the page
+    fn title(&self, _digest: Digest) -> Option<&str> {
+        None
+    }
+
+    /// True if the page `to` links back to the page `from`, read from the links of `to`
+    async fn links_back(&mut self, from: Digest, to: Digest) -> bool {
+        self.get(to)
+            .await
+            .is_some_and(|to| to.get_outbound().contains(&from))
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Search
+ *
+ *******************************************************************************************************************/
+
+// A page waiting to be expanded, ordered so that the cheapest is popped first from the max-heap
+struct Candidate {
+    cost: f64,
+    digest: Digest,
+    // The page of a reciprocal link that is not known to link back. The page is only reached once it is confirmed
+    unconfirmed: Option<Digest>,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.digest.cmp(&self.digest))
+            .then_with(|| other.unconfirmed.cmp(&self.unconfirmed))
+    }
+}
+
+// How the search reached a page
+struct Visit {
+    cost: f64,
+    hops: usize,
+    wiki: String,
+    // Previous pages, and the edge followed from each. With ties, every page from which the page is reached at its
+    // cost, in the order found. The first page of the search has none
+    from: Vec<(Digest, Edge)>,
+}
+
+impl Visit {
+    // True if reaching the page from `from` at `cost` makes it cheaper or, with ties, adds a page it is reached from
+    fn improved_by(&self, cost: f64, from: Digest, ties: bool) -> bool {
+        cost < self.cost - COST_TOLERANCE
+            || ties
+                && cost <= self.cost + COST_TOLERANCE
+                && self.from.iter().all(|(page, _)| *page != from)
+    }
+}
+
+// Reach a page by a visit that improves on the one known, as a cheaper visit or, with ties, as more pages it is reached
+// from at its cost
+fn reach(
+    visits: &mut HashMap<Digest, Visit>,
+    queue: &mut BinaryHeap<Candidate>,
+    digest: Digest,
+    visit: Visit,
+) {
+    match visits.get_mut(&digest) {
+        Some(known) if visit.cost >= known.cost - COST_TOLERANCE => known.from.extend(visit.from),
+        _ => {
+            queue.push(Candidate {
+                cost: visit.cost,
+                digest,
+                unconfirmed: None,
+            });
+            visits.insert(digest, visit);
+        }
+    }
+}
+
+// One page of a route, with the edge followed to reach it, and the cost of the route up to the page
+#[derive(Debug, Clone)]
+struct Step {
+    digest: Digest,
+    wiki: String,
+    edge: Option<Edge>,
+    cost: f64,
+}
+
+// A path found by a search, before its titles are added
+#[derive(Debug, Clone)]
+struct Route {
+    steps: Vec<Step>,
+}
+
+impl Route {
+    fn cost(&self) -> f64 {
+        self.steps.last().map(|step| step.cost).unwrap_or_default()
+    }
+
+    fn pages(&self) -> Vec<Digest> {
+        self.steps.iter().map(|step| step.digest).collect()
+    }
+
+    fn to_path(&self, titles: &HashMap<Digest, String>) -> Path {
+        let hops: Vec<Hop> = self
+            .steps
+            .iter()
+            .map(|step| Hop {
+                digest: step.digest,
+                wiki: step.wiki.clone(),
+                title: titles.get(&step.digest).cloned().unwrap_or_default(),
+                edge: step.edge,
+                interlanguage: step.edge == Some(Edge::Interlanguage),
+                context: None,
+                score: None,
+            })
+            .collect();
+        let interlanguage_links = hops.iter().filter(|hop| hop.interlanguage).count();
+        Path {
+            links: hops.len() - 1,
+            interlanguage_links,
+            cost: self.cost(),
+            hops,
+            score: None,
+        }
+    }
+}
+
+// Pages and links that a search may not follow
+#[derive(Default)]
+struct Excluded {
+    pages: HashSet<Digest>,
+    links: HashSet<(Digest, Digest)>,
+}
+
+// What is known across the searches made for one request
+struct Explorer<'a> {
+    to: Digest,
+    options: &'a PathOptions,
+    titles: HashMap<Digest, String>,
+    expanded: usize,
+    filtered: usize,
+    // Pages whose titles are matched by avoid_pattern
+    matched: HashSet<Digest>,
+    categories: CategoryTrees,
+}
+
+/// Find the cheapest path from one page to another, or the paths asked for by options.mode
+pub async fn search<G: Graph>(
+    graph: &mut G,
+    from: &Page,
+    to: &Page,
+    options: &PathOptions,
+) -> Search {
+    let mut explorer = Explorer {
+        to: to.digest,
+        options,
+        titles: HashMap::from([
+            (from.digest, from.title.clone()),
+            (to.digest, to.title.clone()),
+        ]),
+        expanded: 0,
+        filtered: 0,
+        matched: HashSet::new(),
+        categories: CategoryTrees::default(),
+    };
+    let start = Step {
+        digest: from.digest,
+        wiki: from.wiki.clone(),
+        edge: None,
+        cost: 0.0,
+    };
+
+    let (routes, truncated) = match options.mode {
+        Mode::Shortest => (explorer.shortest(graph, &start).await, false),
+        Mode::AllShortest => {
+            let visits = explorer
+                .explore(
+                    graph,
+                    &start,
+                    to.digest,
+                    options.max_hops,
+                    &Excluded::default(),
+                    true,
+                )
+                .await;
+            trace_routes(to.digest, &visits, MAX_PATHS, options.max_hops)
+        }
+        Mode::KShortest(k) => {
+            let routes = explorer.k_shortest(graph, &start, k).await;
+            (routes, false)
+        }
+    };
+
+    let mut paths: Vec<Path> = routes
+        .iter()
+        .map(|route| route.to_path(&explorer.titles))
+        .collect();
+    if options.rank {
+        rank(graph, &mut paths, options.fake_hub).await;
+    }
+    Search {
+        path: paths.first().cloned(),
+        paths: match options.mode {
+            Mode::Shortest => Vec::new(),
+            _ => paths,
+        },
+        truncated,
+        expanded: explorer.expanded,
+        fetched: graph.fetched(),
+        filtered: explorer.filtered,
+        dataset: None,
+    }
+}
+
+impl Explorer<'_> {
+    // Visit pages from the start, cheapest first, until the last page is reached or no pages are left. With ties,
+    // pages are visited until every page cheaper than the last has been expanded, and each page records every page
+    // from which it is reached at its cost
+    async fn explore<G: Graph>(
+        &mut self,
+        graph: &mut G,
+        start: &Step,
+        to: Digest,
+        max_hops: usize,
+        excluded: &Excluded,
+        ties: bool,
+    ) -> HashMap<Digest, Visit> {
+        let options = self.options;
+        let mut visits: HashMap<Digest, Visit> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        visits.insert(
+            start.digest,
+            Visit {
+                cost: start.cost,
+                hops: 0,
+                wiki: start.wiki.clone(),
+                from: Vec::new(),
+            },
+        );
+        queue.push(Candidate {
+            cost: start.cost,
+            digest: start.digest,
+            unconfirmed: None,
+        });
+
+        let mut found: Option<f64> = None;
+        while let Some(Candidate {
+            cost,
+            digest,
+            unconfirmed,
+        }) = queue.pop()
+        {
+            if found.is_some_and(|found| cost > found + COST_TOLERANCE) {
+                break;
+            }
+            // A reciprocal link is confirmed once the page linked to is the cheapest left, if it still improves the
+            // visit to the page, so pages reached more cheaply in other ways are not read only to check their links
+            if let Some(from) = unconfirmed {
+                if visits
+                    .get(&digest)
+                    .is_some_and(|visit| !visit.improved_by(cost, from, ties))
+                {
+                    continue;
+                }
+                if graph.links_back(from, digest).await {
+                    let from_visit = &visits[&from];
+                    let visit = Visit {
+                        cost,
+                        hops: from_visit.hops + 1,
+                        wiki: from_visit.wiki.clone(),
+                        from: vec![(from, Edge::Link)],
+                    };
+                    reach(&mut visits, &mut queue, digest, visit);
+                }
+                continue;
+            }
+            let visit = &visits[&digest];
+            if cost > visit.cost {
+                continue;
+            }
+            if digest == to {
+                found = Some(cost);
+                match ties {
+                    true => continue,
+                    false => break,
+                }
+            }
+            if visit.hops >= max_hops || self.expanded >= MAX_EXPANDED {
+                continue;
+            }
+            let (hops, wiki) = (visit.hops, visit.wiki.clone());
+
+            let entry = match graph.get(digest).await {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let avoided = options
+                .avoid_pattern
+                .as_ref()
+                .is_some_and(|pattern| pattern.0.is_match(entry.get_title()));
+            if digest != start.digest
+                && (avoided || !self.categories.allows(graph, &entry, options).await)
+            {
+                if avoided {
+                    self.matched.insert(digest);
+                }
+                self.filtered += 1;
+                continue;
+            }
+            self.expanded += 1;
+            if !entry.get_title().is_empty() {
+                self.titles.insert(digest, entry.get_title().to_string());
+            }
+
+            let links = entry
+                .get_outbound()
+                .iter()
+                .map(|to| (*to, &wiki, 1.0, Edge::Link));
+            let namespace_links = entry
+                .get_namespace_links()
+                .iter()
+                .filter(|(ns, _)| options.namespaces.contains(ns) && !options.reciprocal)
+                .map(|(ns, to)| (*to, &wiki, 1.0, Edge::Namespace(*ns)));
+            let langlinks = entry
+                .get_langlinks()
+                .iter()
+                .filter(|_| options.langlinks)
+                .map(|(wiki, to)| (*to, wiki, options.langlink_weight, Edge::Interlanguage));
+            for (next, next_wiki, weight, edge) in links.chain(namespace_links).chain(langlinks) {
+                if excluded.pages.contains(&next) || excluded.links.contains(&(digest, next)) {
+                    continue;
+                }
+                if next != to && options.avoid.contains(&next) {
+                    continue;
+                }
+                if next != to && next != start.digest && self.title_avoided(graph, next) {
+                    continue;
+                }
+                let next_cost = cost + weight;
+                if visits
+                    .get(&next)
+                    .is_some_and(|visit| !visit.improved_by(next_cost, digest, ties))
+                {
+                    continue;
+                }
+                if options.reciprocal && edge == Edge::Link && !entry.get_inbound().contains(&next)
+                {
+                    queue.push(Candidate {
+                        cost: next_cost,
+                        digest: next,
+                        unconfirmed: Some(digest),
+                    });
+                    continue;
+                }
+                let visit = Visit {
+                    cost: next_cost,
+                    hops: hops + 1,
+                    wiki: next_wiki.clone(),
+                    from: vec![(digest, edge)],
+                };
+                reach(&mut visits, &mut queue, next, visit);
+            }
+        }
+        visits
+    }
+
+    // True if the title of the page is known without reading it, and is matched by avoid_pattern. Each page matched is
+    // filtered once
+    fn title_avoided<G: Graph>(&mut self, graph: &G, digest: Digest) -> bool {
+        let pattern = match &self.options.avoid_pattern {
+            Some(pattern) => pattern,
+            None => return false,
+        };
+        if self.matched.contains(&digest) {
+            return true;
+        }
+        let matched = graph
+            .title(digest)
+            .is_some_and(|title| pattern.0.is_match(title));
+        if matched {
+            self.matched.insert(digest);
+            self.filtered += 1;
+        }
+        matched
+    }
+
+    // The cheapest path, through each page of via in turn. Each leg may not go back through the pages of the legs
+    // before it
+    async fn shortest<G: Graph>(&mut self, graph: &mut G, start: &Step) -> Vec<Route> {
+        let options = self.options;
+        let legs = options.via.iter().map(|page| page.digest).chain([self.to]);
+        let mut route = Route {
+            steps: vec![start.clone()],
+        };
+        for leg_to in legs {
+            let links = route.steps.len() - 1;
+            let leg_start = route.steps[links].clone();
+            if leg_to == leg_start.digest {
+                continue;
+            }
+            let excluded = Excluded {
+                pages: route.steps[..links]
+                    .iter()
+                    .map(|step| step.digest)
+                    .collect(),
+                links: HashSet::new(),
+            };
+            let max_hops = options.max_hops.saturating_sub(links);
+            let visits = self
+                .explore(graph, &leg_start, leg_to, max_hops, &excluded, false)
+                .await;
+            match trace_routes(leg_to, &visits, 1, max_hops).0.pop() {
+                Some(leg) => route.steps.extend(leg.steps.into_iter().skip(1)),
+                None => return Vec::new(),
+            }
+        }
+        vec![route]
+    }
+
+    // Up to k loopless paths, cheapest first, found with Yen's algorithm. Each path after the first leaves one of the
+    // paths found at a spur page, through a link that none of the paths with the same pages up to the spur follow
+    async fn k_shortest<G: Graph>(&mut self, graph: &mut G, start: &Step, k: usize) -> Vec<Route> {
+        let (to, options) = (self.to, self.options);
+        let visits = self
+            .explore(
+                graph,
+                start,
+                to,
+                options.max_hops,
+                &Excluded::default(),
+                false,
+            )
+            .await;
+        let mut routes = trace_routes(to, &visits, 1, options.max_hops).0;
+        let mut candidates: Vec<Route> = Vec::new();
+        while !routes.is_empty() && routes.len() < k {
+            let last = routes[routes.len() - 1].clone();
+            for spur in 0..last.steps.len() - 1 {
+                if self.expanded >= MAX_EXPANDED {
+                    break;
+                }
+                let root = &last.steps[..=spur];
+                let mut excluded = Excluded::default();
+                for route in &routes {
+                    let same_root = route.steps.len() > spur + 1
+                        && route.steps[..=spur]
+                            .iter()
+                            .zip(root)
+                            .all(|(step, root_step)| step.digest == root_step.digest);
+                    if same_root {
+                        excluded
+                            .links
+                            .insert((route.steps[spur].digest, route.steps[spur + 1].digest));
+                    }
+                }
+                excluded
+                    .pages
+                    .extend(root[..spur].iter().map(|step| step.digest));
+
+                let visits = self
+                    .explore(
+                        graph,
+                        &root[spur],
+                        to,
+                        options.max_hops - spur,
+                        &excluded,
+                        false,
+                    )
+                    .await;
+                let spur_route = match trace_routes(to, &visits, 1, options.max_hops - spur)
+                    .0
+                    .pop()
+                {
+                    Some(spur_route) => spur_route,
+                    None => continue,
+                };
+                let mut steps = root.to_vec();
+                steps.extend(spur_route.steps.into_iter().skip(1));
+                let route = Route { steps };
+                let pages = route.pages();
+                if routes
+                    .iter()
+                    .chain(candidates.iter())
+                    .all(|known| known.pages() != pages)
+                {
+                    candidates.push(route);
+                }
+            }
+            let cheapest = candidates
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    a.cost()
+                        .total_cmp(&b.cost())
+                        .then_with(|| a.steps.len().cmp(&b.steps.len()))
+                })
+                .map(|(index, _)| index);
+            match cheapest {
+                Some(index) => routes.push(candidates.remove(index)),
+                None => break,
+            }
+        }
+        routes
+    }
+}
+
+// Score each link of the paths, and sort the paths by score, best first. The sort is stable, so paths with equal
+// scores stay in order of cost. Links from or to a page that cannot be read are not scored
+async fn rank<G: Graph>(graph: &mut G, paths: &mut [Path], fake_hub: u32) {
+    let mut entries: HashMap<Digest, Option<Entry>> = HashMap::new();
+    for path in paths.iter_mut() {
+        for hop in &path.hops {
+            if let hash_map::Entry::Vacant(vacant) = entries.entry(hop.digest) {
+                vacant.insert(graph.get(hop.digest).await);
+            }
+        }
+        let last = path.hops.len() - 1;
+        for index in 1..path.hops.len() {
+            let from = &entries[&path.hops[index - 1].digest];
+            let to = &entries[&path.hops[index].digest];
+            if let (Some(from), Some(to), Some(edge)) = (from, to, path.hops[index].edge) {
+                path.hops[index].score =
+                    Some(score::score_link(from, to, edge, index == last, fake_hub));
+            }
+        }
+        let links: Vec<&LinkScore> = path
+            .hops
+            .iter()
+            .filter_map(|hop| hop.score.as_ref())
+            .collect();
+        path.score = Some(score::score_path(&links));
+    }
+    let score = |path: &Path| {
+        path.score
+            .as_ref()
+            .map(|score| score.score)
+            .unwrap_or_default()
+    };
+    paths.sort_by(|a, b| score(b).total_cmp(&score(a)));
+}
+
+// Up to limit routes to the last page, following the visits back to the first page. Routes longer than max_hops,
+// which may be reached through ties, are left out. The second value is true if there were more routes than the limit
+fn trace_routes(
+    last: Digest,
+    visits: &HashMap<Digest, Visit>,
+    limit: usize,
+    max_hops: usize,
+) -> (Vec<Route>, bool) {
+    let mut routes = Vec::new();
+    let mut suffix = Vec::new();
+    let truncated = trace_back(last, visits, &mut suffix, &mut routes, limit, max_hops);
+    (routes, truncated)
+}
+
+// Add the routes to the page, ahead of the suffix, returning true if the limit was reached with routes left over
+fn trace_back(
+    digest: Digest,
+    visits: &HashMap<Digest, Visit>,
+    suffix: &mut Vec<Step>,
+    routes: &mut Vec<Route>,
+    limit: usize,
+    max_hops: usize,
+) -> bool {
+    let visit = match visits.get(&digest) {
+        Some(visit) => visit,
+        None => return false,
+    };
+    if suffix.len() > max_hops {
+        return false;
+    }
+    suffix.push(Step {
+        digest,
+        wiki: visit.wiki.clone(),
+        edge: None,
+        cost: visit.cost,
+    });
+    let mut truncated = false;
+    if visit.from.is_empty() {
+        if routes.len() < limit {
+            let mut steps = suffix.clone();
+            steps.reverse();
+            routes.push(Route { steps });
+        } else {
+            truncated = true;
+        }
+    }
+    for (from, from_edge) in &visit.from {
+        if truncated {
+            break;
+        }
+        // Pages reached at no cost may lead back to themselves
+        if suffix.iter().any(|step| step.digest == *from) {
+            continue;
+        }
+        let last = suffix.len() - 1;
+        suffix[last].edge = Some(*from_edge);
+        truncated = trace_back(*from, visits, suffix, routes, limit, max_hops);
+    }
+    suffix.pop();
+    truncated
+}
+
+// Parent categories of the category pages read in one search
+#[derive(Default)]
+struct CategoryTrees {
+    parents: HashMap<Digest, Vec<Digest>>,
+}
+
+impl CategoryTrees {
+    // True if the page may be between the first and the last page of a path
+    async fn allows<G: Graph>(
+        &mut self,
+        graph: &mut G,
+        entry: &Entry,
+        options: &PathOptions,
+    ) -> bool {
+        if !options.include_categories.is_empty()
+            && !self
+                .holds(
+                    graph,
+                    entry,
+                    &options.include_categories,
+                    options.category_depth,
+                )
+                .await
+        {
+            return false;
+        }
+        options.exclude_categories.is_empty()
+            || !self
+                .holds(
+                    graph,
+                    entry,
+                    &options.exclude_categories,
+                    options.category_depth,
+                )
+                .await
+    }
+
+    // True if one of the roots holds the page, directly or through subcategories up to depth levels deep. A category
+    // page is in its own tree
+    async fn holds<G: Graph>(
+        &mut self,
+        graph: &mut G,
+        entry: &Entry,
+        roots: &[Digest],
+        depth: usize,
+    ) -> bool {
+        let mut seen = HashSet::new();
+        let mut level = entry.get_categories().to_vec();
+        level.push(entry.digest());
+        for subcategory_level in 0..=depth {
+            if level.iter().any(|category| roots.contains(category)) {
+                return true;
+            }
+            if subcategory_level == depth {
+                break;
+            }
+            let mut parents = Vec::new();
+            for category in level {
+                if seen.insert(category) {
+                    parents.extend(self.parents_of(graph, category).await);
+                }
+            }
+            level = parents;
+        }
+        false
+    }
+
+    async fn parents_of<G: Graph>(&mut self, graph: &mut G, category: Digest) -> Vec<Digest> {
+        if let Some(parents) = self.parents.get(&category) {
+            return parents.clone();
+        }
+        let parents = graph
+            .get(category)
+            .await
+            .map(|entry| entry.get_categories().to_vec())
+            .unwrap_or_default();
+        self.parents.insert(category, parents.clone());
+        parents
+    }
+}
+
+/// Give each link of the path the sentence around it on the page before. Interlanguage links have no context
+pub async fn add_context(path: &mut Path, tx_to_fetch: &mpsc::Sender<FetchCommand>) {
+    for index in 1..path.hops.len() {
+        if !matches!(path.hops[index].edge, Some(Edge::Link | Edge::Namespace(_))) {
+            continue;
+        }
+        let (from, to) = (&path.hops[index - 1], &path.hops[index]);
+        let wiki = match wiki::get(&to.wiki) {
+            Ok(wiki) => wiki,
+            Err(_) => continue,
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        let command = FetchCommand::Context {
+            wiki: from.wiki.clone(),
+            title: from.title.clone(),
+            tx,
+        };
+        if tx_to_fetch.send(command).await.is_err() {
+            return;
+        }
+        let contexts = match rx.recv().await {
+            Some(Ok(contexts)) => contexts,
+            Some(Err(err)) => {
+                warn!(
+                    "Unable to read the link contexts of {} from {}: {}",
+                    from.title, from.wiki, err
+                );
+                continue;
+            }
+            None => continue,
+        };
+        let digest = Entry::get_digest_for(wiki, &to.title);
+        path.hops[index].context = contexts
+            .into_iter()
+            .find(|(to, _)| *to == digest)
+            .map(|(_, context)| context);
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Read pages from the workers
+ *
+ *******************************************************************************************************************/
+
+pub struct WorkerGraph {
+    tx_commands: TxCommands,
+    tx_to_fetch: mpsc::Sender<FetchCommand>,
+    // Wiki and title of each page named by a page fetched in this search
+    titles: HashMap<Digest, (&'static Wiki, String)>,
+    max_fetches: usize,
+    fetched: usize,
+}
+
+impl WorkerGraph {
+    pub fn new(
+        tx_commands: TxCommands,
+        tx_to_fetch: mpsc::Sender<FetchCommand>,
+        max_fetches: usize,
+    ) -> WorkerGraph {
+        WorkerGraph {
+            tx_commands,
+            tx_to_fetch,
+            titles: HashMap::new(),
+            max_fetches,
+            fetched: 0,
+        }
+    }
+
+    /// Name a page, so that it can be fetched if it is not held by the workers
+    pub fn add_title(&mut self, wiki: &'static Wiki, title: &str) {
+        self.titles
+            .entry(Entry::get_digest_for(wiki, title))
+            .or_insert_with(|| (wiki, title.to_string()));
+    }
+
+    async fn get_from_worker(&self, digest: Digest) -> Option<Entry> {
+        let worker_id = worker::get_worker_id_for(digest, self.tx_commands.len());
+        let (tx, mut rx) = mpsc::channel(1);
+        self.tx_commands[worker_id]
+            .send(WorkerCommand::Get { digest, tx })
+            .await
+            .ok()?;
+        rx.recv().await.flatten()
+    }
+
+    async fn fetch(&mut self, digest: Digest) -> Option<Entry> {
+        let (wiki, title) = self.titles.get(&digest)?.clone();
+        self.fetched += 1;
+
+        let (tx, mut rx) = mpsc::channel(1);
+        self.tx_to_fetch
+            .send(FetchCommand::Get {
+                wiki: wiki.id.clone(),
+                title: title.clone(),
+                tx,
+            })
+            .await
+            .ok()?;
+        let fetch_entry = match rx.recv().await? {
+            Ok(fetch_entry) => fetch_entry,
+            Err(err) => {
+                warn!("Unable to fetch {} from {}: {}", title, wiki.id, err);
+                return None;
+            }
+        };
+
+        for title in &fetch_entry.outbound {
+            self.add_title(wiki, title);
+        }
+        for (_, title) in &fetch_entry.namespace_links {
+            self.add_title(wiki, title);
+        }
+        for title in &fetch_entry.categories {
+            self.add_title(wiki, title);
+        }
+        for (lang, title) in &fetch_entry.langlinks {
+            if let Some(target) = wiki::language_edition(wiki::all(), wiki, lang) {
+                self.add_title(target, title);
+            }
+        }
+        let entry = fetch_entry.to_entry(wiki, wiki::all(), policy::get());
+        if worker::update(entry.clone(), &self.tx_commands)
+            .await
+            .is_err()
+        {
+            warn!("Unable to add {} to the workers", title);
+        }
+        Some(entry)
+    }
+}
+
+impl Graph for WorkerGraph {
+    async fn get(&mut self, digest: Digest) -> Option<Entry> {
+        if let Some(entry) = self.get_from_worker(digest).await {
+            if !entry.is_stub() {
+                return Some(entry);
+            }
+        }
+        if self.fetched >= self.max_fetches {
+            return None;
+        }
+        self.fetch(digest).await
+    }
+
+    fn fetched(&self) -> usize {
+        self.fetched
+    }
+
+    fn title(&self, digest: Digest) -> Option<&str> {
+        self.titles.get(&digest).map(|(_, title)| title.as_str())
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MapGraph(HashMap<Digest, Entry>);
+
+    impl Graph for MapGraph {
+        async fn get(&mut self, digest: Digest) -> Option<Entry> {
+            self.0.get(&digest).cloned()
+        }
+    }
+
+    fn wikis() -> Vec<Wiki> {
+        vec![
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("ja", "https://ja.wikipedia.org/", 10),
+        ]
+    }
+
+    // A page as (wiki, title, links on the same wiki, interlanguage links as (wiki, title))
+    type TestPage<'a> = (usize, &'a str, &'a [&'a str], &'a [(usize, &'a str)]);
+
+    fn graph(wikis: &[Wiki], pages: &[TestPage]) -> MapGraph {
+        let mut entries = HashMap::new();
+        for (wiki, title, links, langlinks) in pages {
+            let wiki = &wikis[*wiki];
+            let outbound = links
+                .iter()
+                .map(|to| Entry::get_digest_for(wiki, to))
+                .collect();
+            let mut entry = Entry::new_for(wiki, title.to_string(), outbound);
+            entry.set_langlinks(
+                langlinks
+                    .iter()
+                    .map(|(to_wiki, to)| {
+                        let to_wiki = &wikis[*to_wiki];
+                        (to_wiki.id.clone(), Entry::get_digest_for(to_wiki, to))
+                    })
+                    .collect(),
+            );
+            entries.insert(entry.digest(), entry);
+        }
+        MapGraph(entries)
+    }
+
+    fn titles(search: &Search) -> Vec<(&str, &str, bool)> {
+        search
+            .path
+            .as_ref()
+            .unwrap()
+            .hops
+            .iter()
+            .map(|hop| (hop.wiki.as_str(), hop.title.as_str(), hop.interlanguage))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Rail transport", "Locomotive"], &[]),
+                (0, "Rail transport", &["Railway signalling"], &[]),
+                (0, "Locomotive", &["Steam engine"], &[]),
+                (0, "Steam engine", &["Railway signalling"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Railway signalling");
+        let search = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(
+            titles(&search),
+            vec![
+                ("en", "Train", false),
+                ("en", "Rail transport", false),
+                ("en", "Railway signalling", false),
+            ]
+        );
+        let path = search.path.unwrap();
+        assert_eq!(path.links, 2);
+        assert_eq!(path.cost, 2.0);
+        assert_eq!(path.interlanguage_links, 0);
+    }
+
+    #[tokio::test]
+    async fn test_search_max_hops() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "A", &["B"], &[]),
+                (0, "B", &["C"], &[]),
+                (0, "C", &["D"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "A");
+        let to = Page::new(&wikis[0], "D");
+        let options = PathOptions {
+            max_hops: 2,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path, None);
+        assert_eq!(result.expanded, 2);
+
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(result.path.unwrap().links, 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_langlinks() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &[], &[(1, "列車")]),
+                (1, "列車", &["新幹線"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[1], "新幹線");
+
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(result.path, None);
+
+        let options = PathOptions {
+            langlinks: true,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            titles(&result),
+            vec![
+                ("en", "Train", false),
+                ("ja", "列車", true),
+                ("ja", "新幹線", false),
+            ]
+        );
+        let path = result.path.unwrap();
+        assert_eq!(path.cost, 3.0);
+        assert_eq!(path.interlanguage_links, 1);
+    }
+
+    #[tokio::test]
+    async fn test_search_namespaces() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Locomotive"], &[]),
+                (0, "Category:Rail vehicles", &["Tram"], &[]),
+            ],
+        );
+        let train = Entry::get_digest_for(&wikis[0], "Train");
+        graph.0.get_mut(&train).unwrap().set_namespace_links(vec![(
+            14,
+            Entry::get_digest_for(&wikis[0], "Category:Rail vehicles"),
+        )]);
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(result.path, None);
+
+        let options = PathOptions {
+            namespaces: vec![14],
+            ..Default::default()
+        };
+        let path = search(&mut graph, &from, &to, &options).await.path.unwrap();
+        let edges: Vec<Option<Edge>> = path.hops.iter().map(|hop| hop.edge).collect();
+        assert_eq!(
+            edges,
+            vec![None, Some(Edge::Namespace(14)), Some(Edge::Link)]
+        );
+        assert_eq!(path.hops[1].title, "Category:Rail vehicles");
+        assert_eq!(path.cost, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_search_categories() {
+        let wikis = wikis();
+        // Two paths from Train to Tram: through Locomotive, in Category:Rail vehicles, or through Horse, in
+        // Category:Animals
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Locomotive", "Horse"], &[]),
+                (0, "Locomotive", &["Tram"], &[]),
+                (0, "Horse", &["Tram"], &[]),
+                (0, "Category:Rail vehicles", &[], &[]),
+                (0, "Category:Rail transport", &[], &[]),
+                (0, "Category:Transport", &[], &[]),
+            ],
+        );
+        let category = |title: &str| Entry::get_digest_for(&wikis[0], title);
+        let mut set_categories = |title: &str, categories: &[&str]| {
+            let digest = Entry::get_digest_for(&wikis[0], title);
+            let categories = categories.iter().map(|title| category(title)).collect();
+            graph.0.get_mut(&digest).unwrap().set_categories(categories);
+        };
+        set_categories("Locomotive", &["Category:Rail vehicles"]);
+        set_categories("Horse", &["Category:Animals"]);
+        set_categories("Category:Rail vehicles", &["Category:Rail transport"]);
+        set_categories("Category:Rail transport", &["Category:Transport"]);
+
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let middle = |search: &Search| search.path.as_ref().map(|path| path.hops[1].title.clone());
+
+        let options = PathOptions {
+            include_categories: vec![category("Category:Rail transport")],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(middle(&result), Some("Locomotive".to_string()));
+        assert_eq!(result.filtered, 1);
+
+        let options = PathOptions {
+            exclude_categories: vec![category("Category:Rail vehicles")],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(middle(&result), Some("Horse".to_string()));
+
+        // Locomotive is two levels below Category:Transport
+        let options = PathOptions {
+            include_categories: vec![category("Category:Transport")],
+            category_depth: 1,
+            ..Default::default()
+        };
+        assert_eq!(search(&mut graph, &from, &to, &options).await.path, None);
+        let options = PathOptions {
+            include_categories: vec![category("Category:Transport")],
+            category_depth: 2,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(middle(&result), Some("Locomotive".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_search_langlink_weight() {
+        let wikis = wikis();
+        // Three links on en, or two interlanguage links and one link on ja
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "A", &["B"], &[(1, "A")]),
+                (0, "B", &["C"], &[]),
+                (0, "C", &["D"], &[]),
+                (1, "A", &["D"], &[]),
+                (1, "D", &[], &[(0, "D")]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "A");
+        let to = Page::new(&wikis[0], "D");
+
+        let options = PathOptions {
+            langlinks: true,
+            ..Default::default()
+        };
+        let path = search(&mut graph, &from, &to, &options).await.path.unwrap();
+        assert_eq!(path.interlanguage_links, 0);
+        assert_eq!(path.cost, 3.0);
+
+        let options = PathOptions {
+            langlinks: true,
+            langlink_weight: 0.5,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            titles(&result),
+            vec![
+                ("en", "A", false),
+                ("ja", "A", true),
+                ("ja", "D", false),
+                ("en", "D", true),
+            ]
+        );
+        assert_eq!(result.path.unwrap().cost, 2.0);
+    }
+
+    // Titles of the pages of each path found
+    fn all_titles(search: &Search) -> Vec<Vec<&str>> {
+        search
+            .paths
+            .iter()
+            .map(|path| path.hops.iter().map(|hop| hop.title.as_str()).collect())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_search_all_shortest() {
+        let wikis = wikis();
+        // Two paths of two links from Train to Tram, and a third of three links
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Locomotive", "Horse", "Rail transport"], &[]),
+                (0, "Locomotive", &["Tram"], &[]),
+                (0, "Horse", &["Horsecar"], &[]),
+                (0, "Horsecar", &["Tram"], &[]),
+                (0, "Rail transport", &["Tram"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert!(result.paths.is_empty());
+        assert_eq!(result.path.unwrap().links, 2);
+
+        let options = PathOptions {
+            mode: Mode::AllShortest,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        let mut paths = all_titles(&result);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                vec!["Train", "Locomotive", "Tram"],
+                vec!["Train", "Rail transport", "Tram"],
+            ]
+        );
+        assert!(!result.truncated);
+        assert_eq!(result.path.as_ref(), result.paths.first());
+        let edges: Vec<Option<Edge>> = result.paths[1].hops.iter().map(|hop| hop.edge).collect();
+        assert_eq!(edges, vec![None, Some(Edge::Link), Some(Edge::Link)]);
+
+        // Paths through ties are still held to max_hops
+        let options = PathOptions {
+            mode: Mode::AllShortest,
+            max_hops: 1,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path, None);
+        assert!(result.paths.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_all_shortest_truncated() {
+        let wikis = wikis();
+        // Two pages between each level, so 2^6 paths of six links from A to Z
+        let mut pages: Vec<(String, Vec<String>)> = Vec::new();
+        let level = |level: usize| vec![format!("L{}a", level), format!("L{}b", level)];
+        pages.push(("A".to_string(), level(1)));
+        for depth in 1..=5 {
+            for page in level(depth) {
+                pages.push((page, level(depth + 1)));
+            }
+        }
+        for page in level(6) {
+            pages.push((page, vec!["Z".to_string()]));
+        }
+        let mut graph = MapGraph(
+            pages
+                .into_iter()
+                .map(|(title, links)| {
+                    let outbound = links.iter().map(|link| Entry::get_digest(link)).collect();
+                    let entry = Entry::new_for(&wikis[0], title, outbound);
+                    (entry.digest(), entry)
+                })
+                .collect(),
+        );
+        let options = PathOptions {
+            mode: Mode::AllShortest,
+            max_hops: 7,
+            ..Default::default()
+        };
+        let from = Page::new(&wikis[0], "A");
+        let to = Page::new(&wikis[0], "Z");
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.paths.len(), MAX_PATHS);
+        assert!(result.truncated);
+        assert!(result.paths.iter().all(|path| path.links == 7));
+    }
+
+    #[tokio::test]
+    async fn test_search_k_shortest() {
+        let wikis = wikis();
+        // Seven loopless paths from C to H: three of three links, three of four, and one of five
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "C", &["D", "E"], &[]),
+                (0, "D", &["F"], &[]),
+                (0, "E", &["D", "F", "G"], &[]),
+                (0, "F", &["G", "H"], &[]),
+                (0, "G", &["H"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "C");
+        let to = Page::new(&wikis[0], "H");
+        let options = PathOptions {
+            mode: Mode::KShortest(3),
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        let mut paths = all_titles(&result);
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                vec!["C", "D", "F", "H"],
+                vec!["C", "E", "F", "H"],
+                vec!["C", "E", "G", "H"]
+            ]
+        );
+
+        // Every loopless path is found, and no more
+        let options = PathOptions {
+            mode: Mode::KShortest(10),
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        let mut paths = all_titles(&result);
+        let links: Vec<usize> = result.paths.iter().map(|path| path.links).collect();
+        assert_eq!(links, vec![3, 3, 3, 4, 4, 4, 5]);
+        paths.sort();
+        paths.dedup();
+        assert_eq!(paths.len(), 7);
+        assert_eq!(result.path.unwrap().links, 3);
+
+        // Paths are held to max_hops
+        let options = PathOptions {
+            mode: Mode::KShortest(10),
+            max_hops: 3,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.paths.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_rank() {
+        let wikis = wikis();
+        // Two paths from Train to Tram. Horsecar links back to Train, and is the first link of Train
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Horsecar", "Locomotive"], &[]),
+                (0, "Locomotive", &["Tram"], &[]),
+                (0, "Horsecar", &["Train", "Tram"], &[]),
+                (0, "Tram", &["Horsecar"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let options = PathOptions {
+            mode: Mode::AllShortest,
+            rank: true,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            all_titles(&result),
+            vec![
+                vec!["Train", "Horsecar", "Tram"],
+                vec!["Train", "Locomotive", "Tram"],
+            ]
+        );
+        assert_eq!(result.path.as_ref(), result.paths.first());
+
+        // Train -> Horsecar is reciprocal, in first position. Horsecar -> Tram is reciprocal, in second position
+        let best = &result.paths[0];
+        assert!(best.hops[0].score.is_none());
+        assert_eq!(
+            best.hops[1].score,
+            Some(LinkScore {
+                reciprocal: true,
+                position: Some(1.0),
+                hub_penalty: 0.0,
+                pagerank: None,
+                score: 1.5,
+            })
+        );
+        assert_eq!(
+            best.score,
+            Some(PathScore {
+                score: 1.375,
+                reciprocal_links: 2,
+                position: Some(0.75),
+                hub_penalty: 0.0,
+            })
+        );
+        assert_eq!(result.paths[1].score.as_ref().unwrap().score, 0.375);
+
+        // Train links to Locomotive as a hub: four links in to one out
+        let locomotive = Entry::get_digest_for(&wikis[0], "Locomotive");
+        for from in ["A", "B", "C", "D"] {
+            graph
+                .0
+                .get_mut(&locomotive)
+                .unwrap()
+                .add_inbound(Entry::get_digest_for(&wikis[0], from));
+        }
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            result.paths[1].hops[1].score.as_ref().unwrap().hub_penalty,
+            0.5
+        );
+
+        // Without rank, nothing is scored
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(result.path.unwrap().score, None);
+    }
+
+    #[tokio::test]
+    async fn test_search_reciprocal() {
+        let wikis = wikis();
+        // Train -> Locomotive -> Tram is the shortest path, but Locomotive does not link back to Train
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Locomotive", "Rail transport"], &[]),
+                (0, "Locomotive", &["Tram"], &[]),
+                (0, "Rail transport", &["Train", "Light rail"], &[]),
+                (0, "Light rail", &["Rail transport", "Tram"], &[]),
+                (0, "Tram", &["Locomotive", "Light rail"], &[]),
+            ],
+        );
+        // The back-links held by each page, as the workers hold them
+        let links: Vec<(Digest, Digest)> = graph
+            .0
+            .values()
+            .flat_map(|entry| entry.get_outbound().iter().map(|to| (entry.digest(), *to)))
+            .collect();
+        for (from, to) in links {
+            graph.0.get_mut(&to).unwrap().add_inbound(from);
+        }
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+
+        let result = search(&mut graph, &from, &to, &PathOptions::default()).await;
+        assert_eq!(result.path.unwrap().links, 2);
+
+        let options = PathOptions {
+            reciprocal: true,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            titles(&result),
+            vec![
+                ("en", "Train", false),
+                ("en", "Rail transport", false),
+                ("en", "Light rail", false),
+                ("en", "Tram", false),
+            ]
+        );
+
+        // Links into other namespaces are not followed
+        let train = Entry::get_digest_for(&wikis[0], "Train");
+        graph.0.get_mut(&train).unwrap().set_namespace_links(vec![(
+            14,
+            Entry::get_digest_for(&wikis[0], "Category:Trams"),
+        )]);
+        graph.0.insert(
+            Entry::get_digest_for(&wikis[0], "Category:Trams"),
+            Entry::new_for(&wikis[0], "Category:Trams".to_string(), vec![to.digest]),
+        );
+        let options = PathOptions {
+            reciprocal: true,
+            namespaces: vec![14],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path.unwrap().links, 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_reciprocal_cold() {
+        // No pages are held by the workers, so the back-links are not known when Train is expanded
+        let pages: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("Train", vec!["Locomotive", "Rail transport"]),
+            ("Locomotive", vec!["Tram"]),
+            ("Rail transport", vec!["Train", "Light rail"]),
+            ("Light rail", vec!["Rail transport", "Tram"]),
+            ("Tram", vec!["Locomotive", "Light rail"]),
+        ]);
+        let (tx_to_fetch, mut rx_by_fetch) = mpsc::channel(8);
+        let fetch = tokio::spawn(async move {
+            let mut requested = Vec::new();
+            while let Some(command) = rx_by_fetch.recv().await {
+                if let FetchCommand::Get { title, tx, .. } = command {
+                    let links = pages[title.as_str()]
+                        .iter()
+                        .map(|link| (0, link.to_string()))
+                        .collect();
+                    let fetch_entry =
+                        crate::fetch::FetchEntry::from_links(title.clone(), links, None);
+                    requested.push(title);
+                    tx.send(Ok(fetch_entry)).await.unwrap();
+                }
+            }
+            requested
+        });
+        let (join_handles, tx_commands) = worker::new(
+            &crate::foundation::tests::get_test_foundation(),
+            tx_to_fetch.clone(),
+        )
+        .await;
+
+        let wiki = wiki::default_wiki();
+        let mut graph = WorkerGraph::new(tx_commands.clone(), tx_to_fetch.clone(), 100);
+        graph.add_title(wiki, "Train");
+        let options = PathOptions {
+            reciprocal: true,
+            ..Default::default()
+        };
+        let result = search(
+            &mut graph,
+            &Page::new(wiki, "Train"),
+            &Page::new(wiki, "Tram"),
+            &options,
+        )
+        .await;
+        assert_eq!(
+            titles(&result),
+            vec![
+                ("en", "Train", false),
+                ("en", "Rail transport", false),
+                ("en", "Light rail", false),
+                ("en", "Tram", false),
+            ]
+        );
+
+        for tx_command in &tx_commands {
+            tx_command.send(WorkerCommand::End).await.unwrap();
+        }
+        worker::shut_down(join_handles).await.unwrap();
+        drop((graph, tx_to_fetch));
+        // Locomotive was read to find that it does not link back, and each page was fetched once
+        let mut requested = fetch.await.unwrap();
+        requested.sort();
+        assert_eq!(
+            requested,
+            vec![
+                "Light rail",
+                "Locomotive",
+                "Rail transport",
+                "Train",
+                "Tram"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_search_avoid() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (
+                    0,
+                    "Train",
+                    &["United States", "List of trains", "1825", "Locomotive"],
+                    &[],
+                ),
+                (0, "United States", &["Tram"], &[]),
+                (0, "List of trains", &["Tram"], &[]),
+                (0, "1825", &["Tram"], &[]),
+                (0, "Locomotive", &["Steam engine"], &[]),
+                (0, "Steam engine", &["Tram"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let through = |result: Search| titles(&result)[1].1.to_string();
+
+        let options = PathOptions {
+            avoid: vec![Entry::get_digest_for(&wikis[0], "United States")],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(through(result), "List of trains");
+
+        let options = PathOptions {
+            avoid: vec![Entry::get_digest_for(&wikis[0], "United States")],
+            avoid_pattern: Some(TitlePattern(Regex::new(r"^List of|^\d{4}$").unwrap())),
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.filtered, 2);
+        assert_eq!(result.path.as_ref().unwrap().links, 3);
+        assert_eq!(through(result), "Locomotive");
+
+        // The first and the last page are never avoided
+        let options = PathOptions {
+            avoid: vec![from.digest, to.digest],
+            avoid_pattern: Some(TitlePattern(Regex::new("^T").unwrap())),
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path.unwrap().links, 2);
+    }
+
+    // Gives the title of each page without reading it, and records the pages read
+    struct TitledGraph(MapGraph, Vec<String>);
+
+    impl Graph for TitledGraph {
+        async fn get(&mut self, digest: Digest) -> Option<Entry> {
+            let entry = self.0.get(digest).await?;
+            self.1.push(entry.get_title().to_string());
+            Some(entry)
+        }
+
+        fn title(&self, digest: Digest) -> Option<&str> {
+            self.0 .0.get(&digest).map(|entry| entry.get_title())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_avoid_pattern_unread() {
+        let wikis = wikis();
+        let graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["List of trains", "1825", "Locomotive"], &[]),
+                (0, "List of trains", &["Tram"], &[]),
+                (0, "1825", &["Tram"], &[]),
+                (0, "Locomotive", &["Steam engine"], &[]),
+                (0, "Steam engine", &["Tram"], &[]),
+            ],
+        );
+        let mut graph = TitledGraph(graph, Vec::new());
+        let options = PathOptions {
+            avoid_pattern: Some(TitlePattern(Regex::new(r"^List of|^\d{4}$").unwrap())),
+            ..Default::default()
+        };
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.filtered, 2);
+        assert_eq!(result.path.unwrap().links, 3);
+        // The pages matched are never read
+        assert_eq!(graph.1, vec!["Train", "Locomotive", "Steam engine"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_reciprocal_reads() {
+        let wikis = wikis();
+        // No back-links are held, so each link is confirmed from the links of the page it leads to
+        let graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Rail transport", "Steam engine"], &[]),
+                (0, "Rail transport", &["Train", "Steam engine", "Tram"], &[]),
+                (0, "Steam engine", &["Train"], &[]),
+                (0, "Tram", &["Rail transport"], &[]),
+            ],
+        );
+        let mut graph = TitledGraph(graph, Vec::new());
+        let options = PathOptions {
+            reciprocal: true,
+            ..Default::default()
+        };
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path.unwrap().links, 2);
+        // Links back to pages already reached, such as Train and Steam engine from Rail transport, are not checked
+        let reads = |title| graph.1.iter().filter(|read| *read == title).count();
+        assert_eq!(reads("Train"), 1);
+        assert_eq!(reads("Steam engine"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_search_via() {
+        let wikis = wikis();
+        let mut graph = graph(
+            &wikis,
+            &[
+                (0, "Train", &["Tram", "Locomotive"], &[]),
+                (0, "Locomotive", &["Steam engine", "Train"], &[]),
+                (0, "Steam engine", &["Tram", "Train"], &[]),
+            ],
+        );
+        let from = Page::new(&wikis[0], "Train");
+        let to = Page::new(&wikis[0], "Tram");
+        let options = PathOptions {
+            via: vec![Page::new(&wikis[0], "Steam engine")],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(
+            titles(&result),
+            vec![
+                ("en", "Train", false),
+                ("en", "Locomotive", false),
+                ("en", "Steam engine", false),
+                ("en", "Tram", false),
+            ]
+        );
+        assert_eq!(result.path.as_ref().unwrap().cost, 3.0);
+
+        // A leg may not go back through the legs before it
+        let options = PathOptions {
+            via: vec![
+                Page::new(&wikis[0], "Steam engine"),
+                Page::new(&wikis[0], "Locomotive"),
+            ],
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path, None);
+
+        // The whole path is held to max_hops
+        let options = PathOptions {
+            via: vec![Page::new(&wikis[0], "Steam engine")],
+            max_hops: 2,
+            ..Default::default()
+        };
+        let result = search(&mut graph, &from, &to, &options).await;
+        assert_eq!(result.path, None);
+    }
+
+    #[tokio::test]
+    async fn test_add_context() {
+        let wiki = wiki::default_wiki();
+        let hop = |title: &str, edge| Hop {
+            digest: Entry::get_digest_for(wiki, title),
+            wiki: wiki.id.clone(),
+            title: title.to_string(),
+            edge,
+            interlanguage: false,
+            context: None,
+            score: None,
+        };
+        let mut path = Path {
+            hops: vec![
+                hop("Train", None),
+                hop("Locomotive", Some(Edge::Link)),
+                hop("Tram", Some(Edge::Link)),
+            ],
+            cost: 2.0,
+            links: 2,
+            interlanguage_links: 0,
+            score: None,
+        };
+
+        // Train gives the context of its link to Locomotive. Locomotive has no link to Tram in its wikitext
+        let (tx_to_fetch, mut rx_by_fetch) = mpsc::channel(1);
+        let fetch = tokio::spawn(async move {
+            let mut requested = Vec::new();
+            while let Some(FetchCommand::Context { title, tx, .. }) = rx_by_fetch.recv().await {
+                let contexts = match title.as_str() {
+                    "Train" => vec![(
+                        Entry::get_digest_for(wiki, "Locomotive"),
+                        "Trains are hauled by a locomotive.".to_string(),
+                    )],
+                    _ => Vec::new(),
+                };
+                requested.push(title);
+                tx.send(Ok(contexts)).await.unwrap();
+            }
+            requested
+        });
+        add_context(&mut path, &tx_to_fetch).await;
+        drop(tx_to_fetch);
+
+        assert_eq!(fetch.await.unwrap(), vec!["Train", "Locomotive"]);
+        assert_eq!(path.hops[0].context, None);
+        assert_eq!(
+            path.hops[1].context.as_deref(),
+            Some("Trains are hauled by a locomotive.")
+        );
+        assert_eq!(path.hops[2].context, None);
+        assert!(!serde_json::to_string(&path.hops[2])
+            .unwrap()
+            .contains("context"));
+    }
+}
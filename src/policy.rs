@@ -0,0 +1,272 @@
+/* *****************************************************************************************************************
+ *
+ * Link policy
+ * -----------
+ *
+ * Links from a page into articles (namespace 0) are always held, and may always be followed by path search. Links
+ * into other namespaces are dropped, unless the namespace is named by opt::link_namespace, as the namespace name or
+ * number. Add "=traverse" to let path search follow links into the namespace:
+ *
+ *    --link_namespace category=traverse --link_namespace portal --link_namespace 10
+ *
+ * Links into each namespace are a separate edge type, so a path through Category:Rail transport is reported as a
+ * link into a category. Path search follows the namespaces marked traverse, unless the query names others with
+ * ?traverse=. A query can only traverse namespaces whose links are held.
+ *
+ * Pages are cached with links into every namespace, so the policy can be changed without fetching pages again. Pages
+ * cached by earlier versions, and dump imports, hold links into articles only.
+ *
+ *******************************************************************************************************************/
+
+use serde::{Serialize, Serializer};
+use std::{fmt, sync::OnceLock};
+
+use crate::title;
+
+static POLICY: OnceLock<LinkPolicy> = OnceLock::new();
+
+static TRAVERSE: &str = "traverse";
+
+// ***********************************************************************************************
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NamespacePolicy {
+    pub ns: i32,
+    // Path search may follow links into the namespace
+    pub traverse: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkPolicy {
+    namespaces: Vec<NamespacePolicy>,
+}
+
+/// The kind of link followed from one page to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Link,
+    Namespace(i32),
+    Interlanguage,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PolicyError {
+    UnknownNamespace(String),
+    InvalidOption(String),
+    NotHeld(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PolicyError::UnknownNamespace(name) => write!(f, "{} is not a namespace", name),
+            PolicyError::InvalidOption(option) => {
+                write!(f, "Unable to read link namespace option {}", option)
+            }
+            PolicyError::NotHeld(name) => write!(f, "Links into {} are not held", name),
+        }
+    }
+}
+
+impl std::error::Error for PolicyError {}
+
+impl LinkPolicy {
+    /// Build the policy from opt::link_namespace
+    pub fn from_options(options: &[String]) -> Result<LinkPolicy, PolicyError> {
+        let mut policy = LinkPolicy::default();
+        for option in options {
+            let (name, traverse) = match option.split_once('=') {
+                Some((name, flag)) if flag.trim().eq_ignore_ascii_case(TRAVERSE) => (name, true),
+                Some(_) => return Err(PolicyError::InvalidOption(option.clone())),
+                None => (option.as_str(), false),
+            };
+            let ns = read_namespace(name)?;
+            if ns == 0 || policy.holds(ns) {
+                return Err(PolicyError::InvalidOption(option.clone()));
+            }
+            policy.namespaces.push(NamespacePolicy { ns, traverse });
+        }
+        Ok(policy)
+    }
+
+    /// True if links into the namespace are held
+    pub fn holds(&self, ns: i32) -> bool {
+        ns == 0 || self.namespaces.iter().any(|policy| policy.ns == ns)
+    }
+
+    /// The namespaces, other than articles, that path search follows unless told otherwise
+    pub fn traversed(&self) -> Vec<i32> {
+        self.namespaces
+            .iter()
+            .filter(|policy| policy.traverse)
+            .map(|policy| policy.ns)
+            .collect()
+    }
+
+    /// Read a list of namespaces to traverse, such as "category,portal". Each must be held
+    pub fn read_traverse(&self, names: &str) -> Result<Vec<i32>, PolicyError> {
+        names
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| {
+                let ns = read_namespace(name)?;
+                if !self.holds(ns) {
+                    return Err(PolicyError::NotHeld(name.trim().to_string()));
+                }
+                Ok(ns)
+            })
+            .filter(|ns| *ns != Ok(0))
+            .collect()
+    }
+
+    pub fn namespaces(&self) -> &[NamespacePolicy] {
+        &self.namespaces
+    }
+}
+
+// A namespace, by name or number
+fn read_namespace(name: &str) -> Result<i32, PolicyError> {
+    let name = name.trim();
+    name.parse::<i32>()
+        .ok()
+        .or_else(|| title::namespace(name))
+        .or_else(|| {
+            ["article", "main"]
+                .contains(&name.to_ascii_lowercase().as_str())
+                .then_some(0)
+        })
+        .ok_or_else(|| PolicyError::UnknownNamespace(name.to_string()))
+}
+
+/// Name of the namespace, in lower case, for logs and results
+pub fn namespace_name(ns: i32) -> String {
+    match title::namespace_name(ns) {
+        Some(name) => name.to_lowercase(),
+        None => format!("namespace {}", ns),
+    }
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edge::Link => write!(f, "link"),
+            Edge::Namespace(ns) => write!(f, "{}", namespace_name(*ns)),
+            Edge::Interlanguage => write!(f, "interlanguage"),
+        }
+    }
+}
+
+impl Serialize for Edge {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Registry
+ *
+ *******************************************************************************************************************/
+
+/// Set the policy. Called once, before any pages are fetched
+pub fn init(policy: LinkPolicy) {
+    if POLICY.set(policy).is_err() {
+        warn!("The link policy has already been set");
+    }
+}
+
+/// The policy. Links into articles alone if init has not been called
+pub fn get() -> &'static LinkPolicy {
+    POLICY.get_or_init(LinkPolicy::default)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(options: &[&str]) -> Vec<String> {
+        options.iter().map(|option| option.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_options() {
+        let policy =
+            LinkPolicy::from_options(&options(&["category=traverse", "Portal", "10"])).unwrap();
+        assert_eq!(
+            policy.namespaces(),
+            &[
+                NamespacePolicy {
+                    ns: 14,
+                    traverse: true
+                },
+                NamespacePolicy {
+                    ns: 100,
+                    traverse: false
+                },
+                NamespacePolicy {
+                    ns: 10,
+                    traverse: false
+                },
+            ]
+        );
+        assert!(policy.holds(0));
+        assert!(policy.holds(100));
+        assert!(!policy.holds(2));
+        assert_eq!(policy.traversed(), vec![14]);
+
+        let policy = LinkPolicy::default();
+        assert!(policy.holds(0));
+        assert!(!policy.holds(14));
+        assert!(policy.traversed().is_empty());
+    }
+
+    #[test]
+    fn test_from_options_fail() {
+        assert_eq!(
+            LinkPolicy::from_options(&options(&["Gallery"])),
+            Err(PolicyError::UnknownNamespace("Gallery".to_string()))
+        );
+        assert_eq!(
+            LinkPolicy::from_options(&options(&["category=yes"])),
+            Err(PolicyError::InvalidOption("category=yes".to_string()))
+        );
+        assert_eq!(
+            LinkPolicy::from_options(&options(&["category", "14=traverse"])),
+            Err(PolicyError::InvalidOption("14=traverse".to_string()))
+        );
+        assert_eq!(
+            LinkPolicy::from_options(&options(&["article"])),
+            Err(PolicyError::InvalidOption("article".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_read_traverse() {
+        let policy = LinkPolicy::from_options(&options(&["category=traverse", "portal"])).unwrap();
+        assert_eq!(policy.read_traverse("portal, Category"), Ok(vec![100, 14]));
+        assert_eq!(policy.read_traverse("article"), Ok(vec![]));
+        assert_eq!(policy.read_traverse(""), Ok(vec![]));
+        assert_eq!(
+            policy.read_traverse("template"),
+            Err(PolicyError::NotHeld("template".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_edge_names() {
+        assert_eq!(Edge::Link.to_string(), "link");
+        assert_eq!(Edge::Namespace(14).to_string(), "category");
+        assert_eq!(Edge::Namespace(100).to_string(), "portal");
+        assert_eq!(Edge::Namespace(2600).to_string(), "namespace 2600");
+        assert_eq!(
+            serde_json::to_string(&Edge::Interlanguage).unwrap(),
+            r#""interlanguage""#
+        );
+    }
+}
@@ -0,0 +1,333 @@
+/* *****************************************************************************************************************
+ *
+ * Refresh
+ * -------
+ *
+ * Keep the pages held fresh without crawling them again. Every opt::refresh_interval seconds, the changes made to
+ * each wiki since the last poll are read from list=recentchanges, or from opt::recentchanges, a file of changes
+ * exported from the wiki. Each changed page that is in the page cache, and whose cached copy is older than the
+ * change, is pulled from the wiki again through the fetch rate limiter. Pages that are not cached are skipped, as they
+ * are pulled when they are first needed.
+ *
+ * A refreshed page that the workers hold is applied to the workers as WorkerCommand::Update. The outbound links of
+ * the held and refreshed versions are compared: a back-link is sent for each link added, and WorkerCommand::
+ * RemoveBacklink for each link removed, so the inbound links held by each worker stay consistent.
+ *
+ * The file holds one change per line, as JSON, with the fields of a recentchanges item:
+ *
+ *    {"wiki": "en", "title": "Train", "revid": 1234, "timestamp": "2024-05-01T12:30:00Z"}
+ *
+ * wiki may be left out for changes to the default wiki. The file is read again at each poll, and changes made before
+ * the last poll are skipped.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{self, Duration},
+};
+
+use crate::entry::Entry;
+use crate::fetch::{self, FetchCommand, RecentChange};
+use crate::policy;
+use crate::wiki::{self, Wiki};
+use crate::worker::{self, TxCommands, WorkerCommand};
+
+// ***********************************************************************************************
+
+/// Where the changes to each wiki are read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    Api,
+    File(PathBuf),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    // Pages changed since the last poll
+    pub changed: usize,
+    // Pages pulled from the wiki again
+    pub refreshed: usize,
+    // Refreshed pages applied to the workers
+    pub updated: usize,
+    pub errors: usize,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pages changed: {}, refreshed: {}, updated in the workers: {}, errors: {}",
+            self.changed, self.refreshed, self.updated, self.errors
+        )
+    }
+}
+
+/// Start the refresher
+pub fn new(
+    tx_to_fetch: mpsc::Sender<FetchCommand>,
+    tx_to_workers: TxCommands,
+    interval: Duration,
+    source: Source,
+) -> JoinHandle<()> {
+    trace!("refresh::new");
+    tokio::spawn(async move { refresh_service(tx_to_fetch, tx_to_workers, interval, source).await })
+}
+
+async fn refresh_service(
+    tx_to_fetch: mpsc::Sender<FetchCommand>,
+    tx_to_workers: TxCommands,
+    interval: Duration,
+    source: Source,
+) {
+    let started = fetch::now();
+    let mut since: HashMap<&str, u64> = wiki::all()
+        .iter()
+        .map(|wiki| (wiki.id.as_str(), started))
+        .collect();
+    let mut interval = time::interval(interval);
+    // The first tick completes at once
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for wiki in wiki::all() {
+            let last = since[wiki.id.as_str()];
+            let changes = match &source {
+                Source::Api => get_recent_changes(wiki, last, &tx_to_fetch).await,
+                Source::File(path) => {
+                    read_changes(path, wiki, wiki::default_wiki()).map_err(|err| err.to_string())
+                }
+            };
+            let changes = match changes {
+                Ok(changes) => changes,
+                Err(err) => {
+                    warn!(
+                        "Unable to read the recent changes of wiki {}: {}",
+                        wiki.id, err
+                    );
+                    continue;
+                }
+            };
+            if let Some(newest) = changes.iter().filter_map(RecentChange::changed).max() {
+                since.insert(wiki.id.as_str(), std::cmp::max(last, newest));
+            }
+            let changes = latest(changes, last);
+            if changes.is_empty() {
+                continue;
+            }
+            let summary = refresh(wiki, changes, &tx_to_fetch, &tx_to_workers).await;
+            info!("Refreshed wiki {}. {}", wiki.id, summary);
+        }
+    }
+}
+
+async fn get_recent_changes(
+    wiki: &Wiki,
+    since: u64,
+    tx_to_fetch: &mpsc::Sender<FetchCommand>,
+) -> Result<Vec<RecentChange>, String> {
+    let (tx, mut rx) = mpsc::channel(1);
+    let command = FetchCommand::RecentChanges {
+        wiki: wiki.id.clone(),
+        since,
+        tx,
+    };
+    let stopped = || "The fetch service has stopped".to_string();
+    tx_to_fetch.send(command).await.map_err(|_| stopped())?;
+    match rx.recv().await {
+        Some(changes) => changes.map_err(|err| err.to_string()),
+        None => Err(stopped()),
+    }
+}
+
+/// Read the changes to the wiki from a file of changes, one JSON change per line. Lines that cannot be read are
+/// skipped
+pub fn read_changes(
+    path: &Path,
+    wiki: &Wiki,
+    default_wiki: &Wiki,
+) -> Result<Vec<RecentChange>, io::Error> {
+    let mut changes = Vec::new();
+    for (number, line) in io::BufReader::new(fs::File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RecentChange>(&line) {
+            Ok(change) => {
+                let change_wiki = change.wiki.as_deref().unwrap_or(&default_wiki.id);
+                if change_wiki.eq_ignore_ascii_case(&wiki.id) {
+                    changes.push(change);
+                }
+            }
+            Err(err) => warn!(
+                "Unable to read line {} of {}: {}",
+                number + 1,
+                path.to_string_lossy(),
+                err
+            ),
+        }
+    }
+    Ok(changes)
+}
+
+/// The latest change to each page, made at or after `since`, in the order in which the pages were first changed
+pub fn latest(changes: Vec<RecentChange>, since: u64) -> Vec<RecentChange> {
+    let mut latest: Vec<RecentChange> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for change in changes {
+        if change.changed().is_none_or(|changed| changed < since) {
+            continue;
+        }
+        match index.get(&change.title) {
+            Some(&position) if latest[position].revid >= change.revid => {}
+            Some(&position) => latest[position] = change,
+            None => {
+                index.insert(change.title.clone(), latest.len());
+                latest.push(change);
+            }
+        }
+    }
+    latest
+}
+
+/// Pull each changed page that is cached again, and apply the pages that the workers hold
+pub async fn refresh(
+    wiki: &Wiki,
+    changes: Vec<RecentChange>,
+    tx_to_fetch: &mpsc::Sender<FetchCommand>,
+    tx_to_workers: &TxCommands,
+) -> Summary {
+    let mut summary = Summary {
+        changed: changes.len(),
+        ..Default::default()
+    };
+    for change in changes {
+        let title = change.title.clone();
+        let (tx, mut rx) = mpsc::channel(1);
+        let command = FetchCommand::Refresh {
+            wiki: wiki.id.clone(),
+            change,
+            tx,
+        };
+        if tx_to_fetch.send(command).await.is_err() {
+            break;
+        }
+        let fetch_entry = match rx.recv().await.flatten() {
+            Some(Ok(fetch_entry)) => fetch_entry,
+            Some(Err(err)) => {
+                warn!("Unable to refresh {} on wiki {}: {}", title, wiki.id, err);
+                summary.errors += 1;
+                continue;
+            }
+            None => continue,
+        };
+        summary.refreshed += 1;
+
+        let entry = fetch_entry.to_entry(wiki, wiki::all(), policy::get());
+        let held = match get_held(&entry, tx_to_workers).await {
+            Some(held) => held,
+            None => continue,
+        };
+        match worker::update_held(&held, entry, tx_to_workers).await {
+            Ok(_) => summary.updated += 1,
+            Err(_) => summary.errors += 1,
+        }
+    }
+    summary
+}
+
+// The version of the page that the workers hold, if they hold more than a stub
+async fn get_held(entry: &Entry, tx_to_workers: &TxCommands) -> Option<Entry> {
+    let digest = entry.digest();
+    let worker_id = worker::get_worker_id_for(digest, tx_to_workers.len());
+    let (tx, mut rx) = mpsc::channel(1);
+    tx_to_workers[worker_id]
+        .send(WorkerCommand::Get { digest, tx })
+        .await
+        .ok()?;
+    rx.recv().await.flatten().filter(|held| !held.is_stub())
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(title: &str, revid: u64, timestamp: &str) -> RecentChange {
+        RecentChange {
+            wiki: None,
+            title: title.to_string(),
+            revid,
+            timestamp: timestamp.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_latest() {
+        let since = fetch::read_timestamp("2024-05-01T12:00:00Z").unwrap();
+        let changes = vec![
+            change("Train", 10, "2024-05-01T11:59:59Z"),
+            change("Train", 12, "2024-05-01T12:05:00Z"),
+            change("Tram", 11, "2024-05-01T12:01:00Z"),
+            change("Train", 13, "2024-05-01T12:06:00Z"),
+            change("Tram", 9, "2024-05-01T12:00:00Z"),
+        ];
+        assert_eq!(
+            latest(changes, since),
+            vec![
+                change("Train", 13, "2024-05-01T12:06:00Z"),
+                change("Tram", 11, "2024-05-01T12:01:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_changes() {
+        let path = std::env::temp_dir().join(format!(
+            "six_degrees_recentchanges_{}.json",
+            std::process::id()
+        ));
+        fs::write(
+            &path,
+            r#"{"title": "Train", "revid": 10, "timestamp": "2024-05-01T12:00:00Z"}
+{"wiki": "de", "title": "Zug", "revid": 11, "timestamp": "2024-05-01T12:00:00Z"}
+not a change
+
+{"wiki": "EN", "title": "Tram", "revid": 12, "timestamp": "2024-05-01T12:00:00Z"}
+"#,
+        )
+        .unwrap();
+        let en = Wiki::new("en", "https://en.wikipedia.org/", 10);
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+
+        let titles = |wiki| -> Vec<String> {
+            read_changes(&path, wiki, &en)
+                .unwrap()
+                .into_iter()
+                .map(|change| change.title)
+                .collect()
+        };
+        assert_eq!(titles(&en), vec!["Train", "Tram"]);
+        assert_eq!(titles(&de), vec!["Zug"]);
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_changes(&path, &en, &en).is_err());
+    }
+}
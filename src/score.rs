@@ -0,0 +1,252 @@
+/* *****************************************************************************************************************
+ *
+ * Score
+ * -----
+ *
+ * Rank the paths found by a search by the strength of their links. Each link from one page to the next is scored on
+ *    reciprocity   the page linked to links back. For an interlanguage link, the page linked to has an
+ *                  interlanguage link back
+ *    position      how early the link is among the links of the page it is from, from 1 for the first link to near 0
+ *                  for the last. Links near the top of a page, in the lead, are usually the most closely related.
+ *                  Interlanguage links have no position, and score POSITION_UNKNOWN
+ *    hub penalty   the page linked to is a hub, whose inbound links outnumber its outbound links by more than the
+ *                  opt::fake_hub percentage. The penalty rises from 0 at the threshold towards 1 as the ratio grows,
+ *                  so paths that pass through hubs such as countries or years rank below paths through specific
+ *                  pages. The last page of a path is chosen by the search, and is not penalised
+ *    pagerank      the PageRank of the page linked to, relative to the mean, as stored by pagerank::compute. It is
+ *                  scored as pagerank / (1 + pagerank), from 0 towards 1, so an average page scores 0.5 and no page
+ *                  outweighs the other scores. Until PageRank has been computed, and for the last page of a path, it
+ *                  is not scored
+ *
+ *    link score = RECIPROCITY_WEIGHT * reciprocal + POSITION_WEIGHT * position - HUB_WEIGHT * hub penalty
+ *                 + PAGERANK_WEIGHT * pagerank
+ *
+ * The score of a path is the mean of the scores of its links, so paths of different lengths can be compared. The
+ * inbound counts are those of the back-links held by the workers, so a hub is only recognised once enough of the
+ * pages that link to it have been loaded.
+ *
+ *******************************************************************************************************************/
+
+use crate::entry::{Digest, Entry};
+use crate::policy::Edge;
+
+static RECIPROCITY_WEIGHT: f64 = 1.0;
+static POSITION_WEIGHT: f64 = 0.5;
+static HUB_WEIGHT: f64 = 1.0;
+static PAGERANK_WEIGHT: f64 = 0.5;
+// Position of a link that has none
+static POSITION_UNKNOWN: f64 = 0.5;
+
+// ***********************************************************************************************
+
+/// Score of the link into a page
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LinkScore {
+    pub reciprocal: bool,
+    pub position: Option<f64>,
+    pub hub_penalty: f64,
+    // Relative PageRank of the page linked to, if it has been computed
+    pub pagerank: Option<f64>,
+    pub score: f64,
+}
+
+/// Score of a path, with the breakdown of the scores of its links
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct PathScore {
+    // Mean of the scores of the links
+    pub score: f64,
+    pub reciprocal_links: usize,
+    // Mean position of the links that have one
+    pub position: Option<f64>,
+    // Sum of the hub penalties
+    pub hub_penalty: f64,
+}
+
+/// Score the link from one page to the next. `last` is true if the page linked to is the last page of the path
+pub fn score_link(from: &Entry, to: &Entry, edge: Edge, last: bool, fake_hub: u32) -> LinkScore {
+    let (reciprocal, position) = match edge {
+        Edge::Link => (
+            to.get_outbound().contains(&from.digest()),
+            position(from.get_outbound().iter(), to.digest()),
+        ),
+        Edge::Namespace(ns) => (
+            to.get_outbound().contains(&from.digest())
+                || to
+                    .get_namespace_links()
+                    .iter()
+                    .any(|(_, link)| *link == from.digest()),
+            position(
+                from.get_namespace_links()
+                    .iter()
+                    .filter(|(link_ns, _)| *link_ns == ns)
+                    .map(|(_, link)| link),
+                to.digest(),
+            ),
+        ),
+        Edge::Interlanguage => (
+            to.get_langlinks()
+                .iter()
+                .any(|(_, link)| *link == from.digest()),
+            None,
+        ),
+    };
+    let (hub_penalty, pagerank) = match last {
+        true => (0.0, None),
+        false => (hub_penalty(to, fake_hub), to.get_pagerank()),
+    };
+    let score = RECIPROCITY_WEIGHT * if reciprocal { 1.0 } else { 0.0 }
+        + POSITION_WEIGHT * position.unwrap_or(POSITION_UNKNOWN)
+        - HUB_WEIGHT * hub_penalty
+        + PAGERANK_WEIGHT * pagerank.map_or(0.0, |pagerank| pagerank / (1.0 + pagerank));
+    LinkScore {
+        reciprocal,
+        position,
+        hub_penalty,
+        pagerank,
+        score,
+    }
+}
+
+/// Score a path from the scores of its links
+pub fn score_path(links: &[&LinkScore]) -> PathScore {
+    let positions: Vec<f64> = links.iter().filter_map(|link| link.position).collect();
+    let mean = |values: &mut dyn Iterator<Item = f64>, count: usize| match count {
+        0 => None,
+        count => Some(values.sum::<f64>() / count as f64),
+    };
+    PathScore {
+        score: mean(&mut links.iter().map(|link| link.score), links.len()).unwrap_or_default(),
+        reciprocal_links: links.iter().filter(|link| link.reciprocal).count(),
+        position: mean(&mut positions.iter().copied(), positions.len()),
+        hub_penalty: links.iter().map(|link| link.hub_penalty).sum(),
+    }
+}
+
+/// Penalty for passing through a page whose inbound links outnumber its outbound links by more than fake_hub
+/// percent. 0 at or below the threshold, rising towards 1
+pub fn hub_penalty(entry: &Entry, fake_hub: u32) -> f64 {
+    let ratio = entry.get_inbound_count() as f64 * 100.0 / entry.get_outbound_count().max(1) as f64;
+    match fake_hub > 0 && ratio > fake_hub as f64 {
+        true => 1.0 - fake_hub as f64 / ratio,
+        false => 0.0,
+    }
+}
+
+// Position of the link among the links of the page, from 1 for the first
+fn position<'a>(links: impl Iterator<Item = &'a Digest> + Clone, to: Digest) -> Option<f64> {
+    let count = links.clone().count();
+    let index = links.into_iter().position(|link| *link == to)?;
+    Some(1.0 - index as f64 / count as f64)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(title: &str, links: &[&str]) -> Entry {
+        Entry::new(
+            title.to_string(),
+            links.iter().map(|link| Entry::get_digest(link)).collect(),
+        )
+    }
+
+    #[test]
+    fn test_score_link() {
+        let train = entry("Train", &["Locomotive", "Rail transport", "Tram", "Horse"]);
+        let tram = entry("Tram", &["Train"]);
+        let horse = entry("Horse", &["Mammal"]);
+
+        let link = score_link(&train, &tram, Edge::Link, false, 200);
+        assert!(link.reciprocal);
+        assert_eq!(link.position, Some(0.5));
+        assert_eq!(link.hub_penalty, 0.0);
+        assert_eq!(link.score, 1.25);
+
+        let link = score_link(&train, &horse, Edge::Link, false, 200);
+        assert!(!link.reciprocal);
+        assert_eq!(link.position, Some(0.25));
+        assert_eq!(link.score, 0.125);
+
+        let mut zug = Entry::new("Zug".to_string(), vec![]);
+        zug.set_langlinks(vec![("en".to_string(), train.digest())]);
+        let link = score_link(&train, &zug, Edge::Interlanguage, true, 200);
+        assert!(link.reciprocal);
+        assert_eq!(link.position, None);
+        assert_eq!(link.score, 1.25);
+    }
+
+    #[test]
+    fn test_hub_penalty() {
+        let mut hub = entry("United States", &["North America", "Washington, D.C."]);
+        for from in 0..8 {
+            hub.add_inbound(Entry::get_digest(&format!("Page {}", from)));
+        }
+        // 8 inbound to 2 outbound is 400%
+        assert_eq!(hub_penalty(&hub, 200), 0.5);
+        assert_eq!(hub_penalty(&hub, 400), 0.0);
+        assert_eq!(hub_penalty(&hub, 0), 0.0);
+
+        let train = entry("Train", &["United States"]);
+        let link = score_link(&train, &hub, Edge::Link, false, 200);
+        assert_eq!(link.hub_penalty, 0.5);
+        assert_eq!(link.score, 0.0);
+        let link = score_link(&train, &hub, Edge::Link, true, 200);
+        assert_eq!(link.hub_penalty, 0.0);
+    }
+
+    #[test]
+    fn test_score_pagerank() {
+        let train = entry("Train", &["Locomotive", "Tram"]);
+        let mut tram = entry("Tram", &["Light rail"]);
+        assert_eq!(
+            score_link(&train, &tram, Edge::Link, false, 200).pagerank,
+            None
+        );
+
+        tram.set_pagerank(3.0);
+        let link = score_link(&train, &tram, Edge::Link, false, 200);
+        assert_eq!(link.pagerank, Some(3.0));
+        // 0.5 * 0.5 for the position, and 0.5 * 3 / 4 for the PageRank
+        assert_eq!(link.score, 0.625);
+        let link = score_link(&train, &tram, Edge::Link, true, 200);
+        assert_eq!(link.pagerank, None);
+        assert_eq!(link.score, 0.25);
+    }
+
+    #[test]
+    fn test_score_path() {
+        let links = [
+            LinkScore {
+                reciprocal: true,
+                position: Some(1.0),
+                hub_penalty: 0.0,
+                pagerank: None,
+                score: 1.5,
+            },
+            LinkScore {
+                reciprocal: false,
+                position: None,
+                hub_penalty: 0.25,
+                pagerank: Some(1.0),
+                score: 0.0,
+            },
+        ];
+        let links: Vec<&LinkScore> = links.iter().collect();
+        assert_eq!(
+            score_path(&links),
+            PathScore {
+                score: 0.75,
+                reciprocal_links: 1,
+                position: Some(1.0),
+                hub_penalty: 0.25,
+            }
+        );
+        assert_eq!(score_path(&[]).score, 0.0);
+    }
+}
@@ -31,3 +31,144 @@
  * This model becomes problematic if the objects in the Links slab nee to be moved becasuse the number of links has changed
  *
  *********************************************************************************************************************/
+
+use std::collections::HashMap;
+
+use crate::entry::{Digest, Entry};
+
+/* ********************************************************************************************************************
+ *
+ * Slab
+ *
+ * Until the tiered memory strategy is in place, each slab holds its entries in a map keyed by digest. A worker holds
+ * foundation::slabs_per_worker slabs, and selects the slab for a digest using Worker::extract_slab_id_from
+ *
+ *********************************************************************************************************************/
+
+#[derive(Debug, Default)]
+pub struct Slab {
+    entries: HashMap<Digest, Entry>,
+}
+
+impl Slab {
+    pub fn new() -> Slab {
+        Slab::default()
+    }
+
+    pub fn get(&self, digest: &Digest) -> Option<&Entry> {
+        self.entries.get(digest)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Entry> {
+        self.entries.values_mut()
+    }
+
+    /// Add an entry, or replace the content of an existing entry. Back-links already held for the page are retained
+    pub fn update(&mut self, entry: Entry) {
+        match self.entries.get_mut(&entry.digest()) {
+            Some(existing) => existing.replace_with(entry),
+            None => {
+                self.entries.insert(entry.digest(), entry);
+            }
+        }
+    }
+
+    /// Record a link from `from` into `to`. If `to` has not been loaded, a stub entry is created to hold the back-link.
+    /// Returns the number of links into `to`
+    pub fn add_backlink(&mut self, to: Digest, from: Digest) -> usize {
+        let entry = self
+            .entries
+            .entry(to)
+            .or_insert_with(|| Entry::new_stub(to));
+        entry.add_inbound(from);
+        entry.get_inbound().len()
+    }
+
+    /// Remove the link from `from` into `to`. A stub left with no back-links is removed. Returns true if the link was
+    /// held
+    pub fn remove_backlink(&mut self, to: Digest, from: Digest) -> bool {
+        let entry = match self.entries.get_mut(&to) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let removed = entry.remove_inbound(from);
+        if entry.is_stub() && entry.get_inbound().is_empty() {
+            self.entries.remove(&to);
+        }
+        removed
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backlink_creates_stub() {
+        let mut slab = Slab::new();
+        let train = Entry::get_digest("Train");
+        assert_eq!(
+            slab.add_backlink(train, Entry::get_digest("Rail transport")),
+            1
+        );
+        assert_eq!(slab.add_backlink(train, Entry::get_digest("Railway")), 2);
+
+        assert_eq!(slab.len(), 1);
+        let entry = slab.get(&train).unwrap();
+        assert!(entry.is_stub());
+        assert_eq!(entry.get_inbound().len(), 2);
+    }
+
+    #[test]
+    fn test_remove_backlink() {
+        let mut slab = Slab::new();
+        let train = Entry::get_digest("Train");
+        let tram = Entry::get_digest("Tram");
+        let rail_transport = Entry::get_digest("Rail transport");
+        slab.update(Entry::new("Train".to_string(), vec![]));
+        slab.add_backlink(train, rail_transport);
+        slab.add_backlink(tram, rail_transport);
+
+        assert!(slab.remove_backlink(train, rail_transport));
+        assert!(!slab.remove_backlink(train, rail_transport));
+        assert!(slab.get(&train).unwrap().get_inbound().is_empty());
+
+        // The stub held only for the back-link is removed with it
+        assert!(slab.remove_backlink(tram, rail_transport));
+        assert!(slab.get(&tram).is_none());
+        assert_eq!(slab.len(), 1);
+        assert!(!slab.remove_backlink(tram, rail_transport));
+    }
+
+    #[test]
+    fn test_update_after_backlink() {
+        let mut slab = Slab::new();
+        let train = Entry::get_digest("Train");
+        slab.add_backlink(train, Entry::get_digest("Rail transport"));
+        slab.update(Entry::new(
+            "Train".to_string(),
+            vec![Entry::get_digest("Locomotive")],
+        ));
+
+        assert_eq!(slab.len(), 1);
+        let entry = slab.get(&train).unwrap();
+        assert_eq!(entry.get_title(), "Train");
+        assert_eq!(entry.get_outbound().len(), 1);
+        assert_eq!(entry.get_inbound().len(), 1);
+    }
+}
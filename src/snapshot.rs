@@ -0,0 +1,231 @@
+/* *****************************************************************************************************************
+ *
+ * Snapshot
+ * --------
+ *
+ * A snapshot is a file of Entries that can be bulk-loaded into the workers. Snapshots are written by the dump
+ * importers, and loaded into the workers at startup with the --snapshot option.
+ *
+ * Format
+ *    MAGIC (8 bytes, includes the format version)
+ *    repeated for each entry
+ *       length of the serialized entry (u32, little-endian)
+ *       the entry, serialized with Entry::to
+ *
+ * Snapshots written by earlier versions (MAGIC_EARLIER) cannot be loaded: the first version did not hold the wiki in
+ * the digests, the second did not hold interlanguage links, the third did not hold links into other namespaces, and
+ * the fourth did not hold categories. They must be imported again from the dumps.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::entry::Entry;
+use crate::worker::{self, TxCommands};
+
+static MAGIC: &[u8; 8] = b"6DSNAP05";
+static MAGIC_EARLIER: &[&[u8; 8]] = &[b"6DSNAP01", b"6DSNAP02", b"6DSNAP03", b"6DSNAP04"];
+
+// ***********************************************************************************************
+
+// Reported by the dump importers
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub pages: u64,
+    pub redirects: u64,
+    pub links: u64,
+    pub entries: u64,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pages: {}, redirects: {}, links: {}, entries written: {}",
+            self.pages, self.redirects, self.links, self.entries
+        )
+    }
+}
+
+pub struct SnapshotWriter {
+    writer: BufWriter<File>,
+    count: u64,
+}
+
+impl SnapshotWriter {
+    pub fn create(path: &Path) -> Result<SnapshotWriter, io::Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        Ok(SnapshotWriter { writer, count: 0 })
+    }
+
+    pub fn write(&mut self, entry: &Entry) -> Result<(), io::Error> {
+        let bytes = entry.to();
+        self.writer.write_all(&(bytes.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&bytes)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flush the snapshot to disk, returning the number of entries written
+    pub fn finish(mut self) -> Result<u64, io::Error> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(self.count)
+    }
+}
+
+pub struct SnapshotReader {
+    reader: BufReader<File>,
+}
+
+impl SnapshotReader {
+    pub fn open(path: &Path) -> Result<SnapshotReader, io::Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if MAGIC_EARLIER.contains(&&magic) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} was written by an earlier version of six_degrees. Import the dumps again",
+                    path.to_string_lossy()
+                ),
+            ));
+        }
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a six_degrees snapshot", path.to_string_lossy()),
+            ));
+        }
+        Ok(SnapshotReader { reader })
+    }
+}
+
+impl Iterator for SnapshotReader {
+    type Item = Result<Entry, io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut length = [0u8; 4];
+        match self.reader.read_exact(&mut length) {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        let mut bytes = vec![0u8; u32::from_le_bytes(length) as usize];
+        if let Err(err) = self.reader.read_exact(&mut bytes) {
+            return Some(Err(err));
+        }
+        Some(Entry::from(&bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)))
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Load a snapshot into the workers
+ *
+ *******************************************************************************************************************/
+
+pub async fn load(
+    path: &Path,
+    tx_commands: &TxCommands,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    info!("Loading snapshot {}", path.to_string_lossy());
+    let mut count = 0;
+    for entry in SnapshotReader::open(path)? {
+        worker::update(entry?, tx_commands).await?;
+        count += 1;
+    }
+    info!("Loaded {} entries from snapshot", count);
+    Ok(count)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+// Module is public, as get_snapshot_path is called from test functions in the dump importers
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{fs, path::PathBuf};
+
+    #[test]
+    fn test_write_read() {
+        let path = get_snapshot_path("write_read");
+        let entries = vec![
+            Entry::new(
+                "Rail transport".to_string(),
+                vec![Entry::get_digest("Train")],
+            ),
+            Entry::new("Train".to_string(), vec![]),
+        ];
+
+        let mut writer = SnapshotWriter::create(&path).unwrap();
+        for entry in &entries {
+            writer.write(entry).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let restored: Vec<Entry> = SnapshotReader::open(&path)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .collect();
+        assert_eq!(restored, entries);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_snapshot() {
+        let path = get_snapshot_path("not_a_snapshot");
+        fs::write(&path, "{\"title\": \"Train\"}").unwrap();
+        assert!(SnapshotReader::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_earlier_snapshot() {
+        let path = get_snapshot_path("earlier");
+        fs::write(&path, MAGIC_EARLIER[0]).unwrap();
+        let err = SnapshotReader::open(&path).err().unwrap();
+        assert!(err.to_string().contains("Import the dumps again"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_snapshot() {
+        let path = get_snapshot_path("truncated");
+        let mut writer = SnapshotWriter::create(&path).unwrap();
+        writer
+            .write(&Entry::new("Train".to_string(), vec![]))
+            .unwrap();
+        writer.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 2]).unwrap();
+
+        let mut reader = SnapshotReader::open(&path).unwrap();
+        assert!(reader.next().unwrap().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    // ***********************************************************************************************
+
+    pub fn get_snapshot_path(name: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "six_degrees_snapshot_{}_{}.snap",
+            name,
+            std::process::id()
+        ));
+        path
+    }
+}
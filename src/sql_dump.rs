@@ -0,0 +1,604 @@
+/* *****************************************************************************************************************
+ *
+ * SQL dump importer
+ * -----------------
+ *
+ * Build a snapshot from the Wikipedia SQL dumps (https://dumps.wikimedia.org/enwiki/latest/), rather than fetching
+ * every page through the API. The importer reads
+ *
+ *    page        (enwiki-latest-page.sql.gz)        page id, namespace, title, and whether the page is a redirect
+ *    redirect    (enwiki-latest-redirect.sql.gz)    the target of each redirect page
+ *    linktarget  (enwiki-latest-linktarget.sql.gz)  the namespace and title referenced by a link target id
+ *    pagelinks   (enwiki-latest-pagelinks.sql.gz)   the links from each page
+ *
+ * linktarget is only required for dumps that reference link targets by id (MediaWiki 1.41 onwards). Older pagelinks
+ * dumps hold the namespace and title of the target in each row. Column positions are read from the CREATE TABLE
+ * statement at the head of each dump, so the importer follows schema changes between dump versions.
+ *
+ * Dumps are streamed one INSERT statement at a time, and may be gzip compressed or plain. The importer holds the
+ * titles of pages in namespace 0, and the digests of redirects and link targets, but never holds the links
+ * themselves. pagelinks is ordered by pl_from, so the links for each page are contiguous and each Entry is written
+ * to the snapshot as soon as the next page starts.
+ *
+ * Only links between pages in namespace 0 are imported. Links to a redirect are replaced with a link to the
+ * redirect target, and the redirect page itself is not imported. Inbound links are not held in the snapshot: they
+ * are rebuilt as back-links when the snapshot is loaded into the workers.
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use flate2::read::MultiGzDecoder;
+
+use crate::entry::{Digest, Entry};
+use crate::snapshot::{ImportSummary, SnapshotWriter};
+
+// Values of the requested columns for a row. NULL values are None
+type Row = Vec<Option<String>>;
+
+#[derive(Debug)]
+pub struct SqlDumps {
+    pub page: PathBuf,
+    pub pagelinks: PathBuf,
+    pub redirect: PathBuf,
+    pub linktarget: Option<PathBuf>,
+}
+
+/* *****************************************************************************************************************
+ *
+ * Import the dumps into a snapshot
+ *
+ *******************************************************************************************************************/
+
+pub fn import(dumps: &SqlDumps, output: &Path) -> Result<ImportSummary, io::Error> {
+    let mut summary = ImportSummary::default();
+
+    // Titles of articles, and of redirects, in namespace 0
+    let mut titles: HashMap<u64, String> = HashMap::new();
+    let mut redirect_titles: HashMap<u64, String> = HashMap::new();
+    let columns = [
+        "page_id",
+        "page_namespace",
+        "page_title",
+        "page_is_redirect",
+    ];
+    read_table(&dumps.page, "page", &columns, |mut row| {
+        if field(&row, 1) != Some("0") {
+            return Ok(());
+        }
+        let id = parse_id(&row, 0)?;
+        let title = to_title(row[2].take().unwrap_or_default());
+        if field(&row, 3) == Some("1") {
+            redirect_titles.insert(id, title);
+        } else {
+            titles.insert(id, title);
+        }
+        Ok(())
+    })?;
+    summary.pages = titles.len() as u64;
+    info!(
+        "Read {} pages and {} redirects from {}",
+        titles.len(),
+        redirect_titles.len(),
+        dumps.page.to_string_lossy()
+    );
+
+    // Digest of each redirect page, mapped to the digest of its target
+    let mut redirects: HashMap<Digest, Digest> = HashMap::new();
+    let columns = ["rd_from", "rd_namespace", "rd_title", "rd_interwiki"];
+    read_table(&dumps.redirect, "redirect", &columns, |row| {
+        if field(&row, 1) != Some("0") || !field(&row, 3).unwrap_or_default().is_empty() {
+            return Ok(());
+        }
+        if let Some(title) = redirect_titles.get(&parse_id(&row, 0)?) {
+            let target = to_title(field(&row, 2).unwrap_or_default().to_string());
+            redirects.insert(Entry::get_digest(title), Entry::get_digest(&target));
+        }
+        Ok(())
+    })?;
+    drop(redirect_titles);
+    summary.redirects = redirects.len() as u64;
+    let resolve = |digest: Digest| *redirects.get(&digest).unwrap_or(&digest);
+
+    // Link targets in namespace 0, for dumps that reference link targets by id
+    let pagelinks_columns = read_columns(&dumps.pagelinks, "pagelinks")?;
+    let by_target_id = pagelinks_columns
+        .iter()
+        .any(|column| column == "pl_target_id");
+    let mut link_targets: HashMap<u64, Digest> = HashMap::new();
+    if by_target_id {
+        let linktarget = dumps.linktarget.as_ref().ok_or_else(|| {
+            invalid_data("pagelinks references link targets by id. The linktarget dump is required")
+        })?;
+        let columns = ["lt_id", "lt_namespace", "lt_title"];
+        read_table(linktarget, "linktarget", &columns, |row| {
+            if field(&row, 1) == Some("0") {
+                let title = to_title(field(&row, 2).unwrap_or_default().to_string());
+                link_targets.insert(parse_id(&row, 0)?, resolve(Entry::get_digest(&title)));
+            }
+            Ok(())
+        })?;
+    }
+
+    // Stream the links, writing an Entry each time the source page changes
+    let mut writer = SnapshotWriter::create(output)?;
+    let mut page = PageLinks::default();
+    let columns: &[&str] = if by_target_id {
+        &["pl_from", "pl_from_namespace", "pl_target_id"]
+    } else {
+        &["pl_from", "pl_from_namespace", "pl_namespace", "pl_title"]
+    };
+    read_table(&dumps.pagelinks, "pagelinks", columns, |row| {
+        if field(&row, 1) != Some("0") {
+            return Ok(());
+        }
+        let from = parse_id(&row, 0)?;
+        let to = if by_target_id {
+            link_targets.get(&parse_id(&row, 2)?).copied()
+        } else if field(&row, 2) == Some("0") {
+            let title = to_title(field(&row, 3).unwrap_or_default().to_string());
+            Some(resolve(Entry::get_digest(&title)))
+        } else {
+            None
+        };
+
+        if page.from != Some(from) {
+            summary.links += page.write_to(&mut writer, &mut titles)?;
+            page.from = Some(from);
+        }
+        if let Some(to) = to {
+            page.add(to);
+        }
+        Ok(())
+    })?;
+    summary.links += page.write_to(&mut writer, &mut titles)?;
+
+    // Pages without links
+    for (_, title) in titles.drain() {
+        writer.write(&Entry::new(title, Vec::new()))?;
+    }
+
+    summary.entries = writer.finish()?;
+    info!("Import complete. {}", summary);
+    Ok(summary)
+}
+
+// Links from the page currently being read from pagelinks
+#[derive(Default)]
+struct PageLinks {
+    from: Option<u64>,
+    outbound: Vec<Digest>,
+    seen: HashSet<Digest>,
+}
+
+impl PageLinks {
+    fn add(&mut self, to: Digest) {
+        if self.seen.insert(to) {
+            self.outbound.push(to);
+        }
+    }
+
+    // Write the page, and remove it from titles so that it is not written again as a page without links. Links
+    // from redirects, or from pages not found in the page dump, are discarded. Returns the number of links written
+    fn write_to(
+        &mut self,
+        writer: &mut SnapshotWriter,
+        titles: &mut HashMap<u64, String>,
+    ) -> Result<u64, io::Error> {
+        let mut links = 0;
+        if let Some(title) = self.from.and_then(|from| titles.remove(&from)) {
+            let digest = Entry::get_digest(&title);
+            let outbound: Vec<Digest> =
+                self.outbound.drain(..).filter(|to| *to != digest).collect();
+            links = outbound.len() as u64;
+            writer.write(&Entry::new(title, outbound))?;
+        }
+        self.outbound.clear();
+        self.seen.clear();
+        Ok(links)
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Read a table from a dump
+ *
+ *******************************************************************************************************************/
+
+fn open_dump(path: &Path) -> Result<Box<dyn BufRead>, io::Error> {
+    let file = File::open(path)?;
+    if path.extension().is_some_and(|extension| extension == "gz") {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Read the column names from the CREATE TABLE statement for a table
+fn read_columns(path: &Path, table: &str) -> Result<Vec<String>, io::Error> {
+    let mut reader = open_dump(path)?;
+    let create = format!("CREATE TABLE `{}`", table);
+    let mut line = Vec::new();
+    let mut columns = Vec::new();
+    let mut in_create = false;
+
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if in_create {
+            if line.starts_with(b")") {
+                return Ok(columns);
+            }
+            if let Some(column) = parse_column(&line) {
+                columns.push(column);
+            }
+        } else if line.starts_with(create.as_bytes()) {
+            in_create = true;
+        }
+    }
+    Err(invalid_data(&format!(
+        "No CREATE TABLE statement for `{}` found in {}",
+        table,
+        path.to_string_lossy()
+    )))
+}
+
+/// Stream the rows of a table, passing the values of the requested columns, in the order requested, to `row`
+fn read_table(
+    path: &Path,
+    table: &str,
+    columns: &[&str],
+    mut row: impl FnMut(Row) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let table_columns = read_columns(path, table)?;
+    let mut indexes = Vec::with_capacity(columns.len());
+    for column in columns {
+        match table_columns.iter().position(|name| name == column) {
+            Some(index) => indexes.push(index),
+            None => {
+                return Err(invalid_data(&format!(
+                    "Column `{}` not found in table `{}`",
+                    column, table
+                )))
+            }
+        }
+    }
+
+    let mut reader = open_dump(path)?;
+    let insert = format!("INSERT INTO `{}` VALUES ", table);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        if line.starts_with(insert.as_bytes()) {
+            parse_values(&line[insert.len()..], |mut values| {
+                let selected = indexes
+                    .iter()
+                    .map(|index| values.get_mut(*index).and_then(|value| value.take()))
+                    .collect();
+                row(selected)
+            })?;
+        }
+    }
+}
+
+// Column definitions in a CREATE TABLE statement take the form:   `page_id` int(8) unsigned NOT NULL,
+fn parse_column(line: &[u8]) -> Option<String> {
+    let line = String::from_utf8_lossy(line);
+    let definition = line.trim_start().strip_prefix('`')?;
+    let end = definition.find('`')?;
+    Some(definition[..end].to_string())
+}
+
+/// Parse the tuples following VALUES in an INSERT statement: (1,0,'Title',NULL),(2,...);
+fn parse_values(
+    statement: &[u8],
+    mut row: impl FnMut(Row) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let mut pos = 0;
+    loop {
+        if statement.get(pos) != Some(&b'(') {
+            return Err(invalid_data("Malformed INSERT statement: expected '('"));
+        }
+        pos += 1;
+
+        let mut values = Vec::new();
+        loop {
+            let (value, next) = parse_value(statement, pos)?;
+            values.push(value);
+            pos = next;
+            match statement.get(pos) {
+                Some(b',') => pos += 1,
+                Some(b')') => {
+                    pos += 1;
+                    break;
+                }
+                _ => {
+                    return Err(invalid_data(
+                        "Malformed INSERT statement: expected ',' or ')'",
+                    ))
+                }
+            }
+        }
+        row(values)?;
+
+        match statement.get(pos) {
+            Some(b',') => pos += 1,
+            _ => return Ok(()),
+        }
+    }
+}
+
+// Returns the value, and the position following the value
+fn parse_value(statement: &[u8], mut pos: usize) -> Result<(Option<String>, usize), io::Error> {
+    if statement.get(pos) != Some(&b'\'') {
+        let start = pos;
+        while let Some(byte) = statement.get(pos) {
+            if *byte == b',' || *byte == b')' {
+                break;
+            }
+            pos += 1;
+        }
+        let token = String::from_utf8_lossy(&statement[start..pos]);
+        let token = token.trim();
+        return match token {
+            "NULL" => Ok((None, pos)),
+            _ => Ok((Some(token.to_string()), pos)),
+        };
+    }
+
+    let mut bytes = Vec::new();
+    pos += 1;
+    loop {
+        match statement.get(pos) {
+            Some(b'\\') => {
+                let escaped = match statement.get(pos + 1) {
+                    Some(b'0') => 0,
+                    Some(b'n') => b'\n',
+                    Some(b'r') => b'\r',
+                    Some(b't') => b'\t',
+                    Some(b'Z') => 0x1a,
+                    Some(byte) => *byte,
+                    None => {
+                        return Err(invalid_data(
+                            "Malformed INSERT statement: unterminated string",
+                        ))
+                    }
+                };
+                bytes.push(escaped);
+                pos += 2;
+            }
+            Some(b'\'') => {
+                return Ok((Some(String::from_utf8_lossy(&bytes).into_owned()), pos + 1))
+            }
+            Some(byte) => {
+                bytes.push(*byte);
+                pos += 1;
+            }
+            None => {
+                return Err(invalid_data(
+                    "Malformed INSERT statement: unterminated string",
+                ))
+            }
+        }
+    }
+}
+
+// ***********************************************************************************************
+
+fn field(row: &Row, index: usize) -> Option<&str> {
+    row[index].as_deref()
+}
+
+fn parse_id(row: &Row, index: usize) -> Result<u64, io::Error> {
+    field(row, index)
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| invalid_data("Expected a numeric id"))
+}
+
+// Dumps hold titles with underscores in place of spaces. The API, and the digests, use spaces
+fn to_title(title: String) -> String {
+    title.replace('_', " ")
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{tests::get_snapshot_path, SnapshotReader};
+    use flate2::{write::GzEncoder, Compression};
+    use std::{fs, io::Write};
+
+    #[test]
+    fn test_parse_values() {
+        let mut rows = Vec::new();
+        parse_values(
+            br"(10,0,'AC/DC',0,NULL),(12,1,'It\'s_a_\\test',1,0.5);",
+            |row| {
+                rows.push(row);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][2], Some("AC/DC".to_string()));
+        assert_eq!(rows[0][4], None);
+        assert_eq!(rows[1][2], Some(r"It's_a_\test".to_string()));
+        assert_eq!(rows[1][4], Some("0.5".to_string()));
+    }
+
+    #[test]
+    fn test_parse_values_fail() {
+        let result = parse_values(b"(10,0,'Unterminated", |_| Ok(()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!(
+            parse_column(b"  `page_id` int(8) unsigned NOT NULL AUTO_INCREMENT,\n"),
+            Some("page_id".to_string())
+        );
+        assert_eq!(parse_column(b"  PRIMARY KEY (`page_id`),\n"), None);
+    }
+
+    #[test]
+    fn test_import_linktarget() {
+        let dumps = write_dumps("linktarget", PAGELINKS_BY_TARGET_ID, true);
+        let output = get_snapshot_path("sql_linktarget");
+        let summary = import(&dumps, &output).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                pages: 3,
+                redirects: 1,
+                links: 3,
+                entries: 3
+            }
+        );
+        check_snapshot(&output);
+    }
+
+    #[test]
+    fn test_import_legacy_pagelinks() {
+        let dumps = write_dumps("legacy", PAGELINKS_BY_TITLE, false);
+        let output = get_snapshot_path("sql_legacy");
+        let summary = import(&dumps, &output).unwrap();
+        assert_eq!(summary.links, 3);
+        assert_eq!(summary.entries, 3);
+        check_snapshot(&output);
+    }
+
+    #[test]
+    fn test_import_missing_linktarget() {
+        let mut dumps = write_dumps("missing_linktarget", PAGELINKS_BY_TARGET_ID, true);
+        dumps.linktarget = None;
+        let output = get_snapshot_path("sql_missing_linktarget");
+        assert!(import(&dumps, &output).is_err());
+    }
+
+    // ***********************************************************************************************
+
+    // Rail transport -> Train, Trains (a redirect to Train), Locomotive, Talk:Train, Rail transport
+    // Train -> Rail transport
+    // Locomotive has no links
+    fn check_snapshot(output: &Path) {
+        let entries: HashMap<String, Entry> = SnapshotReader::open(output)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .map(|entry| (entry.get_title().to_string(), entry))
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(
+            entries["Rail transport"].get_outbound(),
+            &[Entry::get_digest("Train"), Entry::get_digest("Locomotive")]
+        );
+        assert_eq!(
+            entries["Train"].get_outbound(),
+            &[Entry::get_digest("Rail transport")]
+        );
+        assert!(entries["Locomotive"].get_outbound().is_empty());
+        fs::remove_file(output).unwrap();
+    }
+
+    fn write_dumps(name: &str, pagelinks: &str, by_target_id: bool) -> SqlDumps {
+        let mut directory = std::env::temp_dir();
+        directory.push(format!("six_degrees_sql_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+
+        let write = |file: &str, contents: &str| {
+            let path = directory.join(file);
+            let mut encoder =
+                GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+            encoder.write_all(contents.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+            path
+        };
+
+        SqlDumps {
+            page: write("page.sql.gz", PAGE),
+            pagelinks: write("pagelinks.sql.gz", pagelinks),
+            redirect: write("redirect.sql.gz", REDIRECT),
+            linktarget: match by_target_id {
+                true => Some(write("linktarget.sql.gz", LINKTARGET)),
+                false => None,
+            },
+        }
+    }
+
+    const PAGE: &str = r###"-- MySQL dump 10.19  Distrib 10.3.38-MariaDB, for debian-linux-gnu (x86_64)
+DROP TABLE IF EXISTS `page`;
+CREATE TABLE `page` (
+  `page_id` int(8) unsigned NOT NULL AUTO_INCREMENT,
+  `page_namespace` int(11) NOT NULL DEFAULT 0,
+  `page_title` varbinary(255) NOT NULL DEFAULT '',
+  `page_is_redirect` tinyint(1) unsigned NOT NULL DEFAULT 0,
+  `page_is_new` tinyint(1) unsigned NOT NULL DEFAULT 0,
+  `page_random` double unsigned NOT NULL DEFAULT 0,
+  `page_touched` binary(14) NOT NULL,
+  PRIMARY KEY (`page_id`),
+  KEY `page_random` (`page_random`)
+) ENGINE=InnoDB AUTO_INCREMENT=75697133 DEFAULT CHARSET=binary ROW_FORMAT=COMPRESSED;
+INSERT INTO `page` VALUES (1,0,'Rail_transport',0,0,0.1,'20240101000000'),(2,0,'Train',0,0,0.2,'20240101000000'),(3,0,'Trains',1,0,0.3,'20240101000000');
+INSERT INTO `page` VALUES (4,1,'Train',0,0,0.4,'20240101000000'),(5,0,'Locomotive',0,0,0.5,'20240101000000');
+"###;
+
+    const REDIRECT: &str = r###"CREATE TABLE `redirect` (
+  `rd_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `rd_namespace` int(11) NOT NULL DEFAULT 0,
+  `rd_title` varbinary(255) NOT NULL DEFAULT '',
+  `rd_interwiki` varbinary(32) DEFAULT NULL,
+  `rd_fragment` varbinary(255) DEFAULT NULL,
+  PRIMARY KEY (`rd_from`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary ROW_FORMAT=COMPRESSED;
+INSERT INTO `redirect` VALUES (3,0,'Train','','');
+"###;
+
+    const LINKTARGET: &str = r###"CREATE TABLE `linktarget` (
+  `lt_id` bigint(20) unsigned NOT NULL AUTO_INCREMENT,
+  `lt_namespace` int(11) NOT NULL,
+  `lt_title` varbinary(255) NOT NULL,
+  PRIMARY KEY (`lt_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary ROW_FORMAT=COMPRESSED;
+INSERT INTO `linktarget` VALUES (100,0,'Train'),(101,0,'Trains'),(102,0,'Locomotive'),(103,1,'Train'),(104,0,'Rail_transport');
+"###;
+
+    const PAGELINKS_BY_TARGET_ID: &str = r###"CREATE TABLE `pagelinks` (
+  `pl_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `pl_from_namespace` int(11) NOT NULL DEFAULT 0,
+  `pl_target_id` bigint(20) unsigned NOT NULL,
+  PRIMARY KEY (`pl_from`,`pl_target_id`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary ROW_FORMAT=COMPRESSED;
+INSERT INTO `pagelinks` VALUES (1,0,100),(1,0,101),(1,0,102),(1,0,103),(1,0,104),(2,0,104);
+INSERT INTO `pagelinks` VALUES (3,0,100),(4,1,104);
+"###;
+
+    const PAGELINKS_BY_TITLE: &str = r###"CREATE TABLE `pagelinks` (
+  `pl_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `pl_namespace` int(11) NOT NULL DEFAULT 0,
+  `pl_title` varbinary(255) NOT NULL DEFAULT '',
+  `pl_from_namespace` int(11) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`pl_from`,`pl_namespace`,`pl_title`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary ROW_FORMAT=COMPRESSED;
+INSERT INTO `pagelinks` VALUES (1,0,'Train',0),(1,0,'Trains',0),(1,0,'Locomotive',0),(1,1,'Train',0),(1,0,'Rail_transport',0),(2,0,'Rail_transport',0);
+INSERT INTO `pagelinks` VALUES (3,0,'Train',0),(4,0,'Rail_transport',1);
+"###;
+}
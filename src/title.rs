@@ -0,0 +1,546 @@
+/* *****************************************************************************************************************
+ *
+ * Title
+ * -----
+ *
+ * Normalise page titles the way MediaWiki does, so that every spelling of a title maps to the same digest, the same
+ * worker and the same cache record. See https://www.mediawiki.org/wiki/Manual:Page_title
+ *
+ *    rail_transport          ->  Rail transport
+ *    " Rail   transport "    ->  Rail transport
+ *    category : trains       ->  Category:Trains
+ *    WP:Namespace            ->  Wikipedia:Namespace
+ *
+ * Underscores become spaces, runs of white space are collapsed and trimmed, directional marks are removed, and the
+ * title is converted to Unicode NFC. A namespace prefix is replaced by the name of the namespace on the wiki.
+ *
+ * Namespaces have local names on each language edition, such as Kategorie for Category on de.wikipedia.org. A wiki
+ * holds the local names and aliases of its language, from LOCAL_NAMESPACES, and accepts the canonical English names
+ * as well, as MediaWiki does. A prefix in any of these is replaced by the local name, so "kategorie:züge" and
+ * "Category:Züge" are both "Kategorie:Züge" on de. Wikis of languages without a table use the canonical names.
+ *
+ * Most wikis capitalise the first letter of a title. Wiktionary does not, so "train" and "Train" are different pages.
+ * The namespace name is capitalised on every wiki. Titles without a wiki are normalised for the default wiki.
+ *
+ * Titles can also be read from the URL of a page on any of the wikis served, in the forms
+ *
+ *    https://en.wikipedia.org/wiki/Rail_transport#History
+ *    https://en.m.wikipedia.org/wiki/Rail_transport
+ *    https://en.wikipedia.org/w/index.php?title=Rail_transport&oldid=1
+ *    en.wikipedia.org/wiki/%C3%89clair
+ *
+ *******************************************************************************************************************/
+
+use percent_encoding::percent_decode_str;
+use std::fmt;
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+use crate::wiki::{self, Wiki};
+
+// Canonical namespace names and aliases used on Wikipedia. See https://en.wikipedia.org/wiki/Wikipedia:Namespace
+// The canonical name of each namespace comes first
+static NAMESPACES: &[(&str, i32)] = &[
+    ("Media", -2),
+    ("Special", -1),
+    ("Talk", 1),
+    ("User", 2),
+    ("User talk", 3),
+    ("Wikipedia", 4),
+    ("WP", 4),
+    ("Project", 4),
+    ("Wikipedia talk", 5),
+    ("WT", 5),
+    ("Project talk", 5),
+    ("File", 6),
+    ("Image", 6),
+    ("File talk", 7),
+    ("Image talk", 7),
+    ("MediaWiki", 8),
+    ("MediaWiki talk", 9),
+    ("Template", 10),
+    ("Template talk", 11),
+    ("Help", 12),
+    ("Help talk", 13),
+    ("Category", 14),
+    ("Category talk", 15),
+    ("Portal", 100),
+    ("Portal talk", 101),
+    ("Draft", 118),
+    ("Draft talk", 119),
+    ("TimedText", 710),
+    ("TimedText talk", 711),
+    ("Module", 828),
+    ("Module talk", 829),
+];
+
+// Local namespace names and aliases on language editions of Wikipedia, by language. The local name of each namespace
+// comes first. See Special:NamespaceInfo on each wiki
+static LOCAL_NAMESPACES: &[(&str, &[(&str, i32)])] = &[
+    (
+        "de",
+        &[
+            ("Medium", -2),
+            ("Spezial", -1),
+            ("Diskussion", 1),
+            ("Benutzer", 2),
+            ("Benutzerin", 2),
+            ("Benutzer Diskussion", 3),
+            ("Benutzerin Diskussion", 3),
+            ("Wikipedia Diskussion", 5),
+            ("Datei", 6),
+            ("Bild", 6),
+            ("Datei Diskussion", 7),
+            ("Bild Diskussion", 7),
+            ("MediaWiki Diskussion", 9),
+            ("Vorlage", 10),
+            ("Vorlage Diskussion", 11),
+            ("Hilfe", 12),
+            ("Hilfe Diskussion", 13),
+            ("Kategorie", 14),
+            ("Kategorie Diskussion", 15),
+            ("Portal Diskussion", 101),
+        ],
+    ),
+    (
+        "es",
+        &[
+            ("Medio", -2),
+            ("Especial", -1),
+            ("Discusión", 1),
+            ("Usuario", 2),
+            ("Usuaria", 2),
+            ("Usuario discusión", 3),
+            ("Usuaria discusión", 3),
+            ("Wikipedia discusión", 5),
+            ("Archivo", 6),
+            ("Imagen", 6),
+            ("Archivo discusión", 7),
+            ("MediaWiki discusión", 9),
+            ("Plantilla", 10),
+            ("Plantilla discusión", 11),
+            ("Ayuda", 12),
+            ("Ayuda discusión", 13),
+            ("Categoría", 14),
+            ("Categoría discusión", 15),
+            ("Portal discusión", 101),
+        ],
+    ),
+    (
+        "fr",
+        &[
+            ("Média", -2),
+            ("Spécial", -1),
+            ("Discussion", 1),
+            ("Utilisateur", 2),
+            ("Utilisatrice", 2),
+            ("Discussion utilisateur", 3),
+            ("Discussion utilisatrice", 3),
+            ("Wikipédia", 4),
+            ("Discussion Wikipédia", 5),
+            ("Fichier", 6),
+            ("Discussion fichier", 7),
+            ("Discussion MediaWiki", 9),
+            ("Modèle", 10),
+            ("Discussion modèle", 11),
+            ("Aide", 12),
+            ("Discussion aide", 13),
+            ("Catégorie", 14),
+            ("Discussion catégorie", 15),
+            ("Portail", 100),
+            ("Discussion Portail", 101),
+        ],
+    ),
+];
+
+// Left-to-right and right-to-left marks, which MediaWiki strips from titles
+static DIRECTIONAL_MARKS: &[char] = &[
+    '\u{200e}', '\u{200f}', '\u{202a}', '\u{202b}', '\u{202c}', '\u{202d}', '\u{202e}',
+];
+
+/* *****************************************************************************************************************
+ *
+ * Normalise
+ *
+ *******************************************************************************************************************/
+
+/// Normalise a title on the wiki, including its namespace prefix
+pub fn normalise_for(wiki: &Wiki, title: &str) -> String {
+    parse_for(wiki, title).1
+}
+
+/// Split a title on the default wiki into (namespace, normalised title)
+pub fn parse(title: &str) -> (i32, String) {
+    parse_for(wiki::default_wiki(), title)
+}
+
+/// Split a title into (namespace, normalised title). Titles without a namespace prefix known on the wiki are in
+/// namespace 0
+pub fn parse_for(wiki: &Wiki, title: &str) -> (i32, String) {
+    let title = normalise_text(title);
+    let title = title
+        .strip_prefix(':')
+        .map(str::trim_start)
+        .unwrap_or(&title);
+
+    if let Some((prefix, rest)) = title.split_once(':') {
+        if let Some(ns) = namespace_for(wiki, prefix.trim_end()) {
+            let name =
+                local_namespace_name(wiki, ns).expect("Internal error: namespace without a name");
+            let rest = capitalise(rest.trim_start(), wiki.case_sensitive);
+            return (ns, format!("{}:{}", name, rest));
+        }
+    }
+    (0, capitalise(title, wiki.case_sensitive))
+}
+
+/// Title of a category on the wiki, from its name with or without a namespace prefix. The prefix is the local name of
+/// the category namespace, so "Trains" and "Category:Trains" are both "Kategorie:Trains" on de
+pub fn category_for(wiki: &Wiki, name: &str) -> String {
+    match parse_for(wiki, name) {
+        (14, category) => category,
+        _ => {
+            let prefix =
+                local_namespace_name(wiki, 14).expect("Internal error: namespace without a name");
+            parse_for(wiki, &format!("{}:{}", prefix, name)).1
+        }
+    }
+}
+
+/// Look up a namespace on the default wiki by any of its names, ignoring case
+pub fn namespace(name: &str) -> Option<i32> {
+    namespace_for(wiki::default_wiki(), name)
+}
+
+/// Look up a namespace by any of its local or canonical names on the wiki, ignoring case
+pub fn namespace_for(wiki: &Wiki, name: &str) -> Option<i32> {
+    let name = normalise_text(name).to_lowercase();
+    wiki.namespaces
+        .iter()
+        .chain(NAMESPACES)
+        .find(|(candidate, _)| candidate.to_lowercase() == name)
+        .map(|(_, ns)| *ns)
+}
+
+/// Name of a namespace on the wiki: the local name if the wiki has one, or else the canonical name
+pub fn local_namespace_name(wiki: &Wiki, ns: i32) -> Option<&'static str> {
+    wiki.namespaces
+        .iter()
+        .find(|(_, candidate)| *candidate == ns)
+        .map(|(name, _)| *name)
+        .or_else(|| namespace_name(ns))
+}
+
+/// Local namespace names and aliases of a language edition, if there is a table for the language
+pub fn local_namespaces(language: &str) -> &'static [(&'static str, i32)] {
+    LOCAL_NAMESPACES
+        .iter()
+        .find(|(candidate, _)| *candidate == language)
+        .map(|(_, namespaces)| *namespaces)
+        .unwrap_or_default()
+}
+
+/// Canonical name of a namespace
+pub fn namespace_name(ns: i32) -> Option<&'static str> {
+    NAMESPACES
+        .iter()
+        .find(|(_, candidate)| *candidate == ns)
+        .map(|(name, _)| *name)
+}
+
+// Underscores to spaces, collapse and trim white space, strip directional marks, and convert to NFC
+fn normalise_text(text: &str) -> String {
+    let text = text
+        .replace(DIRECTIONAL_MARKS, "")
+        .replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    match is_nfc_quick(text.chars()) {
+        IsNormalized::Yes => text,
+        _ => text.nfc().collect(),
+    }
+}
+
+fn capitalise(text: &str, case_sensitive: bool) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) if !case_sensitive => first.to_uppercase().chain(chars).collect(),
+        _ => text.to_string(),
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Resolve a URL
+ *
+ *******************************************************************************************************************/
+
+#[derive(Debug, PartialEq)]
+pub enum UrlError {
+    Invalid(String),
+    UnknownHost(String),
+    NotAPage(String),
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UrlError::Invalid(page_url) => write!(f, "{} is not a valid URL", page_url),
+            UrlError::UnknownHost(host) => write!(f, "{} is not the host of a wiki served", host),
+            UrlError::NotAPage(page_url) => write!(f, "{} is not the URL of a page", page_url),
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+/// Read the wiki and normalised title from the URL of a page. The host must match the domain name of one of the
+/// wikis, ignoring the mobile "m."
+pub fn from_url<'a>(page_url: &str, wikis: &'a [Wiki]) -> Result<(&'a Wiki, String), UrlError> {
+    let parsed =
+        parse_url(page_url.trim()).ok_or_else(|| UrlError::Invalid(page_url.to_string()))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(UrlError::Invalid(page_url.to_string()));
+    }
+
+    let host = parsed.host_str().unwrap_or_default();
+    let wiki = wikis
+        .iter()
+        .find(|wiki| desktop_host(&wiki.host()) == desktop_host(host))
+        .ok_or_else(|| UrlError::UnknownHost(host.to_string()))?;
+
+    let raw_title = if let Some(path_title) = parsed.path().strip_prefix("/wiki/") {
+        percent_decode_str(path_title)
+            .decode_utf8()
+            .map_err(|_| UrlError::Invalid(page_url.to_string()))?
+            .to_string()
+    } else if parsed.path().ends_with("/index.php") {
+        parsed
+            .query_pairs()
+            .find(|(key, _)| key == "title")
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default()
+    } else {
+        String::new()
+    };
+
+    let title = normalise_for(wiki, &raw_title);
+    if title.is_empty() {
+        return Err(UrlError::NotAPage(page_url.to_string()));
+    }
+    Ok((wiki, title))
+}
+
+// Accept URLs without a scheme, such as en.wikipedia.org/wiki/Train
+fn parse_url(page_url: &str) -> Option<url::Url> {
+    match page_url.contains("://") {
+        true => url::Url::parse(page_url).ok(),
+        false => url::Url::parse(&format!("https://{}", page_url)).ok(),
+    }
+}
+
+// en.m.wikipedia.org -> en.wikipedia.org
+fn desktop_host(host: &str) -> String {
+    host.to_ascii_lowercase()
+        .split('.')
+        .filter(|label| *label != "m")
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalise() {
+        assert_eq!(
+            normalise_for(wiki::default_wiki(), "rail_transport"),
+            "Rail transport"
+        );
+        assert_eq!(
+            normalise_for(wiki::default_wiki(), "Rail transport "),
+            "Rail transport"
+        );
+        assert_eq!(
+            normalise_for(wiki::default_wiki(), "  éclair   au chocolat "),
+            "Éclair au chocolat"
+        );
+        assert_eq!(normalise_for(wiki::default_wiki(), "iPhone"), "IPhone");
+        assert_eq!(
+            normalise_for(wiki::default_wiki(), "Train\u{200e}"),
+            "Train"
+        );
+        assert_eq!(normalise_for(wiki::default_wiki(), ""), "");
+    }
+
+    #[test]
+    fn test_normalise_nfc() {
+        // "e" followed by a combining acute accent
+        assert_eq!(normalise_for(wiki::default_wiki(), "Cafe\u{301}"), "Café");
+        assert_eq!(
+            normalise_for(wiki::default_wiki(), "Cafe\u{301}"),
+            normalise_for(wiki::default_wiki(), "Café")
+        );
+    }
+
+    #[test]
+    fn test_namespaces() {
+        assert_eq!(
+            parse("category : rail_transport"),
+            (14, "Category:Rail transport".to_string())
+        );
+        assert_eq!(
+            parse(":Category:Trains"),
+            (14, "Category:Trains".to_string())
+        );
+        assert_eq!(parse("user talk:ted"), (3, "User talk:Ted".to_string()));
+        assert_eq!(
+            parse("WP:Namespace"),
+            (4, "Wikipedia:Namespace".to_string())
+        );
+        assert_eq!(parse("Image:Train.jpg"), (6, "File:Train.jpg".to_string()));
+        assert_eq!(parse("Mac: The Movie"), (0, "Mac: The Movie".to_string()));
+        assert_eq!(namespace("template_talk"), Some(11));
+        assert_eq!(namespace("Mac"), None);
+    }
+
+    #[test]
+    fn test_local_namespaces() {
+        let de = Wiki::new("de", "https://de.wikipedia.org/", 10);
+        assert_eq!(
+            parse_for(&de, "kategorie:züge"),
+            (14, "Kategorie:Züge".to_string())
+        );
+        assert_eq!(
+            parse_for(&de, "Category:Züge"),
+            (14, "Kategorie:Züge".to_string())
+        );
+        assert_eq!(
+            parse_for(&de, "benutzerin diskussion:Ted"),
+            (3, "Benutzer Diskussion:Ted".to_string())
+        );
+        assert_eq!(
+            parse_for(&de, "Wikipedia:Namenskonventionen"),
+            (4, "Wikipedia:Namenskonventionen".to_string())
+        );
+        let fr = Wiki::new("fr", "https://fr.wikipedia.org/", 10);
+        assert_eq!(
+            parse_for(&fr, "CATÉGORIE:Train"),
+            (14, "Catégorie:Train".to_string())
+        );
+        assert_eq!(namespace_for(&fr, "wp"), Some(4));
+        assert_eq!(category_for(&fr, "train"), "Catégorie:Train");
+        assert_eq!(category_for(&de, "Category:Züge"), "Kategorie:Züge");
+        assert_eq!(category_for(&de, "Help:Züge"), "Kategorie:Help:Züge");
+        assert_eq!(
+            category_for(wiki::default_wiki(), "Trains"),
+            "Category:Trains"
+        );
+        assert_eq!(local_namespace_name(&fr, 4), Some("Wikipédia"));
+        // Local names are not known on other wikis
+        assert_eq!(parse("Kategorie:Züge"), (0, "Kategorie:Züge".to_string()));
+        assert_eq!(
+            local_namespace_name(wiki::default_wiki(), 14),
+            Some("Category")
+        );
+    }
+
+    #[test]
+    fn test_from_url() {
+        let wikis = [Wiki::new("en", "https://en.wikipedia.org/", 10)];
+        for page_url in [
+            "https://en.wikipedia.org/wiki/Rail_transport",
+            "http://en.wikipedia.org/wiki/Rail_transport#History",
+            "https://en.m.wikipedia.org/wiki/Rail_transport",
+            "https://EN.wikipedia.org/wiki/rail%20transport",
+            "https://en.wikipedia.org/w/index.php?title=Rail_transport&oldid=1",
+            "en.wikipedia.org/wiki/Rail_transport",
+        ] {
+            assert_eq!(
+                from_url(page_url, &wikis).map(|(wiki, title)| (wiki.id.as_str(), title)),
+                Ok(("en", "Rail transport".to_string())),
+                "{}",
+                page_url
+            );
+        }
+        assert_eq!(
+            from_url("https://en.wikipedia.org/wiki/%C3%89clair", &wikis).map(|(_, title)| title),
+            Ok("Éclair".to_string())
+        );
+        assert_eq!(
+            from_url("https://en.wikipedia.org/wiki/C%2B%2B", &wikis).map(|(_, title)| title),
+            Ok("C++".to_string())
+        );
+        assert_eq!(
+            from_url("https://en.wikipedia.org/wiki/AC/DC", &wikis).map(|(_, title)| title),
+            Ok("AC/DC".to_string())
+        );
+        assert_eq!(
+            from_url("https://en.wikipedia.org/wiki/category:Trains", &wikis)
+                .map(|(_, title)| title),
+            Ok("Category:Trains".to_string())
+        );
+    }
+
+    #[test]
+    fn test_from_url_fail() {
+        let wikis = [Wiki::new("en", "https://en.wikipedia.org/", 10)];
+        assert_eq!(
+            from_url("https://fr.wikipedia.org/wiki/Train", &wikis),
+            Err(UrlError::UnknownHost("fr.wikipedia.org".to_string()))
+        );
+        assert_eq!(
+            from_url("https://example.com/wiki/Train", &wikis),
+            Err(UrlError::UnknownHost("example.com".to_string()))
+        );
+        assert_eq!(
+            from_url("https://en.wikipedia.org/", &wikis),
+            Err(UrlError::NotAPage("https://en.wikipedia.org/".to_string()))
+        );
+        assert_eq!(
+            from_url("https://en.wikipedia.org/w/index.php?search=Train", &wikis),
+            Err(UrlError::NotAPage(
+                "https://en.wikipedia.org/w/index.php?search=Train".to_string()
+            ))
+        );
+        assert_eq!(
+            from_url("ftp://en.wikipedia.org/wiki/Train", &wikis),
+            Err(UrlError::Invalid(
+                "ftp://en.wikipedia.org/wiki/Train".to_string()
+            ))
+        );
+        assert!(matches!(
+            from_url("https://[bad/wiki/Train", &wikis),
+            Err(UrlError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_case_sensitive() {
+        let wiktionary = Wiki::new("en.wiktionary", "https://en.wiktionary.org/", 10);
+        assert_eq!(normalise_for(&wiktionary, "train"), "train");
+        assert_eq!(
+            normalise_for(&wiktionary, "category:trains"),
+            "Category:trains"
+        );
+        assert_eq!(normalise_for(wiki::default_wiki(), "train"), "Train");
+    }
+
+    #[test]
+    fn test_from_url_wikis() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("de", "https://de.wikipedia.org/", 10),
+        ];
+        let (wiki, title) = from_url("https://de.m.wikipedia.org/wiki/Eisenbahn", &wikis).unwrap();
+        assert_eq!(wiki.id, "de");
+        assert_eq!(title, "Eisenbahn");
+    }
+}
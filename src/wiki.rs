@@ -0,0 +1,306 @@
+/* *****************************************************************************************************************
+ *
+ * Wiki
+ * ----
+ *
+ * One server can answer queries on several wikis, such as the English and German editions of Wikipedia. Each wiki is
+ * known by a short identifier, which is used in API requests (?wiki=de), in cache paths (<cache>/de/pack), and in the
+ * digest of every title, so that "Berlin" on en and "Berlin" on de are different pages held by different entries.
+ *
+ * The default wiki is named by opt::domain_name. Further wikis are added with opt::wiki, as an identifier, which is
+ * taken to be a language edition of Wikipedia, or as identifier=domain name:
+ *
+ *    --wiki de                                   https://de.wikipedia.org/
+ *    --wiki enwikt=https://en.wiktionary.org/
+ *
+ * Each wiki has its own rate limit, so a busy wiki does not hold back requests to another. The limit defaults to
+ * opt::rate_limit, and is set for a single wiki with opt::wiki_rate_limit, as identifier=requests per second.
+ *
+ * The identifier of the default wiki is read from its domain name: the language code for Wikipedia, and the host
+ * name less ".org" for any other wiki (en.wiktionary.org -> en.wiktionary).
+ *
+ * Dump imports and cache subcommands work on the default wiki.
+ *
+ * Interlanguage links name the language of the target page. The target is on the wiki served that is that language
+ * edition of the same project, so a link to ja from en.wikipedia.org is to ja.wikipedia.org, and a link to fr from
+ * en.wiktionary.org is to fr.wiktionary.org. Links to languages that are not served are not followed.
+ *
+ *******************************************************************************************************************/
+
+use std::{fmt, sync::OnceLock};
+
+use crate::title;
+
+static WIKIS: OnceLock<Vec<Wiki>> = OnceLock::new();
+
+static DEFAULT_DOMAIN_NAME: &str = "https://en.wikipedia.org/";
+static DEFAULT_RATE_LIMIT: u32 = 10;
+
+// ***********************************************************************************************
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wiki {
+    pub id: String,
+    pub domain_name: String,
+    pub rate_limit: u32,
+    // Wiktionary does not capitalise the first letter of titles
+    pub case_sensitive: bool,
+    // Local namespace names and aliases of the language of the wiki, as read by title::local_namespaces
+    pub namespaces: &'static [(&'static str, i32)],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WikiError {
+    InvalidDomain(String),
+    InvalidOption(String),
+    Duplicate(String),
+    Unknown(String),
+}
+
+impl fmt::Display for WikiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WikiError::InvalidDomain(domain_name) => {
+                write!(f, "{} is not a valid domain name", domain_name)
+            }
+            WikiError::InvalidOption(option) => write!(f, "Unable to read wiki option {}", option),
+            WikiError::Duplicate(id) => write!(f, "Wiki {} is given more than once", id),
+            WikiError::Unknown(id) => write!(f, "Wiki {} is not served", id),
+        }
+    }
+}
+
+impl std::error::Error for WikiError {}
+
+impl Wiki {
+    pub fn new(id: &str, domain_name: &str, rate_limit: u32) -> Wiki {
+        Wiki {
+            id: id.to_string(),
+            domain_name: domain_name.to_string(),
+            rate_limit,
+            case_sensitive: domain_name.contains("wiktionary.org"),
+            namespaces: title::local_namespaces(&language_of(domain_name)),
+        }
+    }
+
+    /// Host name of the wiki, in lower case
+    pub fn host(&self) -> String {
+        host_of(&self.domain_name).unwrap_or_default()
+    }
+}
+
+/* *****************************************************************************************************************
+ *
+ * Registry
+ *
+ *******************************************************************************************************************/
+
+/// Set the wikis served. The first wiki is the default. Called once, before any titles are hashed
+pub fn init(wikis: Vec<Wiki>) {
+    if WIKIS.set(wikis).is_err() {
+        warn!("The wikis have already been set");
+    }
+}
+
+/// The wikis served, default first. English Wikipedia alone if init has not been called
+pub fn all() -> &'static [Wiki] {
+    WIKIS.get_or_init(|| vec![Wiki::new("en", DEFAULT_DOMAIN_NAME, DEFAULT_RATE_LIMIT)])
+}
+
+pub fn default_wiki() -> &'static Wiki {
+    &all()[0]
+}
+
+/// Look up a wiki by identifier
+pub fn get(id: &str) -> Result<&'static Wiki, WikiError> {
+    find(all(), id)
+}
+
+pub fn find<'a>(wikis: &'a [Wiki], id: &str) -> Result<&'a Wiki, WikiError> {
+    wikis
+        .iter()
+        .find(|wiki| wiki.id.eq_ignore_ascii_case(id.trim()))
+        .ok_or_else(|| WikiError::Unknown(id.to_string()))
+}
+
+/// The wiki served that is the `lang` edition of the same project as `wiki`
+pub fn language_edition<'a>(wikis: &'a [Wiki], wiki: &Wiki, lang: &str) -> Option<&'a Wiki> {
+    let host = wiki.host();
+    let (_, project) = host.split_once('.')?;
+    let host = format!("{}.{}", lang.trim().to_ascii_lowercase(), project);
+    wikis.iter().find(|candidate| candidate.host() == host)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Read the wikis from the command line
+ *
+ *******************************************************************************************************************/
+
+/// Build the list of wikis from opt::domain_name, opt::wiki and opt::wiki_rate_limit
+pub fn from_options(
+    domain_name: &str,
+    wikis: &[String],
+    rate_limits: &[String],
+    rate_limit: u32,
+) -> Result<Vec<Wiki>, WikiError> {
+    let default_id = id_for_domain(domain_name)?;
+    let mut served = vec![Wiki::new(&default_id, domain_name, rate_limit)];
+
+    for option in wikis {
+        let (id, domain_name) = match option.split_once('=') {
+            Some((id, domain_name)) => (id.trim().to_string(), domain_name.trim().to_string()),
+            None => (
+                option.trim().to_string(),
+                format!("https://{}.wikipedia.org/", option.trim()),
+            ),
+        };
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            return Err(WikiError::InvalidOption(option.clone()));
+        }
+        host_of(&domain_name).ok_or_else(|| WikiError::InvalidDomain(domain_name.clone()))?;
+        if find(&served, &id).is_ok() {
+            return Err(WikiError::Duplicate(id));
+        }
+        served.push(Wiki::new(&id, &domain_name, rate_limit));
+    }
+
+    for option in rate_limits {
+        let (id, limit) = option
+            .split_once('=')
+            .and_then(|(id, limit)| Some((id, limit.trim().parse::<u32>().ok()?)))
+            .ok_or_else(|| WikiError::InvalidOption(option.clone()))?;
+        let id = find(&served, id)?.id.clone();
+        if let Some(wiki) = served.iter_mut().find(|wiki| wiki.id == id) {
+            wiki.rate_limit = limit;
+        }
+    }
+    Ok(served)
+}
+
+/// Identifier of a wiki, read from its domain name
+pub fn id_for_domain(domain_name: &str) -> Result<String, WikiError> {
+    let host =
+        host_of(domain_name).ok_or_else(|| WikiError::InvalidDomain(domain_name.to_string()))?;
+    let host = host.strip_suffix(".org").unwrap_or(&host);
+    Ok(host.strip_suffix(".wikipedia").unwrap_or(host).to_string())
+}
+
+// Language of a wiki, read from the first label of its host name (de.wikipedia.org -> de)
+fn language_of(domain_name: &str) -> String {
+    host_of(domain_name)
+        .and_then(|host| host.split('.').next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn host_of(domain_name: &str) -> Option<String> {
+    url::Url::parse(domain_name)
+        .ok()?
+        .host_str()
+        .map(|host| host.to_ascii_lowercase())
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_for_domain() {
+        assert_eq!(
+            id_for_domain("https://en.wikipedia.org/"),
+            Ok("en".to_string())
+        );
+        assert_eq!(
+            id_for_domain("https://en.wiktionary.org/"),
+            Ok("en.wiktionary".to_string())
+        );
+        assert_eq!(
+            id_for_domain("http://localhost:8080/"),
+            Ok("localhost".to_string())
+        );
+        assert!(id_for_domain("en.wikipedia.org").is_err());
+    }
+
+    #[test]
+    fn test_from_options() {
+        let wikis = from_options(
+            "https://en.wikipedia.org/",
+            &[
+                "de".to_string(),
+                "enwikt=https://en.wiktionary.org/".to_string(),
+            ],
+            &["de=2".to_string()],
+            10,
+        )
+        .unwrap();
+        assert_eq!(
+            wikis,
+            vec![
+                Wiki::new("en", "https://en.wikipedia.org/", 10),
+                Wiki::new("de", "https://de.wikipedia.org/", 2),
+                Wiki::new("enwikt", "https://en.wiktionary.org/", 10),
+            ]
+        );
+        assert!(!wikis[0].case_sensitive);
+        assert!(wikis[2].case_sensitive);
+        assert_eq!(wikis[1].host(), "de.wikipedia.org");
+        assert_eq!(find(&wikis, "DE").unwrap().id, "de");
+        assert_eq!(
+            find(&wikis, "fr"),
+            Err(WikiError::Unknown("fr".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_language_edition() {
+        let wikis = [
+            Wiki::new("en", "https://en.wikipedia.org/", 10),
+            Wiki::new("ja", "https://ja.wikipedia.org/", 10),
+            Wiki::new("enwikt", "https://en.wiktionary.org/", 10),
+            Wiki::new("frwikt", "https://fr.wiktionary.org/", 10),
+        ];
+        let edition = |wiki: usize, lang: &str| {
+            language_edition(&wikis, &wikis[wiki], lang).map(|wiki| wiki.id.as_str())
+        };
+        assert_eq!(edition(0, "ja"), Some("ja"));
+        assert_eq!(edition(1, "en"), Some("en"));
+        assert_eq!(edition(2, "fr"), Some("frwikt"));
+        assert_eq!(edition(0, "fr"), None);
+        assert_eq!(edition(0, "de"), None);
+    }
+
+    #[test]
+    fn test_from_options_fail() {
+        let domain_name = "https://en.wikipedia.org/";
+        assert_eq!(
+            from_options(domain_name, &["en".to_string()], &[], 10),
+            Err(WikiError::Duplicate("en".to_string()))
+        );
+        assert_eq!(
+            from_options(domain_name, &["de/x".to_string()], &[], 10),
+            Err(WikiError::InvalidOption("de/x".to_string()))
+        );
+        assert_eq!(
+            from_options(domain_name, &["de=de.wikipedia.org".to_string()], &[], 10),
+            Err(WikiError::InvalidDomain("de.wikipedia.org".to_string()))
+        );
+        assert_eq!(
+            from_options(domain_name, &[], &["fr=2".to_string()], 10),
+            Err(WikiError::Unknown("fr".to_string()))
+        );
+        assert_eq!(
+            from_options(domain_name, &[], &["en".to_string()], 10),
+            Err(WikiError::InvalidOption("en".to_string()))
+        );
+    }
+}
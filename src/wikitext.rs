@@ -0,0 +1,411 @@
+/* *****************************************************************************************************************
+ *
+ * Wikitext
+ * --------
+ *
+ * Extract links from raw wikitext, for pages that are read from dumps rather than parsed by the Wikipedia API.
+ * Links take the forms
+ *
+ *    [[Target]]                   [[Target|label]]              [[Target#Section|label]]
+ *    [[Category:Target]]          [[:Category:Target]]          [[fr:Cible]]
+ *
+ * Targets are normalised by the title module, the same way as every other title. Section anchors are removed, and
+ * links to a section of the same page are discarded.
+ *
+ * Links are returned as (namespace, title), matching the "ns" and "*" attributes of the API links. Interwiki and
+ * interlanguage links do not reference pages on this wiki, and are discarded. Any lower-case prefix of two or three
+ * letters (optionally followed by a hyphenated variant, e.g. zh-yue) is treated as a language code.
+ *
+ * Only links written into the page are found. Links generated by templates are not expanded.
+ *
+ * The context of a link is the sentence around it, as plain text, to explain why one page links to another. Templates,
+ * references, tables, headings and files are removed, each link is replaced with its label, and sentences longer than
+ * SNIPPET_LENGTH characters are cut around the link.
+ *
+ *******************************************************************************************************************/
+
+use crate::title;
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+
+lazy_static! {
+    static ref COMMENT: Regex = Regex::new(r"(?s)<!--.*?(-->|$)").unwrap();
+    static ref LANGUAGE: Regex = Regex::new(r"^[a-z]{2,3}(-[a-z]+)*$").unwrap();
+    static ref REFERENCE: Regex = Regex::new(r"(?is)<ref[^>]*/>|<ref[^>]*>.*?</ref>").unwrap();
+    static ref TAG: Regex = Regex::new(r"</?[a-zA-Z][^>]*>").unwrap();
+    static ref TEMPLATE: Regex = Regex::new(r"\{\{[^{}]*\}\}").unwrap();
+    static ref INNER_LINK: Regex = Regex::new(r"\[\[([^\[\]]*)\]\]").unwrap();
+    static ref EXTERNAL_LINK: Regex = Regex::new(r"\[(https?:)?//[^\s\]]*\s*([^\]]*)\]").unwrap();
+    static ref MARKER: Regex = Regex::new("\u{e000}[0-9]+\u{e001}|\u{e002}").unwrap();
+    static ref SENTENCE_END: Regex = Regex::new(r#"[.!?]['"’”)]*(\s|$)"#).unwrap();
+}
+
+// Longest context of a link, in characters
+static SNIPPET_LENGTH: usize = 240;
+
+// Private use characters that mark a link in plain text: LINK_START, the index of the link, LINK_LABEL, the label, then
+// LINK_END
+static LINK_START: char = '\u{e000}';
+static LINK_LABEL: char = '\u{e001}';
+static LINK_END: char = '\u{e002}';
+
+static INTERWIKI: &[&str] = &[
+    "b",
+    "c",
+    "commons",
+    "d",
+    "foundation",
+    "m",
+    "meta",
+    "mw",
+    "n",
+    "phab",
+    "q",
+    "s",
+    "species",
+    "v",
+    "voy",
+    "w",
+    "wikibooks",
+    "wikidata",
+    "wikinews",
+    "wikiquote",
+    "wikisource",
+    "wikispecies",
+    "wikiversity",
+    "wikivoyage",
+    "wikt",
+    "wiktionary",
+    "wmf",
+];
+
+static REDIRECT: &str = "#redirect";
+
+/* *****************************************************************************************************************
+ *
+ * Extract links
+ *
+ *******************************************************************************************************************/
+
+/// Extract the links from wikitext, in the order in which they appear on the page. Each link is returned once
+pub fn extract_links(text: &str) -> Vec<(i32, String)> {
+    let text = COMMENT.replace_all(text, "");
+    let mut links: Vec<(i32, String)> = Vec::new();
+    let mut seen: HashSet<(i32, String)> = HashSet::new();
+
+    // Scan from the character after each opening bracket, so links nested in a File caption are found
+    let mut rest: &str = &text;
+    while let Some(start) = rest.find("[[") {
+        rest = &rest[start + 2..];
+        let end = match rest.find("]]") {
+            Some(end) => end,
+            None => break,
+        };
+        let inner = &rest[..end];
+        let target = inner.split('|').next().unwrap_or_default();
+        if target.contains('\n') || target.contains("[[") {
+            continue;
+        }
+        if let Some(link) = parse_link(target) {
+            if seen.insert(link.clone()) {
+                links.push(link);
+            }
+        }
+    }
+    links
+}
+
+/// If the page is a redirect, return the title of the target page
+pub fn redirect_target(text: &str) -> Option<String> {
+    let text = text.trim_start();
+    if !text
+        .get(..REDIRECT.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(REDIRECT))
+    {
+        return None;
+    }
+    extract_links(text)
+        .into_iter()
+        .next()
+        .map(|(_, title)| title)
+}
+
+/// Parse the target of a link into (namespace, normalised title). Returns None for interwiki links, links to a
+/// section of the same page, and targets that are not valid titles
+pub fn parse_link(target: &str) -> Option<(i32, String)> {
+    let target = target.trim();
+    let target = target.strip_prefix(':').unwrap_or(target);
+    let target = target.split('#').next().unwrap_or_default();
+    if target.trim().is_empty() || target.contains(|c| "<>[]{}|".contains(c)) {
+        return None;
+    }
+
+    if let Some((prefix, _)) = target.split_once(':') {
+        let prefix = normalise_spaces(prefix);
+        if title::namespace(&prefix).is_none()
+            && (INTERWIKI
+                .iter()
+                .any(|interwiki| interwiki.eq_ignore_ascii_case(&prefix))
+                || LANGUAGE.is_match(&prefix))
+        {
+            return None;
+        }
+    }
+
+    let (ns, title) = title::parse(target);
+    if ns != 0 && title.ends_with(':') {
+        return None;
+    }
+    Some((ns, title))
+}
+
+/* *****************************************************************************************************************
+ *
+ * Link context
+ *
+ *******************************************************************************************************************/
+
+/// The sentence around the first link to each page, as (normalised title, plain text), in the order in which the links
+/// appear on the page
+pub fn link_contexts(text: &str) -> Vec<(String, String)> {
+    let text = COMMENT.replace_all(text, "");
+    let text = REFERENCE.replace_all(&text, "");
+    let mut text = TAG.replace_all(&text, "").to_string();
+    while TEMPLATE.is_match(&text) {
+        text = TEMPLATE.replace_all(&text, "").to_string();
+    }
+    let text = EXTERNAL_LINK.replace_all(&text, "$2");
+
+    // Replace links with marked labels, innermost first, so links in a File caption are removed with the File
+    let mut targets: Vec<String> = Vec::new();
+    let mut text = text.to_string();
+    while INNER_LINK.is_match(&text) {
+        text = INNER_LINK
+            .replace_all(&text, |caps: &Captures| mark_link(&caps[1], &mut targets))
+            .to_string();
+    }
+
+    let mut seen = HashSet::new();
+    let mut contexts = Vec::new();
+    for line in text.lines() {
+        let line = line.trim_start_matches(|c: char| "*#:;".contains(c) || c.is_whitespace());
+        if line.starts_with(|c| "=|!{".contains(c)) {
+            continue;
+        }
+        let mut rest = 0;
+        while let Some(start) = line[rest..].find(LINK_START).map(|start| start + rest) {
+            let label_start = match line[start..].find(LINK_LABEL) {
+                Some(label_start) => start + label_start,
+                None => break,
+            };
+            let end = match line[label_start..].find(LINK_END) {
+                Some(end) => label_start + end,
+                None => break,
+            };
+            rest = end;
+            let index: usize = match line[start + LINK_START.len_utf8()..label_start].parse() {
+                Ok(index) => index,
+                Err(_) => continue,
+            };
+            let target = &targets[index];
+            if !seen.insert(target.clone()) {
+                continue;
+            }
+            let snippet = snippet(line, start, label_start + LINK_LABEL.len_utf8(), end);
+            if !snippet.is_empty() {
+                contexts.push((target.clone(), snippet));
+            }
+        }
+    }
+    contexts
+}
+
+// Replace a link with its label, marked with the index of the target. Files, category tags, interwiki links and links
+// to a section of the same page are replaced with their label, unmarked, or removed
+fn mark_link(inner: &str, targets: &mut Vec<String>) -> String {
+    let (target, label) = match inner.split_once('|') {
+        Some((target, label)) => (target, label.rsplit('|').next().unwrap_or_default()),
+        None => (inner, inner.trim().trim_start_matches(':')),
+    };
+    match parse_link(target) {
+        Some((6, _)) => String::new(),
+        Some((14, _)) if !target.trim_start().starts_with(':') => String::new(),
+        Some((_, title)) => {
+            targets.push(title);
+            format!(
+                "{}{}{}{}{}",
+                LINK_START,
+                targets.len() - 1,
+                LINK_LABEL,
+                label,
+                LINK_END
+            )
+        }
+        None if inner.contains('|') => label.to_string(),
+        None => String::new(),
+    }
+}
+
+// The sentence of the line that holds the link from start to end, cut to SNIPPET_LENGTH characters around the label
+fn snippet(line: &str, start: usize, label_start: usize, end: usize) -> String {
+    let sentence_start = SENTENCE_END
+        .find_iter(&line[..start])
+        .last()
+        .map_or(0, |sentence_end| sentence_end.end());
+    let sentence_end = SENTENCE_END
+        .find(&line[end..])
+        .map_or(line.len(), |sentence_end| end + sentence_end.end());
+
+    let label = plain_text(&line[label_start..end]);
+    let room = SNIPPET_LENGTH.saturating_sub(label.chars().count()) / 2;
+    let before = plain_text(&line[sentence_start..start]);
+    let before = match before.chars().count() {
+        count if count > room => {
+            format!("…{}", before.chars().skip(count - room).collect::<String>())
+        }
+        _ => before,
+    };
+    let after = plain_text(&line[end..sentence_end]);
+    let after = match after.chars().count() {
+        count if count > room => format!("{}…", after.chars().take(room).collect::<String>()),
+        _ => after,
+    };
+    normalise_spaces(&format!("{}{}{}", before, label, after))
+}
+
+// Remove link markers and bold and italic quotes
+fn plain_text(text: &str) -> String {
+    MARKER
+        .replace_all(text, "")
+        .replace("'''", "")
+        .replace("''", "")
+}
+
+fn normalise_spaces(text: &str) -> String {
+    text.replace('_', " ")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_links() {
+        let text = "The [[rail_transport|railway]] uses [[Train#History|trains]] and \
+                    [[ steam  locomotive ]]. See [[#Section]], [[Train]] and [[fr:Train]].";
+        assert_eq!(
+            extract_links(text),
+            vec![
+                (0, "Rail transport".to_string()),
+                (0, "Train".to_string()),
+                (0, "Steam locomotive".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_links_namespaces() {
+        let text =
+            "[[Category:Rail transport]] [[:category:trains]] [[Talk:Train]] [[user talk:Ted]] \
+                    [[wikt:train]] [[Commons:Trains]] [[Mac: The Movie]]";
+        assert_eq!(
+            extract_links(text),
+            vec![
+                (14, "Category:Rail transport".to_string()),
+                (14, "Category:Trains".to_string()),
+                (1, "Talk:Train".to_string()),
+                (3, "User talk:Ted".to_string()),
+                (0, "Mac: The Movie".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_links_nested() {
+        let text = "[[File:Train.jpg|thumb|A [[locomotive]] hauling [[freight train|freight]]]] \
+                    <!-- [[Commented out]] --> {{Infobox|[[Template link]]}}";
+        assert_eq!(
+            extract_links(text),
+            vec![
+                (6, "File:Train.jpg".to_string()),
+                (0, "Locomotive".to_string()),
+                (0, "Freight train".to_string()),
+                (0, "Template link".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_links_invalid() {
+        let text = "[[{{{1}}}]] [[Broken\nlink]] [[ ]] [[Unclosed";
+        assert!(extract_links(text).is_empty());
+    }
+
+    #[test]
+    fn test_link_contexts() {
+        let text = "{{Infobox train|name=Train}}\n\
+                    A '''train''' is a series of connected vehicles that run along a [[Track (rail transport)|railway \
+                    track]].<ref>{{cite book|title=Trains}}</ref> Trains are hauled by a [[locomotive]]. See also \
+                    [[Tram]].\n\
+                    == History ==\n\
+                    [[File:Train.jpg|thumb|A [[Steam locomotive|steam]] train]]\n\
+                    * [[Monorail]]s run on [[Track (rail transport)|a single rail]]\n\
+                    [[Category:Trains]]";
+        assert_eq!(
+            link_contexts(text),
+            vec![
+                (
+                    "Track (rail transport)".to_string(),
+                    "A train is a series of connected vehicles that run along a railway track."
+                        .to_string()
+                ),
+                (
+                    "Locomotive".to_string(),
+                    "Trains are hauled by a locomotive.".to_string()
+                ),
+                ("Tram".to_string(), "See also Tram.".to_string()),
+                (
+                    "Monorail".to_string(),
+                    "Monorails run on a single rail".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_link_contexts_long_sentence() {
+        let text = format!("{} [[Train]] {}.", "word ".repeat(100), "word ".repeat(100));
+        let contexts = link_contexts(&text);
+        let snippet = &contexts[0].1;
+        assert!(snippet.chars().count() <= SNIPPET_LENGTH + 2);
+        assert!(snippet.starts_with('…'));
+        assert!(snippet.ends_with('…'));
+        assert!(snippet.contains(" Train "));
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        assert_eq!(
+            redirect_target("  #REDIRECT [[rail transport#History]]\n{{R from plural}}"),
+            Some("Rail transport".to_string())
+        );
+        assert_eq!(
+            redirect_target("#redirect[[Train]]"),
+            Some("Train".to_string())
+        );
+        assert_eq!(redirect_target("A [[Train]] is a vehicle"), None);
+        // The first bytes cut through a character
+        assert_eq!(redirect_target("Ελληνικά είναι [[Γλώσσα]]"), None);
+        assert_eq!(redirect_target("列車は[[鉄道]]"), None);
+        assert_eq!(redirect_target("#"), None);
+    }
+}
@@ -1,28 +1,57 @@
-use std::{fmt, sync::mpsc::Receiver};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::mpsc::Receiver,
+};
 
 use sysinfo::{System, SystemExt};
 use tokio::{sync::mpsc, task::JoinHandle};
 
 use crate::entry;
 use crate::entry::Entry;
+use crate::fetch::FetchCommand;
 use crate::foundation;
 use crate::foundation::Foundation;
 use crate::opt::OPT;
+use crate::pagerank::{RankCommand, RankState};
+use crate::slabs::Slab;
+use crate::wiki;
 
 // ***********************************************************************************************
 
 static MpscBufferSize: usize = 64;
 
+// Pages with this many inbound links are hubs, and are pinned in the page cache
+static HUB_INBOUND_LINKS: usize = 500;
+
 #[derive(Debug)]
 pub enum WorkerCommand {
     End,
     // Get an entry if it exists
     Request {
+        wiki: String,
         title: String,
         tx_resp: mpsc::Sender<WorkerResponse>,
     },
+    // Get a copy of the entry for a digest, if the worker holds one
+    Get {
+        digest: entry::Digest,
+        tx: mpsc::Sender<Option<Entry>>,
+    },
     // Add or update an entry
     Update(Entry),
+    // Record links from one page into pages held by this worker
+    Backlink {
+        from: entry::Digest,
+        to: Vec<entry::Digest>,
+    },
+    // Remove links from one page into pages held by this worker, once the page no longer links to them
+    RemoveBacklink {
+        from: entry::Digest,
+        to: Vec<entry::Digest>,
+    },
+    // A phase of a PageRank computation, sent by pagerank::compute
+    Rank(RankCommand),
 }
 
 #[derive(Debug, PartialEq)]
@@ -45,12 +74,43 @@ pub struct Worker {
     bitwise_slab_match: u16,
     tx_commands: TxCommands,
     rx_command: RxCommand,
+    tx_to_fetch: mpsc::Sender<FetchCommand>,
+    slabs: Vec<Slab>,
+    // State of the PageRank computation in progress
+    rank: Option<RankState>,
+}
+
+/// The links added to and removed from a page between the version held and a newer version
+#[derive(Debug, Default, PartialEq)]
+pub struct OutboundDiff {
+    pub added: Vec<entry::Digest>,
+    pub removed: Vec<entry::Digest>,
+}
+
+impl OutboundDiff {
+    /// Compare the outbound links of two versions of a page. Each page appears once, in the order linked
+    pub fn between(held: &[entry::Digest], outbound: &[entry::Digest]) -> OutboundDiff {
+        let held_links: HashSet<&entry::Digest> = held.iter().collect();
+        let links: HashSet<&entry::Digest> = outbound.iter().collect();
+        let mut seen = HashSet::new();
+        let added = outbound
+            .iter()
+            .filter(|to| !held_links.contains(to) && seen.insert(**to))
+            .copied()
+            .collect();
+        let removed = held
+            .iter()
+            .filter(|to| !links.contains(to) && seen.insert(**to))
+            .copied()
+            .collect();
+        OutboundDiff { added, removed }
+    }
 }
 
 type Workers = Vec<Worker>;
-type TxCommand = mpsc::Sender<WorkerCommand>;
+pub type TxCommand = mpsc::Sender<WorkerCommand>;
 type RxCommand = mpsc::Receiver<WorkerCommand>;
-type TxCommands = Vec<TxCommand>;
+pub type TxCommands = Vec<TxCommand>;
 type RxCommands = Vec<RxCommand>;
 
 /* *****************************************************************************************************************
@@ -66,7 +126,10 @@ type RxCommands = Vec<RxCommand>;
 
 /// Create worker tasks
 
-pub async fn new(foundation: &foundation::Foundation) -> (Vec<JoinHandle<()>>, TxCommands) {
+pub async fn new(
+    foundation: &foundation::Foundation,
+    tx_to_fetch: mpsc::Sender<FetchCommand>,
+) -> (Vec<JoinHandle<()>>, TxCommands) {
     trace!("worker::new");
 
     let worker_count = foundation.get_worker_count().try_into().unwrap();
@@ -78,8 +141,11 @@ pub async fn new(foundation: &foundation::Foundation) -> (Vec<JoinHandle<()>>, T
             worker_id,
             tx_commands: tx_commands.clone(),
             rx_command,
+            tx_to_fetch: tx_to_fetch.clone(),
             bitwise_worker_match: (foundation.get_worker_count() - 1).try_into().unwrap(),
             bitwise_slab_match: (foundation.get_slabs_per_worker() - 1).try_into().unwrap(),
+            slabs: new_slabs(foundation),
+            rank: None,
         };
         trace!("Spawning worker {}", worker_id);
         join_handles.push(tokio::spawn(
@@ -103,6 +169,82 @@ fn init_command_handles(worker_count: usize) -> (TxCommands, RxCommands) {
     (tx_commands, rx_commands)
 }
 
+fn new_slabs(foundation: &foundation::Foundation) -> Vec<Slab> {
+    (0..foundation.get_slabs_per_worker())
+        .map(|_| Slab::new())
+        .collect()
+}
+
+// Entries for pages that have been loaded, rather than stubs
+fn pages(slabs: &[Slab]) -> impl Iterator<Item = &Entry> {
+    slabs
+        .iter()
+        .flat_map(|slab| slab.iter())
+        .filter(|entry| !entry.is_stub())
+}
+
+/// Identify the worker that holds the entry for a digest. Uses the same bits as Worker::extract_worker_id_from
+pub fn get_worker_id_for(digest: entry::Digest, worker_count: usize) -> usize {
+    let id = u16::from_le_bytes([digest[0], digest[1]]) as usize;
+    id & (worker_count - 1)
+}
+
+/// Add or update an entry in the worker that owns it, and add a back-link to each page that the entry references.
+/// Back-links are sent before the entry, so that they are held in a stub if the referenced page is not yet loaded
+pub async fn update(
+    entry: Entry,
+    tx_commands: &TxCommands,
+) -> Result<(), mpsc::error::SendError<WorkerCommand>> {
+    let from = entry.digest();
+    let to = entry.get_outbound().iter().copied();
+    send_by_worker(to, tx_commands, |to| WorkerCommand::Backlink { from, to }).await?;
+    tx_commands[get_worker_id_for(from, tx_commands.len())]
+        .send(WorkerCommand::Update(entry))
+        .await
+}
+
+/// Replace an entry held by the workers with a newer version of the page. Back-links are sent for the links that the
+/// held version did not have, and removed for the links that the newer version no longer has, so that the inbound
+/// links of the pages linked from both are left as they are
+pub async fn update_held(
+    held: &Entry,
+    entry: Entry,
+    tx_commands: &TxCommands,
+) -> Result<(), mpsc::error::SendError<WorkerCommand>> {
+    let from = entry.digest();
+    let diff = OutboundDiff::between(held.get_outbound(), entry.get_outbound());
+    send_by_worker(diff.added.into_iter(), tx_commands, |to| {
+        WorkerCommand::Backlink { from, to }
+    })
+    .await?;
+    send_by_worker(diff.removed.into_iter(), tx_commands, |to| {
+        WorkerCommand::RemoveBacklink { from, to }
+    })
+    .await?;
+    tx_commands[get_worker_id_for(from, tx_commands.len())]
+        .send(WorkerCommand::Update(entry))
+        .await
+}
+
+// Group the pages by the worker that owns them, and send one command to each worker
+async fn send_by_worker(
+    pages: impl Iterator<Item = entry::Digest>,
+    tx_commands: &TxCommands,
+    command: impl Fn(Vec<entry::Digest>) -> WorkerCommand,
+) -> Result<(), mpsc::error::SendError<WorkerCommand>> {
+    let mut by_worker: HashMap<usize, Vec<entry::Digest>> = HashMap::new();
+    for page in pages {
+        by_worker
+            .entry(get_worker_id_for(page, tx_commands.len()))
+            .or_default()
+            .push(page);
+    }
+    for (worker_id, pages) in by_worker {
+        tx_commands[worker_id].send(command(pages)).await?;
+    }
+    Ok(())
+}
+
 pub async fn shut_down(
     join_handles: Vec<JoinHandle<()>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
@@ -126,13 +268,43 @@ impl Worker {
                 worker.worker_id, &worker_command
             );
             match worker_command {
-                Request { title, tx_resp } => {
-                    let digest = entry::Entry::get_digest(&title);
+                Request {
+                    wiki,
+                    title,
+                    tx_resp,
+                } => {
+                    let digest = match wiki::get(&wiki) {
+                        Ok(wiki) => entry::Entry::get_digest_for(wiki, &title),
+                        Err(err) => {
+                            warn!("Worker {}: {}", worker.worker_id, err);
+                            continue;
+                        }
+                    };
                     let id = worker.extract_worker_id_from(digest);
                     Worker::process_request(title, tx_resp).await
                 }
+                Get { digest, tx } => {
+                    let _ = tx.send(worker.get_entry(digest).cloned()).await;
+                }
                 End => break,
-                Update(_) => todo!(),
+                Rank(command) => worker.rank(command).await,
+                Update(entry) => worker.update_entry(entry),
+                RemoveBacklink { from, to } => worker.remove_backlinks(from, to),
+                Backlink { from, to } => {
+                    let hubs = worker.add_backlinks(from, to);
+                    if !hubs.is_empty()
+                        && worker
+                            .tx_to_fetch
+                            .send(FetchCommand::Pin(hubs))
+                            .await
+                            .is_err()
+                    {
+                        warn!(
+                            "Worker {} unable to pin hubs in the page cache",
+                            worker.worker_id
+                        );
+                    }
+                }
             }
         }
         debug!("Worker {} exiting...", worker.worker_id);
@@ -167,6 +339,92 @@ impl Worker {
         //         Add page to slab when fetch responds
     }
 
+    // Handle a phase of a PageRank computation. A phase that arrives without a computation in progress is ignored, and
+    // the coordinator stops waiting when its channel closes
+    async fn rank(&mut self, command: RankCommand) {
+        if let RankCommand::Start { seed, damping, tx } = command {
+            self.rank = Some(RankState::new(seed, damping));
+            let pages = pages(&self.slabs).count();
+            let held =
+                seed.is_some_and(|seed| self.get_entry(seed).is_some_and(|entry| !entry.is_stub()));
+            let _ = tx.send((pages, held)).await;
+            return;
+        }
+        let state = match self.rank.as_mut() {
+            Some(state) => state,
+            None => return,
+        };
+        let pages = pages(&self.slabs);
+        match command {
+            RankCommand::Start { .. } => {}
+            RankCommand::Init { pages: total, tx } => {
+                state.init(pages, total);
+                let _ = tx.send(()).await;
+            }
+            // Shares are sent from a task of their own, so the worker is never blocked by a full channel to a worker
+            // that is itself sending
+            RankCommand::Scatter { tx } => {
+                let (by_worker, dangling) = state.scatter(pages, self.tx_commands.len());
+                let tx_commands = self.tx_commands.clone();
+                tokio::spawn(async move {
+                    for (worker_id, shares) in by_worker {
+                        let command = WorkerCommand::Rank(RankCommand::Contribute(shares));
+                        if tx_commands[worker_id].send(command).await.is_err() {
+                            return;
+                        }
+                    }
+                    let _ = tx.send(dangling).await;
+                });
+            }
+            RankCommand::Contribute(shares) => state.contribute(shares),
+            RankCommand::Gather { tx } => {
+                let _ = tx.send(state.gather()).await;
+            }
+            RankCommand::Apply { lost, tx } => {
+                let _ = tx.send(state.apply(lost)).await;
+            }
+            RankCommand::Finish { store, top, tx } => {
+                let _ = tx.send(state.top(pages, top)).await;
+                if let Some(state) = self.rank.take().filter(|_| store) {
+                    for entry in self.slabs.iter_mut().flat_map(|slab| slab.iter_mut()) {
+                        if let Some(relative) = state.relative(entry.digest()) {
+                            entry.set_pagerank(relative);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn get_entry(&self, digest: entry::Digest) -> Option<&Entry> {
+        let slab_id = self.extract_slab_id_from(digest) as usize;
+        self.slabs[slab_id].get(&digest)
+    }
+
+    fn update_entry(&mut self, entry: Entry) {
+        let slab_id = self.extract_slab_id_from(entry.digest()) as usize;
+        self.slabs[slab_id].update(entry);
+    }
+
+    // Returns the pages that became hubs with these back-links
+    fn add_backlinks(&mut self, from: entry::Digest, to: Vec<entry::Digest>) -> Vec<entry::Digest> {
+        let mut hubs = Vec::new();
+        for to in to {
+            let slab_id = self.extract_slab_id_from(to) as usize;
+            if self.slabs[slab_id].add_backlink(to, from) == HUB_INBOUND_LINKS {
+                hubs.push(to);
+            }
+        }
+        hubs
+    }
+
+    fn remove_backlinks(&mut self, from: entry::Digest, to: Vec<entry::Digest>) {
+        for to in to {
+            let slab_id = self.extract_slab_id_from(to) as usize;
+            self.slabs[slab_id].remove_backlink(to, from);
+        }
+    }
+
     fn extract_worker_id_from(&self, digest: crate::entry::Digest) -> u16 {
         let mut id: u16 = digest[1].into();
         id = id << 8;
@@ -226,8 +484,16 @@ impl fmt::Display for WorkerCommand {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match self {
             WorkerCommand::End => "End".to_string(),
-            WorkerCommand::Request { title, tx_resp } => format!("Request:: Title: {}", title),
-            WorkerCommand::Update(_) => todo!(),
+            WorkerCommand::Request { wiki, title, .. } => {
+                format!("Request:: Wiki: {} Title: {}", wiki, title)
+            }
+            WorkerCommand::Get { .. } => "Get".to_string(),
+            WorkerCommand::Update(entry) => format!("Update:: Title: {}", entry.get_title()),
+            WorkerCommand::Backlink { from: _, to } => format!("Backlink:: {} pages", to.len()),
+            WorkerCommand::RemoveBacklink { from: _, to } => {
+                format!("RemoveBacklink:: {} pages", to.len())
+            }
+            WorkerCommand::Rank(command) => format!("Rank:: {}", command),
         };
         write!(f, "{}", msg)
     }
@@ -245,8 +511,9 @@ mod tests {
 
     #[tokio::test]
     async fn test_new_worker() {
+        let (tx_to_fetch, _rx_by_fetch) = mpsc::channel(1);
         let (mut join_handles, mut tx_handles) =
-            new(&foundation::tests::get_test_foundation()).await;
+            new(&foundation::tests::get_test_foundation(), tx_to_fetch).await;
 
         assert_eq!(join_handles.len(), 128);
         for tx_handle in tx_handles.drain(..) {
@@ -262,14 +529,14 @@ mod tests {
     fn test_extract_worker_id_from() {
         let worker = get_test_worker();
         let digest = crate::entry::Entry::get_digest("Rail transport");
-        assert_eq!(worker.extract_worker_id_from(digest), 11);
+        assert_eq!(worker.extract_worker_id_from(digest), 24);
     }
 
     #[test]
     fn test_extract_slab_id_from() {
         let worker = get_test_worker();
         let digest = crate::entry::Entry::get_digest("Rail transport");
-        assert_eq!(worker.extract_slab_id_from(digest), 4);
+        assert_eq!(worker.extract_slab_id_from(digest), 3);
     }
 
     #[test]
@@ -295,6 +562,7 @@ mod tests {
             mpsc::Receiver<WorkerResponse>,
         ) = mpsc::channel(1024);
         let request = WorkerCommand::Request {
+            wiki: "en".to_string(),
             title: "Railways".to_string(),
             tx_resp: response_tx.clone(),
         };
@@ -304,19 +572,168 @@ mod tests {
         assert!(response == WorkerResponse::Fetch);
     }
 
+    #[test]
+    fn test_get_worker_id_for() {
+        let worker = get_test_worker();
+        let digest = crate::entry::Entry::get_digest("Rail transport");
+        assert_eq!(get_worker_id_for(digest, 128), 24);
+        assert_eq!(
+            get_worker_id_for(digest, 128),
+            worker.extract_worker_id_from(digest) as usize
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_and_backlink() {
+        let mut worker = get_test_worker();
+
+        // A single worker owns every digest
+        let (tx_command, mut rx_command) = mpsc::channel(8);
+        let tx_commands = vec![tx_command];
+        let train = Entry::get_digest("Train");
+        let rail_transport = Entry::get_digest("Rail transport");
+        update(
+            Entry::new("Rail transport".to_string(), vec![train]),
+            &tx_commands,
+        )
+        .await
+        .unwrap();
+        update(Entry::new("Train".to_string(), vec![]), &tx_commands)
+            .await
+            .unwrap();
+        drop(tx_commands);
+
+        let mut commands = Vec::new();
+        while let Some(command) = rx_command.recv().await {
+            commands.push(command);
+        }
+        assert!(matches!(commands[0], WorkerCommand::Backlink { .. }));
+        assert!(matches!(commands[1], WorkerCommand::Update(_)));
+        assert!(matches!(commands[2], WorkerCommand::Update(_)));
+
+        for command in commands {
+            match command {
+                WorkerCommand::Update(entry) => worker.update_entry(entry),
+                WorkerCommand::Backlink { from, to } => {
+                    worker.add_backlinks(from, to);
+                }
+                _ => unreachable!(),
+            }
+        }
+        let entry = worker.get_entry(train).unwrap();
+        assert_eq!(entry.get_title(), "Train");
+        assert_eq!(entry.get_inbound(), &[rail_transport]);
+        let entry = worker.get_entry(rail_transport).unwrap();
+        assert_eq!(entry.get_outbound(), &[train]);
+        assert!(entry.get_inbound().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_update_held() {
+        let mut worker = get_test_worker();
+        let (tx_command, mut rx_command) = mpsc::channel(8);
+        let tx_commands = vec![tx_command];
+        let train = Entry::get_digest("Train");
+        let locomotive = Entry::get_digest("Locomotive");
+        let tram = Entry::get_digest("Tram");
+        let monorail = Entry::get_digest("Monorail");
+        update(
+            Entry::new("Train".to_string(), vec![locomotive, tram]),
+            &tx_commands,
+        )
+        .await
+        .unwrap();
+        let held = Entry::new("Train".to_string(), vec![locomotive, tram]);
+        update_held(
+            &held,
+            Entry::new("Train".to_string(), vec![locomotive, monorail]),
+            &tx_commands,
+        )
+        .await
+        .unwrap();
+        drop(tx_commands);
+
+        let (mut backlinks, mut removed) = (Vec::new(), Vec::new());
+        while let Some(command) = rx_command.recv().await {
+            match command {
+                WorkerCommand::Update(entry) => worker.update_entry(entry),
+                WorkerCommand::Backlink { from, to } => {
+                    backlinks.push(to.clone());
+                    worker.add_backlinks(from, to);
+                }
+                WorkerCommand::RemoveBacklink { from, to } => {
+                    removed.push(to.clone());
+                    worker.remove_backlinks(from, to);
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert_eq!(backlinks, vec![vec![locomotive, tram], vec![monorail]]);
+        assert_eq!(removed, vec![vec![tram]]);
+        assert_eq!(
+            worker.get_entry(locomotive).unwrap().get_inbound(),
+            &[train]
+        );
+        assert_eq!(worker.get_entry(monorail).unwrap().get_inbound(), &[train]);
+        assert!(worker.get_entry(tram).is_none());
+        assert_eq!(
+            worker.get_entry(train).unwrap().get_outbound(),
+            &[locomotive, monorail]
+        );
+    }
+
+    #[test]
+    fn test_outbound_diff() {
+        let [train, tram, monorail, locomotive] =
+            ["Train", "Tram", "Monorail", "Locomotive"].map(Entry::get_digest);
+        let diff = OutboundDiff::between(
+            &[train, tram, tram, locomotive],
+            &[monorail, train, monorail],
+        );
+        assert_eq!(
+            diff,
+            OutboundDiff {
+                added: vec![monorail],
+                removed: vec![tram, locomotive],
+            }
+        );
+        assert_eq!(
+            OutboundDiff::between(&[train, tram], &[tram, train]),
+            OutboundDiff::default()
+        );
+    }
+
+    #[test]
+    fn test_add_backlinks_finds_hubs() {
+        let mut worker = get_test_worker();
+        let hub = Entry::get_digest("United States");
+        let other = Entry::get_digest("Train");
+        for page in 1..HUB_INBOUND_LINKS {
+            let from = Entry::get_digest(&format!("Page {}", page));
+            assert!(worker.add_backlinks(from, vec![hub, other]).is_empty());
+        }
+        let from = Entry::get_digest("Page 0");
+        assert_eq!(worker.add_backlinks(from, vec![hub]), vec![hub]);
+        assert!(worker.add_backlinks(from, vec![hub]).is_empty());
+    }
+
     fn get_test_worker() -> Worker {
         let foundation = foundation::tests::get_test_foundation();
         let worker_count = foundation.get_worker_count().try_into().unwrap();
 
         let (tx_commands, mut rx_commands) = init_command_handles(worker_count);
         let rx_command = rx_commands.swap_remove(0);
+        let (tx_to_fetch, _) = mpsc::channel(1);
 
         Worker {
             worker_id: 0,
             tx_commands,
             rx_command,
+            tx_to_fetch,
             bitwise_worker_match: (foundation.get_worker_count() - 1).try_into().unwrap(),
             bitwise_slab_match: (foundation.get_slabs_per_worker() - 1).try_into().unwrap(),
+            slabs: new_slabs(&foundation),
+            rank: None,
         }
     }
 }
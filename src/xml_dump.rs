@@ -0,0 +1,497 @@
+/* *****************************************************************************************************************
+ *
+ * XML dump importer
+ * -----------------
+ *
+ * Build a snapshot from a MediaWiki XML page dump (e.g. enwiki-latest-pages-articles.xml.bz2), as an alternative to
+ * the SQL dumps. The dump may be bzip2 compressed (including multistream dumps), gzip compressed, or plain.
+ *
+ * The dump is stream-parsed one <page> at a time. Links are extracted from the wikitext of each page in namespace 0,
+ * and the page is converted into a FetchEntry with FetchEntry::from_links, so the disambiguation and redirect rules
+ * applied to pages pulled from Wikipedia also apply to pages read from the dump. A page is a redirect if the dump
+ * holds a <redirect> element for the page, or if the wikitext starts with #REDIRECT.
+ *
+ * The links of each page are read from its last revision. With as_of, the links are read from the last revision made
+ * at or before as_of, so a history dump (pages-meta-history) gives the graph as it was at that time. Pages with no
+ * revision by then are left out, and redirects are found from the wikitext of the revision alone, as the <redirect>
+ * element of the page describes its latest revision.
+ *
+ * Redirects may appear in the dump after the pages that link to them, so the import takes two passes
+ *    1. Parse the dump, recording redirects and disambiguation pages, and spooling every other FetchEntry to a
+ *       temporary file alongside the output
+ *    2. Read the spool, replace links to redirects with links to the redirect target, discard links to
+ *       disambiguation pages, and write each Entry to the snapshot
+ *
+ *******************************************************************************************************************/
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use quick_xml::{escape::resolve_predefined_entity, events::Event, Reader, XmlVersion};
+
+use crate::entry::{Digest, Entry};
+use crate::fetch::{self, FetchEntry};
+use crate::snapshot::{ImportSummary, SnapshotWriter};
+use crate::wikitext;
+
+#[derive(Debug, Default)]
+struct DumpPage {
+    title: String,
+    ns: String,
+    redirect: Option<String>,
+    // Text of the revision read
+    text: String,
+    // True once a revision has been read
+    revised: bool,
+}
+
+#[derive(Debug, Default)]
+struct Revision {
+    timestamp: String,
+    text: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Title,
+    Ns,
+    Timestamp,
+    Text,
+}
+
+/* *****************************************************************************************************************
+ *
+ * Import the dump into a snapshot
+ *
+ *******************************************************************************************************************/
+
+/// Import the dump. With `as_of`, in seconds since the Unix epoch, pages are read as they were at that time
+pub fn import(dump: &Path, output: &Path, as_of: Option<u64>) -> Result<ImportSummary, io::Error> {
+    let mut summary = ImportSummary::default();
+    let mut redirects: HashMap<Digest, Digest> = HashMap::new();
+    let mut disambiguation: HashSet<Digest> = HashSet::new();
+
+    // Pass 1: parse the dump into the spool
+    let spool_path = output.with_extension("spool");
+    let mut spool = BufWriter::new(File::create(&spool_path)?);
+    read_pages(dump, as_of, |page| {
+        if page.ns.trim() != "0" {
+            return Ok(());
+        }
+        let fetch_entry = to_fetch_entry(page);
+        if let Some(target) = &fetch_entry.redirect {
+            redirects.insert(fetch_entry.digest, Entry::get_digest(target));
+            return Ok(());
+        }
+        if fetch_entry.disambiguation {
+            disambiguation.insert(fetch_entry.digest);
+        }
+        bincode::serialize_into(&mut spool, &fetch_entry).map_err(to_io_error)?;
+        summary.pages += 1;
+        Ok(())
+    })?;
+    spool.flush()?;
+    drop(spool);
+    summary.redirects = redirects.len() as u64;
+    info!(
+        "Read {} pages and {} redirects from {}",
+        summary.pages,
+        summary.redirects,
+        dump.to_string_lossy()
+    );
+
+    // Pass 2: resolve the links and write the snapshot
+    let mut writer = SnapshotWriter::create(output)?;
+    let mut spool = BufReader::new(File::open(&spool_path)?);
+    let mut seen: HashSet<Digest> = HashSet::new();
+    for _ in 0..summary.pages {
+        let fetch_entry: FetchEntry = bincode::deserialize_from(&mut spool).map_err(to_io_error)?;
+        seen.clear();
+        let outbound: Vec<Digest> = fetch_entry
+            .outbound
+            .iter()
+            .map(|title| Entry::get_digest(title))
+            .map(|digest| *redirects.get(&digest).unwrap_or(&digest))
+            .filter(|digest| *digest != fetch_entry.digest && !disambiguation.contains(digest))
+            .filter(|digest| seen.insert(*digest))
+            .collect();
+        summary.links += outbound.len() as u64;
+        writer.write(&Entry::new(fetch_entry.title, outbound))?;
+    }
+    summary.entries = writer.finish()?;
+    fs::remove_file(&spool_path)?;
+
+    info!("Import complete. {}", summary);
+    Ok(summary)
+}
+
+fn to_fetch_entry(page: DumpPage) -> FetchEntry {
+    let redirect = page
+        .redirect
+        .or_else(|| wikitext::redirect_target(&page.text));
+    let links = match redirect {
+        Some(_) => Vec::new(),
+        None => wikitext::extract_links(&page.text),
+    };
+    FetchEntry::from_links(page.title, links, redirect)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Read the pages from a dump
+ *
+ *******************************************************************************************************************/
+
+fn open_dump(path: &Path) -> Result<Box<dyn BufRead>, io::Error> {
+    let file = File::open(path)?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("bz2") => Ok(Box::new(BufReader::new(MultiBzDecoder::new(file)))),
+        Some("gz") => Ok(Box::new(BufReader::new(MultiGzDecoder::new(file)))),
+        _ => Ok(Box::new(BufReader::new(file))),
+    }
+}
+
+/// Stream the pages of a dump, passing each page to `page` as its closing tag is read, with the text of its last
+/// revision, or its last revision made at or before `as_of`. Pages with no such revision are skipped
+fn read_pages(
+    path: &Path,
+    as_of: Option<u64>,
+    mut page: impl FnMut(DumpPage) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let mut reader = Reader::from_reader(open_dump(path)?);
+    let mut buf = Vec::new();
+    let mut current: Option<DumpPage> = None;
+    let mut revision: Option<Revision> = None;
+    let mut field: Option<Field> = None;
+
+    loop {
+        match reader.read_event_into(&mut buf).map_err(to_io_error)? {
+            Event::Start(element) => match element.name().as_ref() {
+                "page" => current = Some(DumpPage::default()),
+                "revision" => revision = Some(Revision::default()),
+                "title" => field = Some(Field::Title),
+                "ns" => field = Some(Field::Ns),
+                "timestamp" => field = Some(Field::Timestamp),
+                "text" => field = Some(Field::Text),
+                _ => (),
+            },
+            Event::Empty(element) if element.name().as_ref() == "redirect" => {
+                if let (Some(current), Some(title)) = (
+                    current.as_mut(),
+                    element.try_get_attribute("title").map_err(to_io_error)?,
+                ) {
+                    current.redirect = Some(
+                        title
+                            .normalized_value(XmlVersion::Implicit1_0)
+                            .map_err(to_io_error)?
+                            .to_string(),
+                    );
+                }
+            }
+            Event::Text(text) => append(&mut current, &mut revision, field, &text.xml10_content()),
+            Event::CData(text) => append(&mut current, &mut revision, field, &text.into_inner()),
+            Event::GeneralRef(reference) => {
+                if let Some(c) = reference.resolve_char_ref().map_err(to_io_error)? {
+                    append(
+                        &mut current,
+                        &mut revision,
+                        field,
+                        c.encode_utf8(&mut [0; 4]),
+                    );
+                } else if let Some(resolved) = resolve_predefined_entity(&reference.into_inner()) {
+                    append(&mut current, &mut revision, field, resolved);
+                }
+            }
+            Event::End(element) => match element.name().as_ref() {
+                "page" => {
+                    if let Some(mut current) = current.take() {
+                        if as_of.is_some() {
+                            current.redirect = None;
+                        }
+                        if current.revised || as_of.is_none() {
+                            page(current)?;
+                        }
+                    }
+                }
+                "revision" => {
+                    if let (Some(current), Some(revision)) = (current.as_mut(), revision.take()) {
+                        let made = fetch::read_timestamp(&revision.timestamp);
+                        if as_of.is_none_or(|as_of| made.is_some_and(|made| made <= as_of)) {
+                            current.text = revision.text;
+                            current.revised = true;
+                        }
+                    }
+                }
+                "title" | "ns" | "timestamp" | "text" => field = None,
+                _ => (),
+            },
+            Event::Eof => return Ok(()),
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+fn append(
+    page: &mut Option<DumpPage>,
+    revision: &mut Option<Revision>,
+    field: Option<Field>,
+    text: &str,
+) {
+    match (field, page, revision) {
+        (Some(Field::Timestamp), _, Some(revision)) => revision.timestamp.push_str(text),
+        (Some(Field::Text), _, Some(revision)) => revision.text.push_str(text),
+        (Some(Field::Title), Some(page), None) => page.title.push_str(text),
+        (Some(Field::Ns), Some(page), None) => page.ns.push_str(text),
+        _ => (),
+    }
+}
+
+fn to_io_error<E: std::error::Error + Send + Sync + 'static>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/* *****************************************************************************************************************
+ *
+ * Tests
+ *
+ * *****************************************************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::{tests::get_snapshot_path, SnapshotReader};
+    use bzip2::{write::BzEncoder, Compression};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_read_pages() {
+        let dump = write_dump("read_pages", "xml", DUMP.as_bytes());
+        let mut pages = Vec::new();
+        read_pages(&dump, None, |page| {
+            pages.push(page);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(pages.len(), 6);
+        assert_eq!(pages[0].title, "Rail transport");
+        assert_eq!(pages[0].ns, "0");
+        assert!(pages[0].text.contains("[[Trains|trains]] & [[Locomotive]]"));
+        assert_eq!(pages[1].redirect, Some("Train".to_string()));
+        assert_eq!(pages[5].ns, "1");
+        fs::remove_file(&dump).unwrap();
+    }
+
+    #[test]
+    fn test_import_bz2() {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(DUMP.as_bytes()).unwrap();
+        let dump = write_dump("import", "xml.bz2", &encoder.finish().unwrap());
+        let output = get_snapshot_path("xml_import");
+
+        let summary = import(&dump, &output, None).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                pages: 3,
+                redirects: 2,
+                links: 3,
+                entries: 3
+            }
+        );
+
+        // Links to the redirects "Trains" and "Railways" are resolved, the link to the disambiguation page
+        // "Mercury" is discarded, and the self link from "Train" is discarded
+        let entries: HashMap<String, Entry> = SnapshotReader::open(&output)
+            .unwrap()
+            .map(|entry| entry.unwrap())
+            .map(|entry| (entry.get_title().to_string(), entry))
+            .collect();
+        assert_eq!(
+            entries["Rail transport"].get_outbound(),
+            &[Entry::get_digest("Train"), Entry::get_digest("Locomotive")]
+        );
+        assert_eq!(
+            entries["Train"].get_outbound(),
+            &[Entry::get_digest("Rail transport")]
+        );
+        assert!(entries["Mercury"].get_outbound().is_empty());
+        assert!(!output.with_extension("spool").exists());
+
+        fs::remove_file(&dump).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn test_read_pages_as_of() {
+        let dump = write_dump("history", "xml", HISTORY_DUMP.as_bytes());
+        let texts = |as_of| {
+            let mut texts = Vec::new();
+            read_pages(&dump, Some(as_of), |page| {
+                texts.push((page.title, page.text, page.redirect));
+                Ok(())
+            })
+            .unwrap();
+            texts
+        };
+
+        // Between the revisions of "Train", before "Tram" was created, and while "Trains" was not yet a redirect
+        let as_of = fetch::read_timestamp("2015-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            texts(as_of),
+            vec![
+                ("Train".to_string(), "A [[Locomotive]].".to_string(), None),
+                ("Trains".to_string(), "See [[Train]].".to_string(), None),
+            ]
+        );
+
+        // A revision made at as_of is read
+        let as_of = fetch::read_timestamp("2016-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            texts(as_of),
+            vec![
+                ("Train".to_string(), "A [[Monorail]].".to_string(), None),
+                (
+                    "Trains".to_string(),
+                    "#REDIRECT [[Train]]".to_string(),
+                    None
+                ),
+                ("Tram".to_string(), "A [[Train]].".to_string(), None),
+            ]
+        );
+        fs::remove_file(&dump).unwrap();
+    }
+
+    // ***********************************************************************************************
+
+    fn write_dump(name: &str, extension: &str, contents: &[u8]) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "six_degrees_xml_{}_{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    const DUMP: &str = r###"<mediawiki xmlns="http://www.mediawiki.org/xml/export-0.10/" version="0.10" xml:lang="en">
+  <siteinfo>
+    <sitename>Wikipedia</sitename>
+    <dbname>enwiki</dbname>
+  </siteinfo>
+  <page>
+    <title>Rail transport</title>
+    <ns>0</ns>
+    <id>1</id>
+    <revision>
+      <id>100</id>
+      <timestamp>2024-01-01T00:00:00Z</timestamp>
+      <text bytes="120" xml:space="preserve">'''Rail transport''' uses [[Trains|trains]] &amp; [[Locomotive]]s. See [[Mercury]] and [[Category:Rail transport]].</text>
+    </revision>
+  </page>
+  <page>
+    <title>Trains</title>
+    <ns>0</ns>
+    <id>2</id>
+    <redirect title="Train" />
+    <revision>
+      <id>101</id>
+      <text bytes="30" xml:space="preserve">#REDIRECT [[Train]]</text>
+    </revision>
+  </page>
+  <page>
+    <title>Train</title>
+    <ns>0</ns>
+    <id>3</id>
+    <revision>
+      <id>102</id>
+      <text bytes="60" xml:space="preserve">A [[train]] runs on [[Railways|rails]].</text>
+    </revision>
+  </page>
+  <page>
+    <title>Railways</title>
+    <ns>0</ns>
+    <id>4</id>
+    <revision>
+      <id>103</id>
+      <text bytes="30" xml:space="preserve">#redirect [[rail_transport]]</text>
+    </revision>
+  </page>
+  <page>
+    <title>Mercury</title>
+    <ns>0</ns>
+    <id>5</id>
+    <revision>
+      <id>104</id>
+      <text bytes="90" xml:space="preserve">* [[Mercury (planet)]]
+* [[Mercury (element)]]
+* [[Mercury (mythology)]]
+* [[Freddie Mercury]]</text>
+    </revision>
+  </page>
+  <page>
+    <title>Talk:Train</title>
+    <ns>1</ns>
+    <id>6</id>
+    <revision>
+      <id>105</id>
+      <text bytes="20" xml:space="preserve">[[Train]] is great</text>
+    </revision>
+  </page>
+</mediawiki>
+"###;
+
+    const HISTORY_DUMP: &str = r###"<mediawiki xmlns="http://www.mediawiki.org/xml/export-0.10/" version="0.10" xml:lang="en">
+  <page>
+    <title>Train</title>
+    <ns>0</ns>
+    <id>1</id>
+    <revision>
+      <id>100</id>
+      <timestamp>2010-01-01T00:00:00Z</timestamp>
+      <text bytes="20" xml:space="preserve">A [[Locomotive]].</text>
+    </revision>
+    <revision>
+      <id>200</id>
+      <timestamp>2016-01-01T00:00:00Z</timestamp>
+      <text bytes="20" xml:space="preserve">A [[Monorail]].</text>
+    </revision>
+  </page>
+  <page>
+    <title>Trains</title>
+    <ns>0</ns>
+    <id>2</id>
+    <redirect title="Train" />
+    <revision>
+      <id>101</id>
+      <timestamp>2011-01-01T00:00:00Z</timestamp>
+      <text bytes="20" xml:space="preserve">See [[Train]].</text>
+    </revision>
+    <revision>
+      <id>201</id>
+      <timestamp>2015-06-01T00:00:00Z</timestamp>
+      <text bytes="20" xml:space="preserve">#REDIRECT [[Train]]</text>
+    </revision>
+  </page>
+  <page>
+    <title>Tram</title>
+    <ns>0</ns>
+    <id>3</id>
+    <revision>
+      <id>102</id>
+      <timestamp>2015-03-01T00:00:00Z</timestamp>
+      <text bytes="20" xml:space="preserve">A [[Train]].</text>
+    </revision>
+  </page>
+</mediawiki>
+"###;
+}